@@ -2,7 +2,7 @@ use std::{ops::BitXor, array};
 use crate::mode::BlockCipher;
 
 // GF(2)[x]/(x^8+x^4+x^3+x+1)上での掛け算
-const fn mul(l: u8, r: u8) -> u8 {
+pub(crate) const fn mul(l: u8, r: u8) -> u8 {
     let mut res = 0;
     let mut v = r;
     let mut i = 0;
@@ -16,7 +16,7 @@ const fn mul(l: u8, r: u8) -> u8 {
     res
 }
 
-const S_BOX: [u8; 256] = {
+pub(crate) const S_BOX: [u8; 256] = {
     let mut exp_table = [0u8; 256];
     let mut log_table = [0u8; 256];
     let mut i = 0;
@@ -38,7 +38,7 @@ const S_BOX: [u8; 256] = {
     sbox
 };
 
-const INV_S_BOX: [u8; 256] = {
+pub(crate) const INV_S_BOX: [u8; 256] = {
     let mut inv = [0; 256];
     let mut i = 0;
     while i < 256 {
@@ -48,7 +48,7 @@ const INV_S_BOX: [u8; 256] = {
     inv
 };
 
-const RC: [u8; 10] = {
+pub(crate) const RC: [u8; 10] = {
     let mut arr = [1u8; 10];
     let mut j = 1;
     while j < 10 {
@@ -86,7 +86,7 @@ pub enum AESkey {
 }
 
 impl AESkey {
-    fn key_expansion(&self) -> Box<[[u8; 16]]> {
+    pub(crate) fn key_expansion(&self) -> Box<[[u8; 16]]> {
         match self {
             AESkey::K128(k) => AESkey::key_expansion_inner(11, &k[..], 4),
             AESkey::K192(k) => AESkey::key_expansion_inner(13, &k[..], 6),
@@ -248,7 +248,8 @@ mod test {
     use crate::aes::AES;
     use crate::aes::AESkey;
     use crate::aes::AESBlock;
-    use crate::mode::BlockCipher;
+    use crate::mode::{BlockCipher, CsVariant};
+    use crate::padding::Padding;
 
     #[test]
     fn shift_rows() {
@@ -354,5 +355,68 @@ mod test {
             &mut aa
         );
         assert_eq!(ans, aa);
+
+        let ans = *b"\x92X+\x01\xcd\xf1I\tb\xf4M\xea\xfb\xae\xfa\x97,\xe488#\x87#w\xdd\x8f+`\xa0\xd7\x85\xce";
+        let a = AES::new(AESkey::K128(*b"!\xf4\x02\xf2[\x1a\x0f\xd7\"\xb81i\xe1\x05\t\xf8"));
+        let aa = a.encrypt_cbc_padded(
+            b"B\x85\xd1\x082\xa8\xed\xf4\xccyy\xe9\xa2W\xe1E\xdc\\\x93E",
+            *b"\x0e\x03^\xa2\xcf\x91'\xa1\xfa\x95\x9b,`\xbb\x9a\x7f",
+            Padding::Pkcs7
+        );
+        assert_eq!(ans[..], aa[..]);
+        assert_eq!(a.decrypt_cbc_padded(&aa, *b"\x0e\x03^\xa2\xcf\x91'\xa1\xfa\x95\x9b,`\xbb\x9a\x7f", Padding::Pkcs7).unwrap(), b"B\x85\xd1\x082\xa8\xed\xf4\xccyy\xe9\xa2W\xe1E\xdc\\\x93E");
+    }
+
+    #[test]
+    fn cbc_cs() {
+        // RFC 3962 Appendix B (CS3)
+        let a = AES::new(AESkey::K128(*b"chicken teriyaki"));
+        let p = b"I would like the General Gau's Chicken, please, and wonton soup.";
+        let cases: [(usize, &[u8]); 6] = [
+            (17, b"\xc655h\xf2\xbf\x8c\xb4\xd8\xa5\x806-\xa7\xff\x7f\x97"),
+            (31, b"\xfc\x00x>\x0e\xfd\xb2\xc1\xd4E\xd4\xc8\xef\xf7\xed\"\x97hrh\xd6\xec\xcc\xc0\xc0{%\xe2^\xcf\xe5"),
+            (32, b"91%#\xa7\x86b\xd5\xbe\x7f\xcb\xcc\x98\xeb\xf5\xa8\x97hrh\xd6\xec\xcc\xc0\xc0{%\xe2^\xcf\xe5\x84"),
+            (47, b"\x97hrh\xd6\xec\xcc\xc0\xc0{%\xe2^\xcf\xe5\x84\xb3\xff\xfd\x94\x0c\x16\xa1\x8c\x1bUI\xd2\xf88\x02\x9e91%#\xa7\x86b\xd5\xbe\x7f\xcb\xcc\x98\xeb\xf5"),
+            (48, b"\x97hrh\xd6\xec\xcc\xc0\xc0{%\xe2^\xcf\xe5\x84\x9d\xad\x8b\xbb\x96\xc4\xcd\xc0;\xc1\x03\xe1\xa1\x94\xbb\xd891%#\xa7\x86b\xd5\xbe\x7f\xcb\xcc\x98\xeb\xf5\xa8"),
+            (64, b"\x97hrh\xd6\xec\xcc\xc0\xc0{%\xe2^\xcf\xe5\x8491%#\xa7\x86b\xd5\xbe\x7f\xcb\xcc\x98\xeb\xf5\xa8H\x07\xef\xe86\xee\x89\xa5&s\r\xbc/{\xc8@\x9d\xad\x8b\xbb\x96\xc4\xcd\xc0;\xc1\x03\xe1\xa1\x94\xbb\xd8"),
+        ];
+        for (len, ans) in cases {
+            // SP 800-38A Addendumにはテストベクトルがないので、CS1とCS2はRFC 3962のCS3から組み立てる
+            // CS3は C1 .. Cn-2 || Cn || Cn-1*。CS1は最後の二つを入れ替えたもので、
+            // CS2は最後のブロックが半端ならCS3、そうでなければCS1と同じ
+            let d = len - (len - 1) / 16 * 16;
+            let (head, tail) = ans.split_at(len - 16 - d);
+            let cs1 = [head, &tail[16..], &tail[..16]].concat();
+            let cs2 = if d == 16 { cs1.clone() } else { ans.to_vec() };
+            for (v, expect) in [(CsVariant::Cs1, &cs1[..]), (CsVariant::Cs2, &cs2[..]), (CsVariant::Cs3, ans)] {
+                let mut aa = vec![0u8; len];
+                a.encrypt_cbc_cs(&p[..len], [0; 16], &mut aa, v);
+                assert_eq!(aa, expect);
+                let mut bb = vec![0u8; len];
+                a.decrypt_cbc_cs(&aa, [0; 16], &mut bb, v);
+                assert_eq!(bb, p[..len]);
+            }
+        }
+
+        // IVが0でない場合。NISTのベクトルではなく、最後のブロックを0で埋めたCBCの暗号文から
+        // Addendumの定義どおりに並べ替えた値
+        let a = AES::new(AESkey::K128(*b"!\xf4\x02\xf2[\x1a\x0f\xd7\"\xb81i\xe1\x05\t\xf8"));
+        let iv = *b"\x0e\x03^\xa2\xcf\x91'\xa1\xfa\x95\x9b,`\xbb\x9a\x7f";
+        let p = b"B\x85\xd1\x082\xa8\xed\xf4\xccyy\xe9\xa2W\xe1E\xdc\\\x93EI\xf6.\x0b\xf1\xdc`p\xe6|\xc4\xab[A6+\xc8+\x7f=V\xed\xc5\xa3\x06\xdb\"\x10W\x07\xd0\x1f\xf4\x81\x9e&\xfa\xef\x97$\xa2\xd4\x06\xc9";
+        let cases: [(usize, CsVariant, &[u8]); 5] = [
+            (23, CsVariant::Cs1, b"\x92X+\x01\xcd\xf1I=\xa3\xa3\x95\xb1\x1by\xba&\xd8\xf8!\xd8%'2"),
+            (23, CsVariant::Cs2, b"=\xa3\xa3\x95\xb1\x1by\xba&\xd8\xf8!\xd8%'2\x92X+\x01\xcd\xf1I"),
+            (32, CsVariant::Cs2, b"\x92X+\x01\xcd\xf1I\tb\xf4M\xea\xfb\xae\xfa\x97\xa5\xed\xbd \xdf\xb4vl\xf1T`\xfc>\xc5M\x98"),
+            (45, CsVariant::Cs1, b"\x92X+\x01\xcd\xf1I\tb\xf4M\xea\xfb\xae\xfa\x97\xa5\xed\xbd \xdf\xb4vl\xf1T`\xfc>\xf90yZ\xfc\xdf<o\x1a\x00\\`\x96\xd3=g"),
+            (45, CsVariant::Cs2, b"\x92X+\x01\xcd\xf1I\tb\xf4M\xea\xfb\xae\xfa\x97\xf90yZ\xfc\xdf<o\x1a\x00\\`\x96\xd3=g\xa5\xed\xbd \xdf\xb4vl\xf1T`\xfc>"),
+        ];
+        for (len, v, ans) in cases {
+            let mut aa = vec![0u8; len];
+            a.encrypt_cbc_cs(&p[..len], iv, &mut aa, v);
+            assert_eq!(aa, ans);
+            let mut bb = vec![0u8; len];
+            a.decrypt_cbc_cs(&aa, iv, &mut bb, v);
+            assert_eq!(bb, p[..len]);
+        }
     }
 }
@@ -1,4 +1,4 @@
-use std::{arch::asm, fmt::Debug, mem::MaybeUninit};
+use std::{arch::asm, fmt::Debug, mem::MaybeUninit, sync::OnceLock};
 
 use crate::{aes::AESkey, mode::BlockCipher};
 
@@ -15,6 +15,9 @@ impl Debug for CPUID {
     }
 }
 
+/// # Safety
+///
+/// CPUID命令が使えるCPU上で呼び出す必要があります。
 pub unsafe fn cpuid(in_eax: u32) -> CPUID {
     let eax;
     let ebx;
@@ -28,7 +31,7 @@ pub unsafe fn cpuid(in_eax: u32) -> CPUID {
         r = out(reg) ebx,
         inout("eax") in_eax => eax,
         out("edx") edx,
-        out("ecx") ecx,
+        inout("ecx") 0 => ecx,
     );
     CPUID { eax, ebx, edx, ecx }
 }
@@ -45,50 +48,212 @@ pub fn support_aesni() -> bool {
     }
 }
 
+// PCLMULQDQ (CPUID.1:ECX bit 1)。GHASHではpshufbも使うのでSSSE3も見る
+pub fn support_pclmulqdq() -> bool {
+    unsafe {
+        let c = cpuid(0);
+        if c.eax < 1 {
+            false
+        } else {
+            let d = cpuid(1);
+            d.ecx & (1 << 1) != 0 && d.ecx & (1 << 9) != 0
+        }
+    }
+}
+
+// XCR0の下位32bit
+unsafe fn xgetbv0() -> u32 {
+    let eax;
+    asm!(
+        "xgetbv",
+        in("ecx") 0,
+        out("eax") eax,
+        out("edx") _,
+    );
+    eax
+}
+
+// AVX2とVAES(256bit)が使えるか
+pub fn support_vaes() -> bool {
+    unsafe {
+        let c = cpuid(0);
+        if c.eax < 7 || !support_aesni() {
+            return false;
+        }
+        let d = cpuid(1);
+        // OSXSAVE, AVX
+        if d.ecx & (1 << 27) == 0 || d.ecx & (1 << 28) == 0 {
+            return false;
+        }
+        // XMM, YMMの状態をOSが保存するか
+        if xgetbv0() & 0b110 != 0b110 {
+            return false;
+        }
+        let e = cpuid(7);
+        e.ebx & (1 << 5) != 0 && e.ecx & (1 << 9) != 0
+    }
+}
+
+// support_vaesに加えてAVX-512Fが使えるか
+pub fn support_vaes_avx512() -> bool {
+    unsafe {
+        if !support_vaes() {
+            return false;
+        }
+        // opmask, ZMM0-15の上位, ZMM16-31の状態をOSが保存するか
+        if xgetbv0() & 0b1110_0000 != 0b1110_0000 {
+            return false;
+        }
+        cpuid(7).ebx & (1 << 16) != 0
+    }
+}
+
+// _encrypt_blocks/_decrypt_blocksで使うレジスタ幅
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VaesWidth {
+    Xmm,
+    Ymm,
+    Zmm,
+}
+
+impl VaesWidth {
+    // cpuidとxgetbvは最初の一回だけ呼ぶ
+    pub fn detect() -> Self {
+        static DETECTED: OnceLock<VaesWidth> = OnceLock::new();
+        *DETECTED.get_or_init(|| {
+            if support_vaes_avx512() {
+                VaesWidth::Zmm
+            } else if support_vaes() {
+                VaesWidth::Ymm
+            } else {
+                VaesWidth::Xmm
+            }
+        })
+    }
+
+    // Zmmが使えるならYmmも使える
+    pub fn is_available(self) -> bool {
+        match self {
+            VaesWidth::Xmm => true,
+            VaesWidth::Ymm => VaesWidth::detect() != VaesWidth::Xmm,
+            VaesWidth::Zmm => VaesWidth::detect() == VaesWidth::Zmm,
+        }
+    }
+}
+
+#[allow(non_camel_case_types)]
 pub struct AES_NI {
     round_key: Box<[u8]>,
     inv_round_key: Box<[u8]>,
+    width: VaesWidth,
 }
 
 impl AES_NI {
     pub fn new(key: AESkey) -> Self {
+        Self::with_width(key, VaesWidth::detect())
+    }
+
+    pub fn with_width(key: AESkey, width: VaesWidth) -> Self {
+        if !width.is_available() {
+            panic!("{:?}はこのCPUでは使えません", width);
+        }
         unsafe {
             match key {
                 AESkey::K128(v) => {
-                    let k = aes_keygen_128(&v as *const [u8] as *const u8);
+                    let k = aes_keygen_128(v.as_ptr());
                     let l = aes_inv_keygen_128(&k);
-                    Self { round_key: k, inv_round_key: l }
+                    Self { round_key: k, inv_round_key: l, width }
+                }
+                AESkey::K192(v) => {
+                    let k = aes_keygen_192(v.as_ptr());
+                    let l = aes_inv_keygen_192(&k);
+                    Self { round_key: k, inv_round_key: l, width }
+                }
+                AESkey::K256(v) => {
+                    let k = aes_keygen_256(v.as_ptr());
+                    let l = aes_inv_keygen_256(&k);
+                    Self { round_key: k, inv_round_key: l, width }
                 }
-                _ => todo!()
             }
         }
     }
+
+    pub fn width(&self) -> VaesWidth {
+        self.width
+    }
+}
+
+// nブロックずつfで処理し、残りを返す
+fn each_chunk(blocks: &mut [[u8; 16]], n: usize, f: impl Fn(*mut u8)) -> &mut [[u8; 16]] {
+    let mut chunks = blocks.chunks_exact_mut(n);
+    for c in &mut chunks {
+        f(c.as_mut_ptr().cast());
+    }
+    chunks.into_remainder()
 }
 
 impl BlockCipher<16> for AES_NI {
     fn _encrypt(&self, in_bytes: [u8; 16]) -> [u8; 16] {
         let mut v = in_bytes;
-        let ptr = v.as_mut_ptr() as *mut u8;
-        let kptr = self.round_key.as_ptr() as *const u8;
-        if self.round_key.len() == 0xb0 {
-            unsafe { aes_enc_128(ptr, kptr) }
-        } else {
-            todo!()
+        let ptr = v.as_mut_ptr();
+        let kptr = self.round_key.as_ptr();
+        match self.round_key.len() {
+            0xb0 => unsafe { aes_enc_128(ptr, kptr) },
+            0xd0 => unsafe { aes_enc_192(ptr, kptr) },
+            0xf0 => unsafe { aes_enc_256(ptr, kptr) },
+            _ => unreachable!(),
         }
         v
     }
 
     fn _decrypt(&self, in_bytes: [u8; 16]) -> [u8; 16] {
         let mut v = in_bytes;
-        let ptr = v.as_mut_ptr() as *mut u8;
-        let kptr = self.inv_round_key.as_ptr() as *const u8;
-        if self.round_key.len() == 0xb0 {
-            unsafe { aes_dec_128(ptr, kptr) }
-        } else {
-            todo!()
+        let ptr = v.as_mut_ptr();
+        let kptr = self.inv_round_key.as_ptr();
+        match self.inv_round_key.len() {
+            0xb0 => unsafe { aes_dec_128(ptr, kptr) },
+            0xd0 => unsafe { aes_dec_192(ptr, kptr) },
+            0xf0 => unsafe { aes_dec_256(ptr, kptr) },
+            _ => unreachable!(),
         }
         v
     }
+
+    fn _aesni_round_key(&self) -> Option<&[u8]> {
+        Some(&self.round_key)
+    }
+
+    fn _encrypt_blocks(&self, blocks: &mut [[u8; 16]]) {
+        let k = self.round_key.as_ptr();
+        let l = self.round_key.len();
+        let mut rest = blocks;
+        if self.width == VaesWidth::Zmm {
+            rest = each_chunk(rest, 32, |d| unsafe { aes_enc_zmm_x8(d, k, l) });
+        }
+        if self.width != VaesWidth::Xmm {
+            rest = each_chunk(rest, 16, |d| unsafe { aes_enc_ymm_x8(d, k, l) });
+        }
+        rest = each_chunk(rest, 8, |d| unsafe { aes_enc_x8(d, k, l) });
+        for b in rest {
+            *b = self._encrypt(*b);
+        }
+    }
+
+    fn _decrypt_blocks(&self, blocks: &mut [[u8; 16]]) {
+        let k = self.inv_round_key.as_ptr();
+        let l = self.inv_round_key.len();
+        let mut rest = blocks;
+        if self.width == VaesWidth::Zmm {
+            rest = each_chunk(rest, 32, |d| unsafe { aes_dec_zmm_x8(d, k, l) });
+        }
+        if self.width != VaesWidth::Xmm {
+            rest = each_chunk(rest, 16, |d| unsafe { aes_dec_ymm_x8(d, k, l) });
+        }
+        rest = each_chunk(rest, 8, |d| unsafe { aes_dec_x8(d, k, l) });
+        for b in rest {
+            *b = self._decrypt(*b);
+        }
+    }
 }
 
 unsafe fn aes_keygen_128(key: *const u8) -> Box<[u8]> {
@@ -148,10 +313,10 @@ unsafe fn aes_keygen_128(key: *const u8) -> Box<[u8]> {
         out("xmm2") _,
         out("xmm3") _,
     );
-    core::mem::transmute::<_, Vec<u8>>(v).into_boxed_slice()
+    core::mem::transmute::<Vec<MaybeUninit<u8>>, Vec<u8>>(v).into_boxed_slice()
 }
 
-unsafe fn aes_inv_keygen_128(data: &Box<[u8]>) -> Box<[u8]> {
+unsafe fn aes_inv_keygen_128(data: &[u8]) -> Box<[u8]> {
     let mut v: Vec<MaybeUninit<u8>> = vec![MaybeUninit::uninit(); 0xb0];
     asm!(
         "movdqu xmm0, [{k}]",
@@ -182,10 +347,239 @@ unsafe fn aes_inv_keygen_128(data: &Box<[u8]>) -> Box<[u8]> {
         d = in(reg) v.as_mut_ptr(),
         out("xmm0") _,
     );
-    core::mem::transmute::<_, Vec<u8>>(v).into_boxed_slice()
+    core::mem::transmute::<Vec<MaybeUninit<u8>>, Vec<u8>>(v).into_boxed_slice()
+}
+
+unsafe fn aes_keygen_192(key: *const u8) -> Box<[u8]> {
+    let mut v: Vec<MaybeUninit<u8>> = vec![MaybeUninit::uninit(); 0xd0];
+    asm!(
+        "movdqu xmm1, [{k}]",
+        "movq xmm3, [{k}+0x10]",
+        "movdqu [{v}], xmm1",
+        "movq [{v}+0x10], xmm3",
+
+        "aeskeygenassist xmm2, xmm3, 0x1",
+        "call 2f",
+        "movdqu [{v}+0x18], xmm1",
+        "movq [{v}+0x28], xmm3",
+        "aeskeygenassist xmm2, xmm3, 0x2",
+        "call 2f",
+        "movdqu [{v}+0x30], xmm1",
+        "movq [{v}+0x40], xmm3",
+        "aeskeygenassist xmm2, xmm3, 0x4",
+        "call 2f",
+        "movdqu [{v}+0x48], xmm1",
+        "movq [{v}+0x58], xmm3",
+        "aeskeygenassist xmm2, xmm3, 0x8",
+        "call 2f",
+        "movdqu [{v}+0x60], xmm1",
+        "movq [{v}+0x70], xmm3",
+        "aeskeygenassist xmm2, xmm3, 0x10",
+        "call 2f",
+        "movdqu [{v}+0x78], xmm1",
+        "movq [{v}+0x88], xmm3",
+        "aeskeygenassist xmm2, xmm3, 0x20",
+        "call 2f",
+        "movdqu [{v}+0x90], xmm1",
+        "movq [{v}+0xa0], xmm3",
+        "aeskeygenassist xmm2, xmm3, 0x40",
+        "call 2f",
+        "movdqu [{v}+0xa8], xmm1",
+        "movq [{v}+0xb8], xmm3",
+        "aeskeygenassist xmm2, xmm3, 0x80",
+        "call 2f",
+        "movdqu [{v}+0xc0], xmm1",
+
+        "jmp 3f",
+
+        "2:",
+        "pshufd xmm2, xmm2, 0x55",
+        "vpslldq xmm4, xmm1, 0x4",
+        "pxor xmm1, xmm4",
+        "vpslldq xmm4, xmm1, 0x4",
+        "pxor xmm1, xmm4",
+        "vpslldq xmm4, xmm1, 0x4",
+        "pxor xmm1, xmm4",
+        "pxor xmm1, xmm2",
+        "pshufd xmm2, xmm1, 0xff",
+        "vpslldq xmm4, xmm3, 0x4",
+        "pxor xmm3, xmm4",
+        "pxor xmm3, xmm2",
+        "ret",
+
+        "3:",
+        k = in(reg) key,
+        v = in(reg) v.as_mut_ptr(),
+        out("xmm1") _,
+        out("xmm2") _,
+        out("xmm3") _,
+        out("xmm4") _,
+    );
+    core::mem::transmute::<Vec<MaybeUninit<u8>>, Vec<u8>>(v).into_boxed_slice()
+}
+
+unsafe fn aes_inv_keygen_192(data: &[u8]) -> Box<[u8]> {
+    let mut v: Vec<MaybeUninit<u8>> = vec![MaybeUninit::uninit(); 0xd0];
+    asm!(
+        "movdqu xmm0, [{k}]",
+        "movdqu [{d}], xmm0",
+
+        "aesimc xmm0, [{k}+0x10]",
+        "movdqu [{d}+0x10], xmm0",
+        "aesimc xmm0, [{k}+0x20]",
+        "movdqu [{d}+0x20], xmm0",
+        "aesimc xmm0, [{k}+0x30]",
+        "movdqu [{d}+0x30], xmm0",
+        "aesimc xmm0, [{k}+0x40]",
+        "movdqu [{d}+0x40], xmm0",
+        "aesimc xmm0, [{k}+0x50]",
+        "movdqu [{d}+0x50], xmm0",
+        "aesimc xmm0, [{k}+0x60]",
+        "movdqu [{d}+0x60], xmm0",
+        "aesimc xmm0, [{k}+0x70]",
+        "movdqu [{d}+0x70], xmm0",
+        "aesimc xmm0, [{k}+0x80]",
+        "movdqu [{d}+0x80], xmm0",
+        "aesimc xmm0, [{k}+0x90]",
+        "movdqu [{d}+0x90], xmm0",
+        "aesimc xmm0, [{k}+0xa0]",
+        "movdqu [{d}+0xa0], xmm0",
+        "aesimc xmm0, [{k}+0xb0]",
+        "movdqu [{d}+0xb0], xmm0",
+
+        "movdqu xmm0, [{k}+0xc0]",
+        "movdqu [{d}+0xc0], xmm0",
+        k = in(reg) data.as_ptr(),
+        d = in(reg) v.as_mut_ptr(),
+        out("xmm0") _,
+    );
+    core::mem::transmute::<Vec<MaybeUninit<u8>>, Vec<u8>>(v).into_boxed_slice()
+}
+
+unsafe fn aes_keygen_256(key: *const u8) -> Box<[u8]> {
+    let mut v: Vec<MaybeUninit<u8>> = vec![MaybeUninit::uninit(); 0xf0];
+    asm!(
+        "movdqu xmm1, [{k}]",
+        "movdqu xmm4, [{k}+0x10]",
+        "movdqu [{v}], xmm1",
+        "movdqu [{v}+0x10], xmm4",
+
+        "aeskeygenassist xmm2, xmm4, 0x1",
+        "call 2f",
+        "movdqu [{v}+0x20], xmm1",
+        "call 4f",
+        "movdqu [{v}+0x30], xmm4",
+        "aeskeygenassist xmm2, xmm4, 0x2",
+        "call 2f",
+        "movdqu [{v}+0x40], xmm1",
+        "call 4f",
+        "movdqu [{v}+0x50], xmm4",
+        "aeskeygenassist xmm2, xmm4, 0x4",
+        "call 2f",
+        "movdqu [{v}+0x60], xmm1",
+        "call 4f",
+        "movdqu [{v}+0x70], xmm4",
+        "aeskeygenassist xmm2, xmm4, 0x8",
+        "call 2f",
+        "movdqu [{v}+0x80], xmm1",
+        "call 4f",
+        "movdqu [{v}+0x90], xmm4",
+        "aeskeygenassist xmm2, xmm4, 0x10",
+        "call 2f",
+        "movdqu [{v}+0xa0], xmm1",
+        "call 4f",
+        "movdqu [{v}+0xb0], xmm4",
+        "aeskeygenassist xmm2, xmm4, 0x20",
+        "call 2f",
+        "movdqu [{v}+0xc0], xmm1",
+        "call 4f",
+        "movdqu [{v}+0xd0], xmm4",
+        "aeskeygenassist xmm2, xmm4, 0x40",
+        "call 2f",
+        "movdqu [{v}+0xe0], xmm1",
+
+        "jmp 3f",
+
+        "2:",
+        "pshufd xmm2, xmm2, 0xff",
+        "vpslldq xmm3, xmm1, 0x4",
+        "pxor xmm1, xmm3",
+        "vpslldq xmm3, xmm1, 0x4",
+        "pxor xmm1, xmm3",
+        "vpslldq xmm3, xmm1, 0x4",
+        "pxor xmm1, xmm3",
+        "pxor xmm1, xmm2",
+        "ret",
+
+        "4:",
+        "aeskeygenassist xmm2, xmm1, 0x0",
+        "pshufd xmm2, xmm2, 0xaa",
+        "vpslldq xmm3, xmm4, 0x4",
+        "pxor xmm4, xmm3",
+        "vpslldq xmm3, xmm4, 0x4",
+        "pxor xmm4, xmm3",
+        "vpslldq xmm3, xmm4, 0x4",
+        "pxor xmm4, xmm3",
+        "pxor xmm4, xmm2",
+        "ret",
+
+        "3:",
+        k = in(reg) key,
+        v = in(reg) v.as_mut_ptr(),
+        out("xmm1") _,
+        out("xmm2") _,
+        out("xmm3") _,
+        out("xmm4") _,
+    );
+    core::mem::transmute::<Vec<MaybeUninit<u8>>, Vec<u8>>(v).into_boxed_slice()
+}
+
+unsafe fn aes_inv_keygen_256(data: &[u8]) -> Box<[u8]> {
+    let mut v: Vec<MaybeUninit<u8>> = vec![MaybeUninit::uninit(); 0xf0];
+    asm!(
+        "movdqu xmm0, [{k}]",
+        "movdqu [{d}], xmm0",
+
+        "aesimc xmm0, [{k}+0x10]",
+        "movdqu [{d}+0x10], xmm0",
+        "aesimc xmm0, [{k}+0x20]",
+        "movdqu [{d}+0x20], xmm0",
+        "aesimc xmm0, [{k}+0x30]",
+        "movdqu [{d}+0x30], xmm0",
+        "aesimc xmm0, [{k}+0x40]",
+        "movdqu [{d}+0x40], xmm0",
+        "aesimc xmm0, [{k}+0x50]",
+        "movdqu [{d}+0x50], xmm0",
+        "aesimc xmm0, [{k}+0x60]",
+        "movdqu [{d}+0x60], xmm0",
+        "aesimc xmm0, [{k}+0x70]",
+        "movdqu [{d}+0x70], xmm0",
+        "aesimc xmm0, [{k}+0x80]",
+        "movdqu [{d}+0x80], xmm0",
+        "aesimc xmm0, [{k}+0x90]",
+        "movdqu [{d}+0x90], xmm0",
+        "aesimc xmm0, [{k}+0xa0]",
+        "movdqu [{d}+0xa0], xmm0",
+        "aesimc xmm0, [{k}+0xb0]",
+        "movdqu [{d}+0xb0], xmm0",
+        "aesimc xmm0, [{k}+0xc0]",
+        "movdqu [{d}+0xc0], xmm0",
+        "aesimc xmm0, [{k}+0xd0]",
+        "movdqu [{d}+0xd0], xmm0",
+
+        "movdqu xmm0, [{k}+0xe0]",
+        "movdqu [{d}+0xe0], xmm0",
+        k = in(reg) data.as_ptr(),
+        d = in(reg) v.as_mut_ptr(),
+        out("xmm0") _,
+    );
+    core::mem::transmute::<Vec<MaybeUninit<u8>>, Vec<u8>>(v).into_boxed_slice()
 }
 
 #[cfg(feature = "use_nightly")]
+/// # Safety
+///
+/// `data`と`key`はそれぞれ16バイトの読み書き可能な領域を指している必要があります。
 pub unsafe fn aesenc(data: *mut u8, key: *const u8) {
     use std::simd::i64x2;
 
@@ -237,74 +631,527 @@ unsafe fn aes_dec_128(data: *mut u8, inv_key: *const u8) {
         "movdqu [{d}], xmm0",
         d = in(reg) data,
         k = in(reg) inv_key,
+        out("xmm0") _,
     )
 }
 
-unsafe fn aes_keygen_192(key: *const u8) -> Box<[u8]> {
-    todo!();
-    let mut v: Vec<MaybeUninit<u8>> = vec![MaybeUninit::uninit(); 0xd0];
+unsafe fn aes_enc_192(data: *mut u8, key: *const u8) {
     asm!(
-        "movdqu xmm1, [{k}]",
-        "movdqu [{v}], xmm1",
+        "movdqu xmm0, [{d}]",
+        "pxor xmm0, [{k}]",
+        "aesenc xmm0, [{k}+16]",
+        "aesenc xmm0, [{k}+32]",
+        "aesenc xmm0, [{k}+48]",
+        "aesenc xmm0, [{k}+64]",
+        "aesenc xmm0, [{k}+80]",
+        "aesenc xmm0, [{k}+96]",
+        "aesenc xmm0, [{k}+112]",
+        "aesenc xmm0, [{k}+128]",
+        "aesenc xmm0, [{k}+144]",
+        "aesenc xmm0, [{k}+160]",
+        "aesenc xmm0, [{k}+176]",
+        "aesenclast xmm0, [{k}+192]",
+        "movdqu [{d}], xmm0",
+        d = in(reg) data,
+        k = in(reg) key,
+        out("xmm0") _,
+    )
+}
 
-        "aeskeygenassist xmm2, xmm1, 0x1",
-        "call 2f",
-        "movdqu [{v}+0x10], xmm1",
-        "aeskeygenassist xmm2, xmm1, 0x2",
-        "call 2f",
-        "movdqu [{v}+0x20], xmm1",
-        "aeskeygenassist xmm2, xmm1, 0x4",
-        "call 2f",
-        "movdqu [{v}+0x30], xmm1",
-        "aeskeygenassist xmm2, xmm1, 0x8",
-        "call 2f",
-        "movdqu [{v}+0x40], xmm1",
-        "aeskeygenassist xmm2, xmm1, 0x10",
-        "call 2f",
-        "movdqu [{v}+0x50], xmm1",
-        "aeskeygenassist xmm2, xmm1, 0x20",
-        "call 2f",
-        "movdqu [{v}+0x60], xmm1",
-        "aeskeygenassist xmm2, xmm1, 0x40",
-        "call 2f",
-        "movdqu [{v}+0x70], xmm1",
-        "aeskeygenassist xmm2, xmm1, 0x80",
-        "call 2f",
-        "movdqu [{v}+0x80], xmm1",
-        "aeskeygenassist xmm2, xmm1, 0x1b",
-        "call 2f",
-        "movdqu [{v}+0x90], xmm1",
-        "aeskeygenassist xmm2, xmm1, 0x36",
-        "call 2f",
-        "movdqu [{v}+0xa0], xmm1",
+unsafe fn aes_dec_192(data: *mut u8, inv_key: *const u8) {
+    asm!(
+        "movdqu xmm0, [{d}]",
+        "pxor xmm0, [{k}+0xc0]",
+        "aesdec xmm0, [{k}+0xb0]",
+        "aesdec xmm0, [{k}+0xa0]",
+        "aesdec xmm0, [{k}+0x90]",
+        "aesdec xmm0, [{k}+0x80]",
+        "aesdec xmm0, [{k}+0x70]",
+        "aesdec xmm0, [{k}+0x60]",
+        "aesdec xmm0, [{k}+0x50]",
+        "aesdec xmm0, [{k}+0x40]",
+        "aesdec xmm0, [{k}+0x30]",
+        "aesdec xmm0, [{k}+0x20]",
+        "aesdec xmm0, [{k}+0x10]",
+        "aesdeclast xmm0, [{k}]",
+        "movdqu [{d}], xmm0",
+        d = in(reg) data,
+        k = in(reg) inv_key,
+        out("xmm0") _,
+    )
+}
 
-        "jmp 3f",
+unsafe fn aes_enc_256(data: *mut u8, key: *const u8) {
+    asm!(
+        "movdqu xmm0, [{d}]",
+        "pxor xmm0, [{k}]",
+        "aesenc xmm0, [{k}+16]",
+        "aesenc xmm0, [{k}+32]",
+        "aesenc xmm0, [{k}+48]",
+        "aesenc xmm0, [{k}+64]",
+        "aesenc xmm0, [{k}+80]",
+        "aesenc xmm0, [{k}+96]",
+        "aesenc xmm0, [{k}+112]",
+        "aesenc xmm0, [{k}+128]",
+        "aesenc xmm0, [{k}+144]",
+        "aesenc xmm0, [{k}+160]",
+        "aesenc xmm0, [{k}+176]",
+        "aesenc xmm0, [{k}+192]",
+        "aesenc xmm0, [{k}+208]",
+        "aesenclast xmm0, [{k}+224]",
+        "movdqu [{d}], xmm0",
+        d = in(reg) data,
+        k = in(reg) key,
+        out("xmm0") _,
+    )
+}
+
+unsafe fn aes_dec_256(data: *mut u8, inv_key: *const u8) {
+    asm!(
+        "movdqu xmm0, [{d}]",
+        "pxor xmm0, [{k}+0xe0]",
+        "aesdec xmm0, [{k}+0xd0]",
+        "aesdec xmm0, [{k}+0xc0]",
+        "aesdec xmm0, [{k}+0xb0]",
+        "aesdec xmm0, [{k}+0xa0]",
+        "aesdec xmm0, [{k}+0x90]",
+        "aesdec xmm0, [{k}+0x80]",
+        "aesdec xmm0, [{k}+0x70]",
+        "aesdec xmm0, [{k}+0x60]",
+        "aesdec xmm0, [{k}+0x50]",
+        "aesdec xmm0, [{k}+0x40]",
+        "aesdec xmm0, [{k}+0x30]",
+        "aesdec xmm0, [{k}+0x20]",
+        "aesdec xmm0, [{k}+0x10]",
+        "aesdeclast xmm0, [{k}]",
+        "movdqu [{d}], xmm0",
+        d = in(reg) data,
+        k = in(reg) inv_key,
+        out("xmm0") _,
+    )
+}
+
+unsafe fn aes_enc_x8(data: *mut u8, key: *const u8, key_len: usize) {
+    asm!(
+        "movdqu xmm8, [{k}]",
+        "movdqu xmm0, [{d}]",
+        "movdqu xmm1, [{d}+0x10]",
+        "movdqu xmm2, [{d}+0x20]",
+        "movdqu xmm3, [{d}+0x30]",
+        "movdqu xmm4, [{d}+0x40]",
+        "movdqu xmm5, [{d}+0x50]",
+        "movdqu xmm6, [{d}+0x60]",
+        "movdqu xmm7, [{d}+0x70]",
+        "pxor xmm0, xmm8",
+        "pxor xmm1, xmm8",
+        "pxor xmm2, xmm8",
+        "pxor xmm3, xmm8",
+        "pxor xmm4, xmm8",
+        "pxor xmm5, xmm8",
+        "pxor xmm6, xmm8",
+        "pxor xmm7, xmm8",
+        "mov {i}, 0x10",
 
         "2:",
-        "pshufd xmm2, xmm2, 0xff",
-        "vpslldq xmm3, xmm1, 0x4",
-        "pxor xmm1, xmm3",
-        "vpslldq xmm3, xmm1, 0x4",
-        "pxor xmm1, xmm3",
-        "vpslldq xmm3, xmm1, 0x4",
-        "pxor xmm1, xmm3",
-        "pxor xmm1, xmm2",
-        "ret",
+        "movdqu xmm8, [{k}+{i}]",
+        "aesenc xmm0, xmm8",
+        "aesenc xmm1, xmm8",
+        "aesenc xmm2, xmm8",
+        "aesenc xmm3, xmm8",
+        "aesenc xmm4, xmm8",
+        "aesenc xmm5, xmm8",
+        "aesenc xmm6, xmm8",
+        "aesenc xmm7, xmm8",
+        "add {i}, 0x10",
+        "cmp {i}, {l}",
+        "jne 2b",
 
-        "3:",
+        "movdqu xmm8, [{k}+{i}]",
+        "aesenclast xmm0, xmm8",
+        "aesenclast xmm1, xmm8",
+        "aesenclast xmm2, xmm8",
+        "aesenclast xmm3, xmm8",
+        "aesenclast xmm4, xmm8",
+        "aesenclast xmm5, xmm8",
+        "aesenclast xmm6, xmm8",
+        "aesenclast xmm7, xmm8",
+        "movdqu [{d}], xmm0",
+        "movdqu [{d}+0x10], xmm1",
+        "movdqu [{d}+0x20], xmm2",
+        "movdqu [{d}+0x30], xmm3",
+        "movdqu [{d}+0x40], xmm4",
+        "movdqu [{d}+0x50], xmm5",
+        "movdqu [{d}+0x60], xmm6",
+        "movdqu [{d}+0x70], xmm7",
+        d = in(reg) data,
         k = in(reg) key,
-        v = in(reg) v.as_mut_ptr(),
+        l = in(reg) key_len - 0x10,
+        i = out(reg) _,
+        out("xmm0") _,
         out("xmm1") _,
         out("xmm2") _,
         out("xmm3") _,
-    );
-    core::mem::transmute::<_, Vec<u8>>(v).into_boxed_slice()
+        out("xmm4") _,
+        out("xmm5") _,
+        out("xmm6") _,
+        out("xmm7") _,
+        out("xmm8") _,
+    )
 }
 
+unsafe fn aes_dec_x8(data: *mut u8, inv_key: *const u8, key_len: usize) {
+    asm!(
+        "mov {i}, {l}",
+        "movdqu xmm8, [{k}+{i}]",
+        "movdqu xmm0, [{d}]",
+        "movdqu xmm1, [{d}+0x10]",
+        "movdqu xmm2, [{d}+0x20]",
+        "movdqu xmm3, [{d}+0x30]",
+        "movdqu xmm4, [{d}+0x40]",
+        "movdqu xmm5, [{d}+0x50]",
+        "movdqu xmm6, [{d}+0x60]",
+        "movdqu xmm7, [{d}+0x70]",
+        "pxor xmm0, xmm8",
+        "pxor xmm1, xmm8",
+        "pxor xmm2, xmm8",
+        "pxor xmm3, xmm8",
+        "pxor xmm4, xmm8",
+        "pxor xmm5, xmm8",
+        "pxor xmm6, xmm8",
+        "pxor xmm7, xmm8",
+
+        "2:",
+        "sub {i}, 0x10",
+        "movdqu xmm8, [{k}+{i}]",
+        "aesdec xmm0, xmm8",
+        "aesdec xmm1, xmm8",
+        "aesdec xmm2, xmm8",
+        "aesdec xmm3, xmm8",
+        "aesdec xmm4, xmm8",
+        "aesdec xmm5, xmm8",
+        "aesdec xmm6, xmm8",
+        "aesdec xmm7, xmm8",
+        "cmp {i}, 0x10",
+        "jne 2b",
+
+        "movdqu xmm8, [{k}]",
+        "aesdeclast xmm0, xmm8",
+        "aesdeclast xmm1, xmm8",
+        "aesdeclast xmm2, xmm8",
+        "aesdeclast xmm3, xmm8",
+        "aesdeclast xmm4, xmm8",
+        "aesdeclast xmm5, xmm8",
+        "aesdeclast xmm6, xmm8",
+        "aesdeclast xmm7, xmm8",
+        "movdqu [{d}], xmm0",
+        "movdqu [{d}+0x10], xmm1",
+        "movdqu [{d}+0x20], xmm2",
+        "movdqu [{d}+0x30], xmm3",
+        "movdqu [{d}+0x40], xmm4",
+        "movdqu [{d}+0x50], xmm5",
+        "movdqu [{d}+0x60], xmm6",
+        "movdqu [{d}+0x70], xmm7",
+        d = in(reg) data,
+        k = in(reg) inv_key,
+        l = in(reg) key_len - 0x10,
+        i = out(reg) _,
+        out("xmm0") _,
+        out("xmm1") _,
+        out("xmm2") _,
+        out("xmm3") _,
+        out("xmm4") _,
+        out("xmm5") _,
+        out("xmm6") _,
+        out("xmm7") _,
+        out("xmm8") _,
+    )
+}
+
+#[target_feature(enable = "avx2,vaes")]
+unsafe fn aes_enc_ymm_x8(data: *mut u8, key: *const u8, key_len: usize) {
+    asm!(
+        "vbroadcasti128 ymm8, [{k}]",
+        "vmovdqu ymm0, [{d}]",
+        "vmovdqu ymm1, [{d}+0x20]",
+        "vmovdqu ymm2, [{d}+0x40]",
+        "vmovdqu ymm3, [{d}+0x60]",
+        "vmovdqu ymm4, [{d}+0x80]",
+        "vmovdqu ymm5, [{d}+0xa0]",
+        "vmovdqu ymm6, [{d}+0xc0]",
+        "vmovdqu ymm7, [{d}+0xe0]",
+        "vpxor ymm0, ymm0, ymm8",
+        "vpxor ymm1, ymm1, ymm8",
+        "vpxor ymm2, ymm2, ymm8",
+        "vpxor ymm3, ymm3, ymm8",
+        "vpxor ymm4, ymm4, ymm8",
+        "vpxor ymm5, ymm5, ymm8",
+        "vpxor ymm6, ymm6, ymm8",
+        "vpxor ymm7, ymm7, ymm8",
+        "mov {i}, 0x10",
+
+        "2:",
+        "vbroadcasti128 ymm8, [{k}+{i}]",
+        "vaesenc ymm0, ymm0, ymm8",
+        "vaesenc ymm1, ymm1, ymm8",
+        "vaesenc ymm2, ymm2, ymm8",
+        "vaesenc ymm3, ymm3, ymm8",
+        "vaesenc ymm4, ymm4, ymm8",
+        "vaesenc ymm5, ymm5, ymm8",
+        "vaesenc ymm6, ymm6, ymm8",
+        "vaesenc ymm7, ymm7, ymm8",
+        "add {i}, 0x10",
+        "cmp {i}, {l}",
+        "jne 2b",
+
+        "vbroadcasti128 ymm8, [{k}+{i}]",
+        "vaesenclast ymm0, ymm0, ymm8",
+        "vaesenclast ymm1, ymm1, ymm8",
+        "vaesenclast ymm2, ymm2, ymm8",
+        "vaesenclast ymm3, ymm3, ymm8",
+        "vaesenclast ymm4, ymm4, ymm8",
+        "vaesenclast ymm5, ymm5, ymm8",
+        "vaesenclast ymm6, ymm6, ymm8",
+        "vaesenclast ymm7, ymm7, ymm8",
+        "vmovdqu [{d}], ymm0",
+        "vmovdqu [{d}+0x20], ymm1",
+        "vmovdqu [{d}+0x40], ymm2",
+        "vmovdqu [{d}+0x60], ymm3",
+        "vmovdqu [{d}+0x80], ymm4",
+        "vmovdqu [{d}+0xa0], ymm5",
+        "vmovdqu [{d}+0xc0], ymm6",
+        "vmovdqu [{d}+0xe0], ymm7",
+        "vzeroupper",
+        d = in(reg) data,
+        k = in(reg) key,
+        l = in(reg) key_len - 0x10,
+        i = out(reg) _,
+        out("ymm0") _,
+        out("ymm1") _,
+        out("ymm2") _,
+        out("ymm3") _,
+        out("ymm4") _,
+        out("ymm5") _,
+        out("ymm6") _,
+        out("ymm7") _,
+        out("ymm8") _,
+    )
+}
+
+#[target_feature(enable = "avx2,vaes")]
+unsafe fn aes_dec_ymm_x8(data: *mut u8, inv_key: *const u8, key_len: usize) {
+    asm!(
+        "mov {i}, {l}",
+        "vbroadcasti128 ymm8, [{k}+{i}]",
+        "vmovdqu ymm0, [{d}]",
+        "vmovdqu ymm1, [{d}+0x20]",
+        "vmovdqu ymm2, [{d}+0x40]",
+        "vmovdqu ymm3, [{d}+0x60]",
+        "vmovdqu ymm4, [{d}+0x80]",
+        "vmovdqu ymm5, [{d}+0xa0]",
+        "vmovdqu ymm6, [{d}+0xc0]",
+        "vmovdqu ymm7, [{d}+0xe0]",
+        "vpxor ymm0, ymm0, ymm8",
+        "vpxor ymm1, ymm1, ymm8",
+        "vpxor ymm2, ymm2, ymm8",
+        "vpxor ymm3, ymm3, ymm8",
+        "vpxor ymm4, ymm4, ymm8",
+        "vpxor ymm5, ymm5, ymm8",
+        "vpxor ymm6, ymm6, ymm8",
+        "vpxor ymm7, ymm7, ymm8",
+
+        "2:",
+        "sub {i}, 0x10",
+        "vbroadcasti128 ymm8, [{k}+{i}]",
+        "vaesdec ymm0, ymm0, ymm8",
+        "vaesdec ymm1, ymm1, ymm8",
+        "vaesdec ymm2, ymm2, ymm8",
+        "vaesdec ymm3, ymm3, ymm8",
+        "vaesdec ymm4, ymm4, ymm8",
+        "vaesdec ymm5, ymm5, ymm8",
+        "vaesdec ymm6, ymm6, ymm8",
+        "vaesdec ymm7, ymm7, ymm8",
+        "cmp {i}, 0x10",
+        "jne 2b",
+
+        "vbroadcasti128 ymm8, [{k}]",
+        "vaesdeclast ymm0, ymm0, ymm8",
+        "vaesdeclast ymm1, ymm1, ymm8",
+        "vaesdeclast ymm2, ymm2, ymm8",
+        "vaesdeclast ymm3, ymm3, ymm8",
+        "vaesdeclast ymm4, ymm4, ymm8",
+        "vaesdeclast ymm5, ymm5, ymm8",
+        "vaesdeclast ymm6, ymm6, ymm8",
+        "vaesdeclast ymm7, ymm7, ymm8",
+        "vmovdqu [{d}], ymm0",
+        "vmovdqu [{d}+0x20], ymm1",
+        "vmovdqu [{d}+0x40], ymm2",
+        "vmovdqu [{d}+0x60], ymm3",
+        "vmovdqu [{d}+0x80], ymm4",
+        "vmovdqu [{d}+0xa0], ymm5",
+        "vmovdqu [{d}+0xc0], ymm6",
+        "vmovdqu [{d}+0xe0], ymm7",
+        "vzeroupper",
+        d = in(reg) data,
+        k = in(reg) inv_key,
+        l = in(reg) key_len - 0x10,
+        i = out(reg) _,
+        out("ymm0") _,
+        out("ymm1") _,
+        out("ymm2") _,
+        out("ymm3") _,
+        out("ymm4") _,
+        out("ymm5") _,
+        out("ymm6") _,
+        out("ymm7") _,
+        out("ymm8") _,
+    )
+}
+
+#[target_feature(enable = "avx512f,vaes")]
+unsafe fn aes_enc_zmm_x8(data: *mut u8, key: *const u8, key_len: usize) {
+    asm!(
+        "vbroadcasti32x4 zmm8, [{k}]",
+        "vmovdqu64 zmm0, [{d}]",
+        "vmovdqu64 zmm1, [{d}+0x40]",
+        "vmovdqu64 zmm2, [{d}+0x80]",
+        "vmovdqu64 zmm3, [{d}+0xc0]",
+        "vmovdqu64 zmm4, [{d}+0x100]",
+        "vmovdqu64 zmm5, [{d}+0x140]",
+        "vmovdqu64 zmm6, [{d}+0x180]",
+        "vmovdqu64 zmm7, [{d}+0x1c0]",
+        "vpxorq zmm0, zmm0, zmm8",
+        "vpxorq zmm1, zmm1, zmm8",
+        "vpxorq zmm2, zmm2, zmm8",
+        "vpxorq zmm3, zmm3, zmm8",
+        "vpxorq zmm4, zmm4, zmm8",
+        "vpxorq zmm5, zmm5, zmm8",
+        "vpxorq zmm6, zmm6, zmm8",
+        "vpxorq zmm7, zmm7, zmm8",
+        "mov {i}, 0x10",
+
+        "2:",
+        "vbroadcasti32x4 zmm8, [{k}+{i}]",
+        "vaesenc zmm0, zmm0, zmm8",
+        "vaesenc zmm1, zmm1, zmm8",
+        "vaesenc zmm2, zmm2, zmm8",
+        "vaesenc zmm3, zmm3, zmm8",
+        "vaesenc zmm4, zmm4, zmm8",
+        "vaesenc zmm5, zmm5, zmm8",
+        "vaesenc zmm6, zmm6, zmm8",
+        "vaesenc zmm7, zmm7, zmm8",
+        "add {i}, 0x10",
+        "cmp {i}, {l}",
+        "jne 2b",
+
+        "vbroadcasti32x4 zmm8, [{k}+{i}]",
+        "vaesenclast zmm0, zmm0, zmm8",
+        "vaesenclast zmm1, zmm1, zmm8",
+        "vaesenclast zmm2, zmm2, zmm8",
+        "vaesenclast zmm3, zmm3, zmm8",
+        "vaesenclast zmm4, zmm4, zmm8",
+        "vaesenclast zmm5, zmm5, zmm8",
+        "vaesenclast zmm6, zmm6, zmm8",
+        "vaesenclast zmm7, zmm7, zmm8",
+        "vmovdqu64 [{d}], zmm0",
+        "vmovdqu64 [{d}+0x40], zmm1",
+        "vmovdqu64 [{d}+0x80], zmm2",
+        "vmovdqu64 [{d}+0xc0], zmm3",
+        "vmovdqu64 [{d}+0x100], zmm4",
+        "vmovdqu64 [{d}+0x140], zmm5",
+        "vmovdqu64 [{d}+0x180], zmm6",
+        "vmovdqu64 [{d}+0x1c0], zmm7",
+        "vzeroupper",
+        d = in(reg) data,
+        k = in(reg) key,
+        l = in(reg) key_len - 0x10,
+        i = out(reg) _,
+        out("zmm0") _,
+        out("zmm1") _,
+        out("zmm2") _,
+        out("zmm3") _,
+        out("zmm4") _,
+        out("zmm5") _,
+        out("zmm6") _,
+        out("zmm7") _,
+        out("zmm8") _,
+    )
+}
+
+#[target_feature(enable = "avx512f,vaes")]
+unsafe fn aes_dec_zmm_x8(data: *mut u8, inv_key: *const u8, key_len: usize) {
+    asm!(
+        "mov {i}, {l}",
+        "vbroadcasti32x4 zmm8, [{k}+{i}]",
+        "vmovdqu64 zmm0, [{d}]",
+        "vmovdqu64 zmm1, [{d}+0x40]",
+        "vmovdqu64 zmm2, [{d}+0x80]",
+        "vmovdqu64 zmm3, [{d}+0xc0]",
+        "vmovdqu64 zmm4, [{d}+0x100]",
+        "vmovdqu64 zmm5, [{d}+0x140]",
+        "vmovdqu64 zmm6, [{d}+0x180]",
+        "vmovdqu64 zmm7, [{d}+0x1c0]",
+        "vpxorq zmm0, zmm0, zmm8",
+        "vpxorq zmm1, zmm1, zmm8",
+        "vpxorq zmm2, zmm2, zmm8",
+        "vpxorq zmm3, zmm3, zmm8",
+        "vpxorq zmm4, zmm4, zmm8",
+        "vpxorq zmm5, zmm5, zmm8",
+        "vpxorq zmm6, zmm6, zmm8",
+        "vpxorq zmm7, zmm7, zmm8",
+
+        "2:",
+        "sub {i}, 0x10",
+        "vbroadcasti32x4 zmm8, [{k}+{i}]",
+        "vaesdec zmm0, zmm0, zmm8",
+        "vaesdec zmm1, zmm1, zmm8",
+        "vaesdec zmm2, zmm2, zmm8",
+        "vaesdec zmm3, zmm3, zmm8",
+        "vaesdec zmm4, zmm4, zmm8",
+        "vaesdec zmm5, zmm5, zmm8",
+        "vaesdec zmm6, zmm6, zmm8",
+        "vaesdec zmm7, zmm7, zmm8",
+        "cmp {i}, 0x10",
+        "jne 2b",
+
+        "vbroadcasti32x4 zmm8, [{k}]",
+        "vaesdeclast zmm0, zmm0, zmm8",
+        "vaesdeclast zmm1, zmm1, zmm8",
+        "vaesdeclast zmm2, zmm2, zmm8",
+        "vaesdeclast zmm3, zmm3, zmm8",
+        "vaesdeclast zmm4, zmm4, zmm8",
+        "vaesdeclast zmm5, zmm5, zmm8",
+        "vaesdeclast zmm6, zmm6, zmm8",
+        "vaesdeclast zmm7, zmm7, zmm8",
+        "vmovdqu64 [{d}], zmm0",
+        "vmovdqu64 [{d}+0x40], zmm1",
+        "vmovdqu64 [{d}+0x80], zmm2",
+        "vmovdqu64 [{d}+0xc0], zmm3",
+        "vmovdqu64 [{d}+0x100], zmm4",
+        "vmovdqu64 [{d}+0x140], zmm5",
+        "vmovdqu64 [{d}+0x180], zmm6",
+        "vmovdqu64 [{d}+0x1c0], zmm7",
+        "vzeroupper",
+        d = in(reg) data,
+        k = in(reg) inv_key,
+        l = in(reg) key_len - 0x10,
+        i = out(reg) _,
+        out("zmm0") _,
+        out("zmm1") _,
+        out("zmm2") _,
+        out("zmm3") _,
+        out("zmm4") _,
+        out("zmm5") _,
+        out("zmm6") _,
+        out("zmm7") _,
+        out("zmm8") _,
+    )
+}
 
 #[cfg(test)]
 mod test {
-    use crate::{aes_ni::AES_NI, aes::AESkey, mode::BlockCipher};
+    use std::array;
+
+    use crate::{aes_ni::{AES_NI, VaesWidth}, aes::{AESkey, AES}, mode::BlockCipher};
 
     #[test]
     fn encrypt() {
@@ -342,4 +1189,43 @@ mod test {
         );
         assert_eq!(c._decrypt(*b"e\x13\xa2\xa4\xc7R\xca@3\xc0\xde\xf6\xab:\xe8\xcb"), ans);
     }
+
+    #[test]
+    fn blocks() {
+        let keys = [
+            AESkey::K128(*b"!\xf4\x02\xf2[\x1a\x0f\xd7\"\xb81i\xe1\x05\t\xf8"),
+            AESkey::K192(*b"\x01kG\xc4\xa2XI\nRA\xea\xc9m\xde\x81\xb8\"\xbd \xd5_\xa2A\x0e"),
+            AESkey::K256(*b"\xa8\x19@\x8c\xe5\x01\x0c\xa2\xe0\x9e\xf5\x9a\xc3\xd8\x9f_\xf8Y]\x02\xb5$\xe6\x1b\xf8\xaf\xa8\x94\xa9]YO"),
+        ];
+        let widths = [VaesWidth::Xmm, VaesWidth::Ymm, VaesWidth::Zmm];
+        let data: [u8; 16 * 63] = array::from_fn(|i| (i * 7 + 3) as u8);
+        let iv: [u8; 16] = array::from_fn(|i| i as u8);
+        for key in keys {
+            for width in widths.into_iter().filter(|w| w.is_available()) {
+                let (soft, ni) = match &key {
+                    AESkey::K128(k) => (AES::new(AESkey::K128(*k)), AES_NI::with_width(AESkey::K128(*k), width)),
+                    AESkey::K192(k) => (AES::new(AESkey::K192(*k)), AES_NI::with_width(AESkey::K192(*k), width)),
+                    AESkey::K256(k) => (AES::new(AESkey::K256(*k)), AES_NI::with_width(AESkey::K256(*k), width)),
+                };
+                assert_eq!(ni.width(), width);
+                let mut ans = [0u8; 16 * 63];
+                let mut out = [0u8; 16 * 63];
+                let mut dec = [0u8; 16 * 63];
+
+                soft.encrypt_ecb(&data, &mut ans);
+                ni.encrypt_ecb(&data, &mut out);
+                assert_eq!(ans, out);
+                ni.decrypt_ecb(&out, &mut dec);
+                assert_eq!(data, dec);
+
+                soft.encrypt_cbc(&data, iv, &mut ans);
+                ni.decrypt_cbc(&ans, iv, &mut dec);
+                assert_eq!(data, dec);
+
+                soft.encrypt_ctr(&data[5..], b"0123", &mut ans[5..]);
+                ni.encrypt_ctr(&data[5..], b"0123", &mut out[5..]);
+                assert_eq!(ans[5..], out[5..]);
+            }
+        }
+    }
 }
@@ -0,0 +1,202 @@
+use std::sync::OnceLock;
+
+use crate::{aes::{AESkey, AES}, bitslice::AESBitslice, mode::BlockCipher, ttable::AESTable};
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+use crate::aes_ni::{support_aesni, AES_NI};
+#[cfg(feature = "use_nightly")]
+use crate::simd::AESSimd;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Software,
+    Bitslice,
+    Table,
+    #[cfg(feature = "use_nightly")]
+    Simd,
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    AesNi,
+}
+
+impl Backend {
+    // CPUIDは最初の一回だけ呼ぶ
+    pub fn detect() -> Self {
+        static DETECTED: OnceLock<Backend> = OnceLock::new();
+        *DETECTED.get_or_init(|| {
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            if support_aesni() {
+                return Backend::AesNi;
+            }
+            Backend::Software
+        })
+    }
+
+    // AES-NIがなければ秘密データでテーブルを引かないビットスライスを選ぶ
+    pub fn detect_constant_time() -> Self {
+        match Backend::detect() {
+            Backend::Software => Backend::Bitslice,
+            b => b,
+        }
+    }
+
+    pub fn is_available(self) -> bool {
+        match self {
+            Backend::Software | Backend::Bitslice | Backend::Table => true,
+            #[cfg(feature = "use_nightly")]
+            Backend::Simd => true,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::AesNi => Backend::detect() == Backend::AesNi,
+        }
+    }
+}
+
+enum Inner {
+    Software(AES),
+    Bitslice(AESBitslice),
+    Table(AESTable),
+    #[cfg(feature = "use_nightly")]
+    Simd(AESSimd),
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    AesNi(AES_NI),
+}
+
+pub struct AutoAES {
+    inner: Inner,
+}
+
+impl AutoAES {
+    pub fn new(key: AESkey) -> Self {
+        Self::with_backend(key, Backend::detect())
+    }
+
+    pub fn with_backend(key: AESkey, backend: Backend) -> Self {
+        if !backend.is_available() {
+            panic!("{:?}はこのCPUでは使えません", backend);
+        }
+        let inner = match backend {
+            Backend::Software => Inner::Software(AES::new(key)),
+            Backend::Bitslice => Inner::Bitslice(AESBitslice::new(key)),
+            Backend::Table => Inner::Table(AESTable::new(key)),
+            #[cfg(feature = "use_nightly")]
+            Backend::Simd => Inner::Simd(AESSimd::new(key)),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Backend::AesNi => Inner::AesNi(AES_NI::new(key)),
+        };
+        Self { inner }
+    }
+
+    pub fn backend(&self) -> Backend {
+        match self.inner {
+            Inner::Software(_) => Backend::Software,
+            Inner::Bitslice(_) => Backend::Bitslice,
+            Inner::Table(_) => Backend::Table,
+            #[cfg(feature = "use_nightly")]
+            Inner::Simd(_) => Backend::Simd,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Inner::AesNi(_) => Backend::AesNi,
+        }
+    }
+}
+
+impl BlockCipher<16> for AutoAES {
+    fn _encrypt(&self, in_bytes: [u8; 16]) -> [u8; 16] {
+        match &self.inner {
+            Inner::Software(c) => c._encrypt(in_bytes),
+            Inner::Bitslice(c) => c._encrypt(in_bytes),
+            Inner::Table(c) => c._encrypt(in_bytes),
+            #[cfg(feature = "use_nightly")]
+            Inner::Simd(c) => c._encrypt(in_bytes),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Inner::AesNi(c) => c._encrypt(in_bytes),
+        }
+    }
+
+    fn _decrypt(&self, in_bytes: [u8; 16]) -> [u8; 16] {
+        match &self.inner {
+            Inner::Software(c) => c._decrypt(in_bytes),
+            Inner::Bitslice(c) => c._decrypt(in_bytes),
+            Inner::Table(c) => c._decrypt(in_bytes),
+            #[cfg(feature = "use_nightly")]
+            Inner::Simd(c) => c._decrypt(in_bytes),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Inner::AesNi(c) => c._decrypt(in_bytes),
+        }
+    }
+
+    fn _encrypt_blocks(&self, blocks: &mut [[u8; 16]]) {
+        match &self.inner {
+            Inner::Software(c) => c._encrypt_blocks(blocks),
+            Inner::Bitslice(c) => c._encrypt_blocks(blocks),
+            Inner::Table(c) => c._encrypt_blocks(blocks),
+            #[cfg(feature = "use_nightly")]
+            Inner::Simd(c) => c._encrypt_blocks(blocks),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Inner::AesNi(c) => c._encrypt_blocks(blocks),
+        }
+    }
+
+    fn _decrypt_blocks(&self, blocks: &mut [[u8; 16]]) {
+        match &self.inner {
+            Inner::Software(c) => c._decrypt_blocks(blocks),
+            Inner::Bitslice(c) => c._decrypt_blocks(blocks),
+            Inner::Table(c) => c._decrypt_blocks(blocks),
+            #[cfg(feature = "use_nightly")]
+            Inner::Simd(c) => c._decrypt_blocks(blocks),
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Inner::AesNi(c) => c._decrypt_blocks(blocks),
+        }
+    }
+
+    fn _aesni_round_key(&self) -> Option<&[u8]> {
+        match &self.inner {
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Inner::AesNi(c) => c._aesni_round_key(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{auto::{AutoAES, Backend}, aes::AESkey, mode::BlockCipher};
+
+    fn backends() -> Vec<Backend> {
+        let mut v = vec![Backend::Software, Backend::Bitslice, Backend::Table];
+        #[cfg(feature = "use_nightly")]
+        v.push(Backend::Simd);
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        if Backend::AesNi.is_available() {
+            v.push(Backend::AesNi);
+        }
+        v
+    }
+
+    #[test]
+    fn detect() {
+        let a = AutoAES::new(AESkey::K128(*b"!\xf4\x02\xf2[\x1a\x0f\xd7\"\xb81i\xe1\x05\t\xf8"));
+        assert_eq!(a.backend(), Backend::detect());
+        assert!(a.backend().is_available());
+        let b = Backend::detect_constant_time();
+        assert!(b != Backend::Software && b != Backend::Table && b.is_available());
+    }
+
+    #[test]
+    fn encrypt_decrypt() {
+        let ans = *b"s\xdf\xffW\xfe$\xe8\x07\xbdO\xb1\xbcN\x07\xcds";
+        for backend in backends() {
+            let a = AutoAES::with_backend(
+                AESkey::K128(*b"!\xf4\x02\xf2[\x1a\x0f\xd7\"\xb81i\xe1\x05\t\xf8"),
+                backend
+            );
+            assert_eq!(a.backend(), backend);
+            assert_eq!(a._encrypt(ans), *b"\x9c)\xe4l\xf1\xce\x04\xe8=:k\x16{{\xe1J");
+            assert_eq!(a._decrypt(*b"\x9c)\xe4l\xf1\xce\x04\xe8=:k\x16{{\xe1J"), ans);
+
+            let c = AutoAES::with_backend(
+                AESkey::K256(*b"\xa8\x19@\x8c\xe5\x01\x0c\xa2\xe0\x9e\xf5\x9a\xc3\xd8\x9f_\xf8Y]\x02\xb5$\xe6\x1b\xf8\xaf\xa8\x94\xa9]YO"),
+                backend
+            );
+            assert_eq!(c._encrypt(ans), *b"e\x13\xa2\xa4\xc7R\xca@3\xc0\xde\xf6\xab:\xe8\xcb");
+            assert_eq!(c._decrypt(*b"e\x13\xa2\xa4\xc7R\xca@3\xc0\xde\xf6\xab:\xe8\xcb"), ans);
+        }
+    }
+}
@@ -0,0 +1,527 @@
+use std::{array, fmt::{self, Debug}, ops::{BitAnd, BitOr, BitXor, Not}};
+
+use crate::{aes::{AESkey, RC}, mode::BlockCipher};
+
+// 4ブロックを8本のu64にビットスライスして処理する
+// (BearSSLのaes_ct64と同じ配置)
+pub(crate) type State = [u64; 8];
+
+// ビットスライスした状態の1ワード (u64をいくつか並べたSIMD型でもよい)
+pub(crate) trait Lanes: Copy + BitXor<Output = Self> + BitAnd<Output = Self> + BitOr<Output = Self> + Not<Output = Self> {
+    fn splat(v: u64) -> Self;
+    fn shl(self, n: u32) -> Self;
+    fn shr(self, n: u32) -> Self;
+    fn rotr(self, n: u32) -> Self {
+        self.shr(n) | self.shl(64 - n)
+    }
+}
+
+impl Lanes for u64 {
+    fn splat(v: u64) -> Self {
+        v
+    }
+    fn shl(self, n: u32) -> Self {
+        self << n
+    }
+    fn shr(self, n: u32) -> Self {
+        self >> n
+    }
+    fn rotr(self, n: u32) -> Self {
+        self.rotate_right(n)
+    }
+}
+
+fn swap_n(x: &mut u64, y: &mut u64, cl: u64, ch: u64, s: u32) {
+    let (a, b) = (*x, *y);
+    *x = (a & cl) | ((b & cl) << s);
+    *y = ((a & ch) >> s) | (b & ch);
+}
+
+// 転置 (自身が逆変換)
+fn ortho(q: &mut State) {
+    for (m, s, l, h) in [
+        (1, 1, 0x5555555555555555, 0xaaaaaaaaaaaaaaaa),
+        (2, 2, 0x3333333333333333, 0xcccccccccccccccc),
+        (4, 4, 0x0f0f0f0f0f0f0f0f, 0xf0f0f0f0f0f0f0f0),
+    ] {
+        for i in (0..8).filter(|i| i & m == 0) {
+            let (a, b) = q.split_at_mut(i + m);
+            swap_n(&mut a[i], &mut b[0], l, h, s);
+        }
+    }
+}
+
+fn interleave_in(w: [u32; 4]) -> (u64, u64) {
+    let mut x = w.map(|v| {
+        let mut v = v as u64;
+        v |= v << 16;
+        v &= 0x0000ffff0000ffff;
+        v |= v << 8;
+        v & 0x00ff00ff00ff00ff
+    });
+    x[2] <<= 8;
+    x[3] <<= 8;
+    (x[0] | x[2], x[1] | x[3])
+}
+
+fn interleave_out(q0: u64, q1: u64) -> [u32; 4] {
+    [q0, q1, q0 >> 8, q1 >> 8].map(|v| {
+        let mut v = v & 0x00ff00ff00ff00ff;
+        v |= v >> 8;
+        v &= 0x0000ffff0000ffff;
+        (v as u32) | ((v >> 16) as u32)
+    })
+}
+
+pub(crate) fn load(blocks: &[[u8; 16]; 4]) -> State {
+    let mut q = [0; 8];
+    for (i, b) in blocks.iter().enumerate() {
+        let w = array::from_fn(|j| u32::from_le_bytes(b[4 * j..4 * j + 4].try_into().unwrap()));
+        (q[i], q[i + 4]) = interleave_in(w);
+    }
+    ortho(&mut q);
+    q
+}
+
+pub(crate) fn store(mut q: State, blocks: &mut [[u8; 16]; 4]) {
+    ortho(&mut q);
+    for (i, b) in blocks.iter_mut().enumerate() {
+        let w = interleave_out(q[i], q[i + 4]);
+        for j in 0..4 {
+            b[4 * j..4 * j + 4].copy_from_slice(&w[j].to_le_bytes());
+        }
+    }
+}
+
+// Boyar-PeraltaのS-box回路
+fn sub_bytes<T: Lanes>(q: &mut [T; 8]) {
+    let x0 = q[7];
+    let x1 = q[6];
+    let x2 = q[5];
+    let x3 = q[4];
+    let x4 = q[3];
+    let x5 = q[2];
+    let x6 = q[1];
+    let x7 = q[0];
+
+    // 上側の線形変換
+    let y14 = x3 ^ x5;
+    let y13 = x0 ^ x6;
+    let y9 = x0 ^ x3;
+    let y8 = x0 ^ x5;
+    let t0 = x1 ^ x2;
+    let y1 = t0 ^ x7;
+    let y4 = y1 ^ x3;
+    let y12 = y13 ^ y14;
+    let y2 = y1 ^ x0;
+    let y5 = y1 ^ x6;
+    let y3 = y5 ^ y8;
+    let t1 = x4 ^ y12;
+    let y15 = t1 ^ x5;
+    let y20 = t1 ^ x1;
+    let y6 = y15 ^ x7;
+    let y10 = y15 ^ t0;
+    let y11 = y20 ^ y9;
+    let y7 = x7 ^ y11;
+    let y17 = y10 ^ y11;
+    let y19 = y10 ^ y8;
+    let y16 = t0 ^ y11;
+    let y21 = y13 ^ y16;
+    let y18 = x0 ^ y16;
+
+    // 非線形部分
+    let t2 = y12 & y15;
+    let t3 = y3 & y6;
+    let t4 = t3 ^ t2;
+    let t5 = y4 & x7;
+    let t6 = t5 ^ t2;
+    let t7 = y13 & y16;
+    let t8 = y5 & y1;
+    let t9 = t8 ^ t7;
+    let t10 = y2 & y7;
+    let t11 = t10 ^ t7;
+    let t12 = y9 & y11;
+    let t13 = y14 & y17;
+    let t14 = t13 ^ t12;
+    let t15 = y8 & y10;
+    let t16 = t15 ^ t12;
+    let t17 = t4 ^ t14;
+    let t18 = t6 ^ t16;
+    let t19 = t9 ^ t14;
+    let t20 = t11 ^ t16;
+    let t21 = t17 ^ y20;
+    let t22 = t18 ^ y19;
+    let t23 = t19 ^ y21;
+    let t24 = t20 ^ y18;
+
+    let t25 = t21 ^ t22;
+    let t26 = t21 & t23;
+    let t27 = t24 ^ t26;
+    let t28 = t25 & t27;
+    let t29 = t28 ^ t22;
+    let t30 = t23 ^ t24;
+    let t31 = t22 ^ t26;
+    let t32 = t31 & t30;
+    let t33 = t32 ^ t24;
+    let t34 = t23 ^ t33;
+    let t35 = t27 ^ t33;
+    let t36 = t24 & t35;
+    let t37 = t36 ^ t34;
+    let t38 = t27 ^ t36;
+    let t39 = t29 & t38;
+    let t40 = t25 ^ t39;
+
+    let t41 = t40 ^ t37;
+    let t42 = t29 ^ t33;
+    let t43 = t29 ^ t40;
+    let t44 = t33 ^ t37;
+    let t45 = t42 ^ t41;
+    let z0 = t44 & y15;
+    let z1 = t37 & y6;
+    let z2 = t33 & x7;
+    let z3 = t43 & y16;
+    let z4 = t40 & y1;
+    let z5 = t29 & y7;
+    let z6 = t42 & y11;
+    let z7 = t45 & y17;
+    let z8 = t41 & y10;
+    let z9 = t44 & y12;
+    let z10 = t37 & y3;
+    let z11 = t33 & y4;
+    let z12 = t43 & y13;
+    let z13 = t40 & y5;
+    let z14 = t29 & y2;
+    let z15 = t42 & y9;
+    let z16 = t45 & y14;
+    let z17 = t41 & y8;
+
+    // 下側の線形変換
+    let t46 = z15 ^ z16;
+    let t47 = z10 ^ z11;
+    let t48 = z5 ^ z13;
+    let t49 = z9 ^ z10;
+    let t50 = z2 ^ z12;
+    let t51 = z2 ^ z5;
+    let t52 = z7 ^ z8;
+    let t53 = z0 ^ z3;
+    let t54 = z6 ^ z7;
+    let t55 = z16 ^ z17;
+    let t56 = z12 ^ t48;
+    let t57 = t50 ^ t53;
+    let t58 = z4 ^ t46;
+    let t59 = z3 ^ t54;
+    let t60 = t46 ^ t57;
+    let t61 = z14 ^ t57;
+    let t62 = t52 ^ t58;
+    let t63 = t49 ^ t58;
+    let t64 = z4 ^ t59;
+    let t65 = t61 ^ t62;
+    let t66 = z1 ^ t63;
+    let s0 = t59 ^ t63;
+    let s6 = t56 ^ !t62;
+    let s7 = t48 ^ !t60;
+    let t67 = t64 ^ t65;
+    let s3 = t53 ^ t66;
+    let s4 = t51 ^ t66;
+    let s5 = t47 ^ t65;
+    let s1 = t64 ^ !s3;
+    let s2 = t55 ^ !t67;
+
+    *q = [s7, s6, s5, s4, s3, s2, s1, s0];
+}
+
+// x -> A^-1(x ^ 0x63) (Aはsub_bytesのアフィン変換の線形部分)
+fn inv_affine<T: Lanes>(q: &mut [T; 8]) {
+    let q0 = !q[0];
+    let q1 = !q[1];
+    let q2 = q[2];
+    let q3 = q[3];
+    let q4 = q[4];
+    let q5 = !q[5];
+    let q6 = !q[6];
+    let q7 = q[7];
+    *q = [
+        q2 ^ q5 ^ q7,
+        q3 ^ q6 ^ q0,
+        q4 ^ q7 ^ q1,
+        q5 ^ q0 ^ q2,
+        q6 ^ q1 ^ q3,
+        q7 ^ q2 ^ q4,
+        q0 ^ q3 ^ q5,
+        q1 ^ q4 ^ q6,
+    ];
+}
+
+// InvS(x) = T(S(T(x))), T(x) = A^-1(x ^ 0x63)
+fn inv_sub_bytes<T: Lanes>(q: &mut [T; 8]) {
+    inv_affine(q);
+    sub_bytes(q);
+    inv_affine(q);
+}
+
+fn shift_rows<T: Lanes>(q: &mut [T; 8]) {
+    for x in q.iter_mut() {
+        let v = *x;
+        *x = (v & T::splat(0x000000000000ffff))
+            | (v & T::splat(0x00000000fff00000)).shr(4)
+            | (v & T::splat(0x00000000000f0000)).shl(12)
+            | (v & T::splat(0x0000ff0000000000)).shr(8)
+            | (v & T::splat(0x000000ff00000000)).shl(8)
+            | (v & T::splat(0xf000000000000000)).shr(12)
+            | (v & T::splat(0x0fff000000000000)).shl(4);
+    }
+}
+
+fn inv_shift_rows<T: Lanes>(q: &mut [T; 8]) {
+    for x in q.iter_mut() {
+        let v = *x;
+        *x = (v & T::splat(0x000000000000ffff))
+            | (v & T::splat(0x000000000fff0000)).shl(4)
+            | (v & T::splat(0x00000000f0000000)).shr(12)
+            | (v & T::splat(0x000000ff00000000)).shl(8)
+            | (v & T::splat(0x0000ff0000000000)).shr(8)
+            | (v & T::splat(0x000f000000000000)).shl(12)
+            | (v & T::splat(0xfff0000000000000)).shr(4);
+    }
+}
+
+// GF(2^8)上での2倍
+fn xtime<T: Lanes>(q: [T; 8]) -> [T; 8] {
+    [q[7], q[0] ^ q[7], q[1], q[2] ^ q[7], q[3] ^ q[7], q[4], q[5], q[6]]
+}
+
+// y_i = 2(x_i + x_{i+1}) + x_{i+1} + x_{i+2} + x_{i+3}
+fn mix_columns<T: Lanes>(q: &mut [T; 8]) {
+    let r = q.map(|v| v.rotr(16));
+    let s: [T; 8] = array::from_fn(|i| q[i] ^ r[i]);
+    let d = xtime(s);
+    *q = array::from_fn(|i| d[i] ^ r[i] ^ s[i].rotr(32));
+}
+
+// InvMixColumns = MixColumns * (5 + 4x^2)
+fn inv_mix_columns<T: Lanes>(q: &mut [T; 8]) {
+    let t: [T; 8] = array::from_fn(|i| q[i] ^ q[i].rotr(32));
+    let d = xtime(xtime(t));
+    *q = array::from_fn(|i| q[i] ^ d[i]);
+    mix_columns(q);
+}
+
+fn add_round_key<T: Lanes>(q: &mut [T; 8], k: &State) {
+    *q = array::from_fn(|i| q[i] ^ T::splat(k[i]));
+}
+
+pub(crate) fn encrypt_state<T: Lanes>(keys: &[State], q: &mut [T; 8]) {
+    let round = keys.len();
+    add_round_key(q, &keys[0]);
+    for k in &keys[1..round - 1] {
+        sub_bytes(q);
+        shift_rows(q);
+        mix_columns(q);
+        add_round_key(q, k);
+    }
+    sub_bytes(q);
+    shift_rows(q);
+    add_round_key(q, &keys[round - 1]);
+}
+
+pub(crate) fn decrypt_state<T: Lanes>(keys: &[State], q: &mut [T; 8]) {
+    let round = keys.len();
+    add_round_key(q, &keys[round - 1]);
+    for k in keys[1..round - 1].iter().rev() {
+        inv_shift_rows(q);
+        inv_sub_bytes(q);
+        add_round_key(q, k);
+        inv_mix_columns(q);
+    }
+    inv_shift_rows(q);
+    inv_sub_bytes(q);
+    add_round_key(q, &keys[0]);
+}
+
+fn sub_word(w: u32) -> u32 {
+    let mut q = [0; 8];
+    q[0] = w as u64;
+    ortho(&mut q);
+    sub_bytes(&mut q);
+    ortho(&mut q);
+    q[0] as u32
+}
+
+pub struct AESBitslice {
+    pub(crate) round_keys: Box<[State]>,
+}
+
+// 拡大鍵は表示しない
+impl Debug for AESBitslice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AESBitslice").finish_non_exhaustive()
+    }
+}
+
+impl AESBitslice {
+    pub fn new(key: AESkey) -> Self {
+        let (k, r): (&[u8], usize) = match &key {
+            AESkey::K128(k) => (&k[..], 11),
+            AESkey::K192(k) => (&k[..], 13),
+            AESkey::K256(k) => (&k[..], 15),
+        };
+        let n = k.len() / 4;
+        let mut w: Vec<u32> = k.chunks_exact(4).map(|v| u32::from_le_bytes(v.try_into().unwrap())).collect();
+        for i in n..4 * r {
+            let mut t = w[i - 1];
+            if i % n == 0 {
+                t = sub_word(t.rotate_right(8)) ^ RC[i / n - 1] as u32;
+            } else if n > 6 && i % n == 4 {
+                t = sub_word(t);
+            }
+            w.push(w[i - n] ^ t);
+        }
+        let round_keys = w.chunks_exact(4).map(|v| {
+            let mut q = [0; 8];
+            let (a, b) = interleave_in(v.try_into().unwrap());
+            q[..4].fill(a);
+            q[4..].fill(b);
+            ortho(&mut q);
+            q
+        }).collect();
+        Self { round_keys }
+    }
+
+    fn encrypt4(&self, blocks: &mut [[u8; 16]; 4]) {
+        let mut q = load(blocks);
+        encrypt_state(&self.round_keys, &mut q);
+        store(q, blocks);
+    }
+
+    fn decrypt4(&self, blocks: &mut [[u8; 16]; 4]) {
+        let mut q = load(blocks);
+        decrypt_state(&self.round_keys, &mut q);
+        store(q, blocks);
+    }
+
+    // 4ブロックに満たない端数は0で埋めて処理する
+    fn each4(blocks: &mut [[u8; 16]], f: impl Fn(&mut [[u8; 16]; 4])) {
+        let mut chunks = blocks.chunks_exact_mut(4);
+        for c in &mut chunks {
+            f(c.try_into().unwrap());
+        }
+        let rest = chunks.into_remainder();
+        if !rest.is_empty() {
+            let mut tmp = [[0; 16]; 4];
+            tmp[..rest.len()].copy_from_slice(rest);
+            f(&mut tmp);
+            rest.copy_from_slice(&tmp[..rest.len()]);
+        }
+    }
+}
+
+impl BlockCipher<16> for AESBitslice {
+    fn _encrypt(&self, in_bytes: [u8; 16]) -> [u8; 16] {
+        let mut b = [in_bytes, [0; 16], [0; 16], [0; 16]];
+        self.encrypt4(&mut b);
+        b[0]
+    }
+
+    fn _decrypt(&self, in_bytes: [u8; 16]) -> [u8; 16] {
+        let mut b = [in_bytes, [0; 16], [0; 16], [0; 16]];
+        self.decrypt4(&mut b);
+        b[0]
+    }
+
+    fn _encrypt_blocks(&self, blocks: &mut [[u8; 16]]) {
+        Self::each4(blocks, |b| self.encrypt4(b));
+    }
+
+    fn _decrypt_blocks(&self, blocks: &mut [[u8; 16]]) {
+        Self::each4(blocks, |b| self.decrypt4(b));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::array;
+
+    use crate::aes::{AESkey, AES, INV_S_BOX, S_BOX};
+    use crate::bitslice::{inv_sub_bytes, load, store, sub_bytes, AESBitslice};
+    use crate::mode::BlockCipher;
+
+    #[test]
+    fn sbox() {
+        for i in 0..4 {
+            let mut b: [[u8; 16]; 4] = array::from_fn(|j| array::from_fn(|k| (64 * i + 16 * j + k) as u8));
+            let mut q = load(&b);
+            sub_bytes::<u64>(&mut q);
+            store(q, &mut b);
+            for (j, v) in b.iter().flatten().enumerate() {
+                assert_eq!(*v, S_BOX[64 * i + j]);
+            }
+            let mut q = load(&b);
+            inv_sub_bytes::<u64>(&mut q);
+            inv_sub_bytes::<u64>(&mut q);
+            store(q, &mut b);
+            for (j, v) in b.iter().flatten().enumerate() {
+                assert_eq!(*v, INV_S_BOX[64 * i + j]);
+            }
+        }
+    }
+
+    #[test]
+    fn encrypt() {
+        let a = AESBitslice::new(
+            AESkey::K128(*b"!\xf4\x02\xf2[\x1a\x0f\xd7\"\xb81i\xe1\x05\t\xf8")
+        );
+        assert_eq!(a._encrypt(*b"s\xdf\xffW\xfe$\xe8\x07\xbdO\xb1\xbcN\x07\xcds"), *b"\x9c)\xe4l\xf1\xce\x04\xe8=:k\x16{{\xe1J");
+
+        let b = AESBitslice::new(
+            AESkey::K192(*b"\x01kG\xc4\xa2XI\nRA\xea\xc9m\xde\x81\xb8\"\xbd \xd5_\xa2A\x0e")
+        );
+        assert_eq!(b._encrypt(*b"s\xdf\xffW\xfe$\xe8\x07\xbdO\xb1\xbcN\x07\xcds"), *b"\xfa\xe3\xc6v\x8f\x90Xj>Rg,b\x05\xca\xb4");
+
+        let c = AESBitslice::new(
+            AESkey::K256(*b"\xa8\x19@\x8c\xe5\x01\x0c\xa2\xe0\x9e\xf5\x9a\xc3\xd8\x9f_\xf8Y]\x02\xb5$\xe6\x1b\xf8\xaf\xa8\x94\xa9]YO")
+        );
+        assert_eq!(c._encrypt(*b"s\xdf\xffW\xfe$\xe8\x07\xbdO\xb1\xbcN\x07\xcds"), *b"e\x13\xa2\xa4\xc7R\xca@3\xc0\xde\xf6\xab:\xe8\xcb");
+
+        // 鍵は表示しない
+        assert_eq!(format!("{:?}", c), "AESBitslice { .. }");
+    }
+
+    #[test]
+    fn decrypt() {
+        let ans = *b"s\xdf\xffW\xfe$\xe8\x07\xbdO\xb1\xbcN\x07\xcds";
+        let a = AESBitslice::new(
+            AESkey::K128(*b"!\xf4\x02\xf2[\x1a\x0f\xd7\"\xb81i\xe1\x05\t\xf8")
+        );
+        assert_eq!(a._decrypt(*b"\x9c)\xe4l\xf1\xce\x04\xe8=:k\x16{{\xe1J"), ans);
+
+        let b = AESBitslice::new(
+            AESkey::K192(*b"\x01kG\xc4\xa2XI\nRA\xea\xc9m\xde\x81\xb8\"\xbd \xd5_\xa2A\x0e")
+        );
+        assert_eq!(b._decrypt(*b"\xfa\xe3\xc6v\x8f\x90Xj>Rg,b\x05\xca\xb4"), ans);
+
+        let c = AESBitslice::new(
+            AESkey::K256(*b"\xa8\x19@\x8c\xe5\x01\x0c\xa2\xe0\x9e\xf5\x9a\xc3\xd8\x9f_\xf8Y]\x02\xb5$\xe6\x1b\xf8\xaf\xa8\x94\xa9]YO")
+        );
+        assert_eq!(c._decrypt(*b"e\x13\xa2\xa4\xc7R\xca@3\xc0\xde\xf6\xab:\xe8\xcb"), ans);
+    }
+
+    #[test]
+    fn blocks() {
+        let key = *b"!\xf4\x02\xf2[\x1a\x0f\xd7\"\xb81i\xe1\x05\t\xf8";
+        let soft = AES::new(AESkey::K128(key));
+        let bs = AESBitslice::new(AESkey::K128(key));
+        let data: [u8; 16 * 11] = array::from_fn(|i| (i * 7 + 3) as u8);
+        let mut ans = [0u8; 16 * 11];
+        let mut out = [0u8; 16 * 11];
+        let mut dec = [0u8; 16 * 11];
+        soft.encrypt_ecb(&data, &mut ans);
+        bs.encrypt_ecb(&data, &mut out);
+        assert_eq!(ans, out);
+        bs.decrypt_ecb(&out, &mut dec);
+        assert_eq!(data, dec);
+
+        soft.encrypt_ctr(&data[3..], b"0123", &mut ans[3..]);
+        bs.encrypt_ctr(&data[3..], b"0123", &mut out[3..]);
+        assert_eq!(ans[3..], out[3..]);
+    }
+}
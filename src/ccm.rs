@@ -0,0 +1,216 @@
+use std::array;
+
+use crate::{error::AesError, mode::{ct_eq, ctr_blocks, BlockCipher}};
+
+// CCM (NIST SP 800-38C) と、タグを省略できるCCM* (IEEE 802.15.4)
+// nonceの長さ(7..=13バイト)とtagの長さは引数のスライスの長さで決まる
+pub struct Ccm<C: BlockCipher<16>> {
+    cipher: C,
+    star: bool,
+}
+
+impl<C: BlockCipher<16>> Ccm<C> {
+    pub fn new(cipher: C) -> Self {
+        Self { cipher, star: false }
+    }
+
+    // 長さ0のタグ(暗号化のみ)も使えるCCM*
+    pub fn new_star(cipher: C) -> Self {
+        Self { cipher, star: true }
+    }
+
+    fn check(&self, nonce: &[u8], in_len: usize, out_len: usize, tag_len: usize) -> Result<(), AesError> {
+        if in_len != out_len {
+            return Err(AesError::LengthMismatch { in_len, out_len });
+        }
+        if !(7..=13).contains(&nonce.len()) {
+            return Err(AesError::InvalidIvLength(nonce.len()));
+        }
+        let valid_tag = matches!(tag_len, 4 | 6 | 8 | 10 | 12 | 14 | 16) || (self.star && tag_len == 0);
+        if !valid_tag {
+            return Err(AesError::InvalidTagLength(tag_len));
+        }
+        // 長さはq = 15 - nonceの長さ バイトで表す
+        let q = 15 - nonce.len();
+        if q < 8 && in_len as u64 >= 1 << (8 * q) {
+            return Err(AesError::TooLong { len: in_len as u64, max: (1 << (8 * q)) - 1 });
+        }
+        Ok(())
+    }
+
+    fn counter(nonce: &[u8], i: usize) -> [u8; 16] {
+        let q = 15 - nonce.len();
+        let mut b = [0; 16];
+        b[0] = (q - 1) as u8;
+        b[1..16 - q].copy_from_slice(nonce);
+        b[16 - q..].copy_from_slice(&(i as u64).to_be_bytes()[8 - q..]);
+        b
+    }
+
+    // 平文に対するCBC-MAC
+    fn mac(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8], tag_len: usize) -> [u8; 16] {
+        let q = 15 - nonce.len();
+        let mut b0 = Ccm::<C>::counter(nonce, plaintext.len());
+        let m = if tag_len == 0 { 0 } else { (tag_len - 2) / 2 };
+        b0[0] = (((!aad.is_empty()) as u8) << 6) | ((m as u8) << 3) | (q - 1) as u8;
+        let mut x = self.cipher._encrypt(b0);
+
+        let absorb = |data: &[u8], x: &mut [u8; 16]| {
+            for c in data.chunks(16) {
+                for (a, b) in x.iter_mut().zip(c) {
+                    *a ^= b;
+                }
+                *x = self.cipher._encrypt(*x);
+            }
+        };
+        if !aad.is_empty() {
+            // AADの長さを前に付けて16バイト単位で0埋めする。最初のブロックは長さとAADの先頭
+            let mut head = [0; 16];
+            let a = aad.len() as u64;
+            let h = if a < 0xff00 {
+                head[..2].copy_from_slice(&(a as u16).to_be_bytes());
+                2
+            } else if a <= u32::MAX as u64 {
+                head[..2].copy_from_slice(&[0xff, 0xfe]);
+                head[2..6].copy_from_slice(&(a as u32).to_be_bytes());
+                6
+            } else {
+                head[..2].copy_from_slice(&[0xff, 0xff]);
+                head[2..10].copy_from_slice(&a.to_be_bytes());
+                10
+            };
+            let n = (16 - h).min(aad.len());
+            head[h..h + n].copy_from_slice(&aad[..n]);
+            absorb(&head[..h + n], &mut x);
+            absorb(&aad[n..], &mut x);
+        }
+        absorb(plaintext, &mut x);
+        x
+    }
+
+    // カウンタ1から使う。0はタグの暗号化用
+    fn ctr(&self, nonce: &[u8], in_bytes: &[u8], out_bytes: &mut [u8]) {
+        let mut i = 0;
+        let _ = ctr_blocks(&self.cipher, in_bytes, out_bytes, || {
+            i += 1;
+            Ok(Ccm::<C>::counter(nonce, i))
+        });
+    }
+
+    fn tag(&self, nonce: &[u8], t: [u8; 16]) -> [u8; 16] {
+        let s0 = self.cipher._encrypt(Ccm::<C>::counter(nonce, 0));
+        array::from_fn(|i| t[i] ^ s0[i])
+    }
+
+    pub fn encrypt(&self, nonce: &[u8], aad: &[u8], in_bytes: &[u8], out_bytes: &mut [u8], tag: &mut [u8]) -> Result<(), AesError> {
+        self.check(nonce, in_bytes.len(), out_bytes.len(), tag.len())?;
+        let t = self.tag(nonce, self.mac(nonce, aad, in_bytes, tag.len()));
+        self.ctr(nonce, in_bytes, out_bytes);
+        let n = tag.len();
+        tag.copy_from_slice(&t[..n]);
+        Ok(())
+    }
+
+    // タグが一致しなければout_bytesは0で埋める
+    pub fn decrypt(&self, nonce: &[u8], aad: &[u8], in_bytes: &[u8], tag: &[u8], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self.check(nonce, in_bytes.len(), out_bytes.len(), tag.len())?;
+        self.ctr(nonce, in_bytes, out_bytes);
+        let t = self.tag(nonce, self.mac(nonce, aad, out_bytes, tag.len()));
+        if !ct_eq(&t[..tag.len()], tag) {
+            out_bytes.fill(0);
+            return Err(AesError::AuthenticationFailed);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::array;
+
+    use crate::{aes::{AESkey, AES}, ccm::Ccm, error::AesError};
+
+    const K1: &[u8; 16] = b"\xc0\xc1\xc2\xc3\xc4\xc5\xc6\xc7\xc8\xc9\xca\xcb\xcc\xcd\xce\xcf";
+    const N1: &[u8; 13] = b"\x00\x00\x00\x03\x02\x01\x00\xa0\xa1\xa2\xa3\xa4\xa5";
+    const A1: &[u8; 8] = b"\x00\x01\x02\x03\x04\x05\x06\x07";
+    const P1: &[u8; 23] = b"\x08\t\n\x0b\x0c\r\x0e\x0f\x10\x11\x12\x13\x14\x15\x16\x17\x18\x19\x1a\x1b\x1c\x1d\x1e";
+    const C1: &[u8; 31] = b"X\x8c\x97\x9aa\xc6c\xd2\xf0f\xd0\xc2\xc0\xf9\x89\x80m_ka\xda\xc3\x84\x17\xe8\xd1,\xfd\xf9&\xe0";
+
+    const K3: &[u8; 32] = b"@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_";
+    const N3: &[u8; 13] = b"\x10\x11\x12\x13\x14\x15\x16\x17\x18\x19\x1a\x1b\x1c";
+    const C3: &[u8; 116] = b"cyN\x84~r\x9c\xcf\xe5\xdf\x18[\x92\x07\x9a7_!e/4<\xa5\xc3v\xd2\xa0R\xa2_\x1d\x9f\xbf?\xee\xd6\xf1d\xf1)\x1f_C\xbf\xb5!\xfc\x1f\xddb\x1bcW\xc0\xd4\xe0\x1eR\x02\x8a\xc9\xad\r\xb1W\xdfUw\xd7\xe3,\xb0`\x18h\x8b\x938\x06\x0f+8G\x92\x08{\x03\x97\xe5r<\x83\xe9\xc1\x7fa\xd3\x81\xaf\xc8N\x14\x03\x90\xbc;\x02\x17]<\xc3\x8f\xe6\xa2\x82\x9e";
+
+    fn inputs() -> ([u8; 300], [u8; 100]) {
+        (array::from_fn(|i| i as u8), array::from_fn(|i| (i * 7 + 3) as u8))
+    }
+
+    #[test]
+    fn encrypt() {
+        // SP 800-38C 付録C Example 1
+        let c = Ccm::new(AES::new(AESkey::K128(array::from_fn(|i| 0x40 + i as u8))));
+        let mut out = [0; 4];
+        let mut tag = [0; 4];
+        c.encrypt(b"\x10\x11\x12\x13\x14\x15\x16", b"\x00\x01\x02\x03\x04\x05\x06\x07", b"\x20\x21\x22\x23", &mut out, &mut tag).unwrap();
+        assert_eq!((out, tag), (*b"\x71\x62\x01\x5b", *b"\x4d\xac\x25\x5d"));
+
+        // SP 800-38C 付録C Example 4。AADの長さは6バイトで表す
+        let aad: Vec<u8> = (0..65536).map(|i| i as u8).collect();
+        let p: [u8; 32] = array::from_fn(|i| 0x20 + i as u8);
+        let mut out = [0; 32];
+        let mut tag = [0; 14];
+        c.encrypt(&array::from_fn::<u8, 13, _>(|i| 0x10 + i as u8), &aad, &p, &mut out, &mut tag).unwrap();
+        assert_eq!(out, *b"\x69\x91\x5d\xad\x1e\x84\xc6\x37\x6a\x68\xc2\x96\x7e\x4d\xab\x61\x5a\xe0\xfd\x1f\xae\xc4\x4c\xc4\x84\x82\x85\x29\x46\x3c\xcf\x72");
+        assert_eq!(tag, *b"\xb4\xac\x6b\xec\x93\xe8\x59\x8e\x7f\x0d\xad\xbc\xea\x5b");
+
+        // RFC 3610 Packet Vector #1
+        let c = Ccm::new(AES::new(AESkey::K128(*K1)));
+        let mut out = [0; 23];
+        let mut tag = [0; 8];
+        c.encrypt(N1, A1, P1, &mut out, &mut tag).unwrap();
+        assert_eq!(out, C1[..23]);
+        assert_eq!(tag, C1[23..]);
+
+        let (a3, p3) = inputs();
+        let c = Ccm::new(AES::new(AESkey::K256(*K3)));
+        let mut out = [0; 100];
+        let mut tag = [0; 16];
+        c.encrypt(N3, &a3, &p3, &mut out, &mut tag).unwrap();
+        assert_eq!(out, C3[..100]);
+        assert_eq!(tag, C3[100..]);
+    }
+
+    #[test]
+    fn decrypt() {
+        let (a3, p3) = inputs();
+        let c = Ccm::new(AES::new(AESkey::K256(*K3)));
+        let mut out = [0; 100];
+        c.decrypt(N3, &a3, &C3[..100], &C3[100..], &mut out).unwrap();
+        assert_eq!(out, p3);
+
+        let mut tag = *C3;
+        tag[110] ^= 0x80;
+        assert_eq!(c.decrypt(N3, &a3, &C3[..100], &tag[100..], &mut out), Err(AesError::AuthenticationFailed));
+        assert_eq!(out, [0; 100]);
+        assert_eq!(c.decrypt(N3, &a3[1..], &C3[..100], &C3[100..], &mut out), Err(AesError::AuthenticationFailed));
+
+        assert_eq!(c.decrypt(N3, &a3, &C3[..100], &C3[100..105], &mut out), Err(AesError::InvalidTagLength(5)));
+        assert_eq!(c.decrypt(&N3[..6], &a3, &C3[..100], &C3[100..], &mut out), Err(AesError::InvalidIvLength(6)));
+        assert_eq!(c.decrypt(N3, &a3, &C3[..100], &[], &mut out), Err(AesError::InvalidTagLength(0)));
+    }
+
+    #[test]
+    fn star() {
+        let c = Ccm::new_star(AES::new(AESkey::K128(*K1)));
+        let mut out = [0; 23];
+        c.encrypt(N1, A1, P1, &mut out, &mut []).unwrap();
+        assert_eq!(out, C1[..23]);
+        let mut dec = [0; 23];
+        c.decrypt(N1, A1, &out, &[], &mut dec).unwrap();
+        assert_eq!(dec, *P1);
+
+        // タグがある場合はCCMと同じ
+        let mut tag = [0; 8];
+        c.encrypt(N1, A1, P1, &mut out, &mut tag).unwrap();
+        assert_eq!(tag, C1[23..]);
+    }
+}
@@ -0,0 +1,328 @@
+use std::{arch::asm, sync::OnceLock};
+
+use crate::aes_ni::support_pclmulqdq;
+
+// pshufbでバイト順を反転するマスクと、POLYVALの還元に使う定数 (上位64bitだけ使う)
+static CONSTS: [u128; 2] = [0x000102030405060708090a0b0c0d0e0f, 0xc2000000000000000000000000000001];
+
+// GHASHはバイト順を反転してHにxを掛けるとPOLYVALと同じ計算になる (RFC 8452 付録A)
+// H^1..H^8をPOLYVALの表現で持つ
+#[derive(Clone)]
+pub(crate) struct HTable([u128; 8]);
+
+impl HTable {
+    // hはGHASHのHをビッグエンディアンで読んだ値。PCLMULQDQが使えなければNone
+    pub(crate) fn new(h: u128) -> Option<Self> {
+        // cpuidは一度だけ呼ぶ
+        static SUPPORTED: OnceLock<bool> = OnceLock::new();
+        if !*SUPPORTED.get_or_init(support_pclmulqdq) {
+            return None;
+        }
+        let mut h1 = h << 1;
+        if h >> 127 != 0 {
+            h1 ^= 0xc2000000000000000000000000000001;
+        }
+        let mut t = [h1; 8];
+        for i in 1..8 {
+            // H^(i+1) = H・H^i を1ブロック分のGHASHとして計算する
+            let mut y = 0;
+            unsafe { ghash_xn(&mut y, &t[i - 1], h1.to_be_bytes().as_ptr(), 1) };
+            t[i] = y;
+        }
+        Some(Self(t))
+    }
+}
+
+// dataの長さは16の倍数
+pub(crate) fn ghash(y: &mut u128, t: &HTable, data: &[u8]) {
+    debug_assert!(data.len().is_multiple_of(16));
+    for c in data.chunks(16 * 8) {
+        unsafe { ghash_xn(y, t.0.as_ptr(), c.as_ptr(), c.len() / 16) };
+    }
+}
+
+// GCMのCTR暗号化8ブロック分と、別の8ブロックgのGHASHを一緒に処理する
+pub(crate) fn ctr_ghash_x8(round_key: &[u8], ctr: &[[u8; 16]; 8], src: &[u8], dst: &mut [u8], y: &mut u128, t: &HTable, g: &[u8]) {
+    assert!(src.len() == 128 && dst.len() == 128 && g.len() == 128);
+    unsafe {
+        aes_ctr_ghash_x8(
+            round_key.as_ptr(),
+            round_key.len(),
+            ctr.as_ptr().cast(),
+            src.as_ptr(),
+            dst.as_mut_ptr(),
+            y,
+            t.0.as_ptr(),
+            g.as_ptr(),
+        )
+    }
+}
+
+// y = (y ^ X1)・H^n ^ X2・H^(n-1) ^ ... ^ Xn・H
+// 掛け算の結果を足し合わせてから最後に一度だけ還元する
+unsafe fn ghash_xn(y: *mut u128, table: *const u128, data: *const u8, n: usize) {
+    asm!(
+        "movdqu xmm6, [{c}]",
+        "movdqu xmm3, [{y}]",
+        "pxor xmm0, xmm0",
+        "pxor xmm1, xmm1",
+        "pxor xmm2, xmm2",
+
+        "2:",
+        "movdqu xmm4, [{d}]",
+        "pshufb xmm4, xmm6",
+        "pxor xmm3, xmm4",
+        "movdqu xmm5, [{h}]",
+        "movdqa xmm4, xmm3",
+        "pclmulqdq xmm4, xmm5, 0x00",
+        "pxor xmm0, xmm4",
+        "movdqa xmm4, xmm3",
+        "pclmulqdq xmm4, xmm5, 0x11",
+        "pxor xmm1, xmm4",
+        "movdqa xmm4, xmm3",
+        "pclmulqdq xmm4, xmm5, 0x01",
+        "pxor xmm2, xmm4",
+        "pclmulqdq xmm3, xmm5, 0x10",
+        "pxor xmm2, xmm3",
+        "pxor xmm3, xmm3",
+        "add {d}, 0x10",
+        "sub {h}, 0x10",
+        "dec {n}",
+        "jnz 2b",
+
+        // 256bitにまとめてからモンゴメリ還元
+        "movdqa xmm4, xmm2",
+        "pslldq xmm4, 8",
+        "psrldq xmm2, 8",
+        "pxor xmm0, xmm4",
+        "pxor xmm1, xmm2",
+        "movdqu xmm5, [{c}+0x10]",
+        "movdqa xmm4, xmm0",
+        "pclmulqdq xmm4, xmm5, 0x10",
+        "pshufd xmm0, xmm0, 0x4e",
+        "pxor xmm0, xmm4",
+        "movdqa xmm4, xmm0",
+        "pclmulqdq xmm4, xmm5, 0x10",
+        "pshufd xmm0, xmm0, 0x4e",
+        "pxor xmm0, xmm4",
+        "pxor xmm0, xmm1",
+        "movdqu [{y}], xmm0",
+        y = in(reg) y,
+        c = in(reg) CONSTS.as_ptr(),
+        d = inout(reg) data => _,
+        h = inout(reg) table.add(n - 1) => _,
+        n = inout(reg) n => _,
+        out("xmm0") _,
+        out("xmm1") _,
+        out("xmm2") _,
+        out("xmm3") _,
+        out("xmm4") _,
+        out("xmm5") _,
+        out("xmm6") _,
+    )
+}
+
+// AESの各ラウンドの間にGHASHを1ブロックずつ挟む
+// どの鍵長でも中間ラウンドは9回以上あるので、8ブロックはループ内で終わる
+#[allow(clippy::too_many_arguments)]
+unsafe fn aes_ctr_ghash_x8(key: *const u8, key_len: usize, ctr: *const u8, src: *const u8, dst: *mut u8, y: *mut u128, table: *const u128, g: *const u8) {
+    asm!(
+        "movdqu xmm15, [{c}]",
+        "movdqu xmm12, [{y}]",
+        "pxor xmm9, xmm9",
+        "pxor xmm10, xmm10",
+        "pxor xmm11, xmm11",
+        "movdqu xmm8, [{k}]",
+        "movdqu xmm0, [{i}]",
+        "movdqu xmm1, [{i}+0x10]",
+        "movdqu xmm2, [{i}+0x20]",
+        "movdqu xmm3, [{i}+0x30]",
+        "movdqu xmm4, [{i}+0x40]",
+        "movdqu xmm5, [{i}+0x50]",
+        "movdqu xmm6, [{i}+0x60]",
+        "movdqu xmm7, [{i}+0x70]",
+        "pxor xmm0, xmm8",
+        "pxor xmm1, xmm8",
+        "pxor xmm2, xmm8",
+        "pxor xmm3, xmm8",
+        "pxor xmm4, xmm8",
+        "pxor xmm5, xmm8",
+        "pxor xmm6, xmm8",
+        "pxor xmm7, xmm8",
+        "mov {i}, 0x10",
+        "mov {j}, 8",
+
+        "2:",
+        "movdqu xmm8, [{k}+{i}]",
+        "aesenc xmm0, xmm8",
+        "aesenc xmm1, xmm8",
+        "aesenc xmm2, xmm8",
+        "aesenc xmm3, xmm8",
+        "aesenc xmm4, xmm8",
+        "aesenc xmm5, xmm8",
+        "aesenc xmm6, xmm8",
+        "aesenc xmm7, xmm8",
+        "test {j}, {j}",
+        "jz 3f",
+        "movdqu xmm13, [{g}]",
+        "pshufb xmm13, xmm15",
+        "pxor xmm12, xmm13",
+        "movdqu xmm14, [{h}]",
+        "movdqa xmm13, xmm12",
+        "pclmulqdq xmm13, xmm14, 0x00",
+        "pxor xmm9, xmm13",
+        "movdqa xmm13, xmm12",
+        "pclmulqdq xmm13, xmm14, 0x11",
+        "pxor xmm10, xmm13",
+        "movdqa xmm13, xmm12",
+        "pclmulqdq xmm13, xmm14, 0x01",
+        "pxor xmm11, xmm13",
+        "pclmulqdq xmm12, xmm14, 0x10",
+        "pxor xmm11, xmm12",
+        "pxor xmm12, xmm12",
+        "add {g}, 0x10",
+        "sub {h}, 0x10",
+        "dec {j}",
+        "3:",
+        "add {i}, 0x10",
+        "cmp {i}, {l}",
+        "jne 2b",
+
+        "movdqu xmm8, [{k}+{i}]",
+        "aesenclast xmm0, xmm8",
+        "aesenclast xmm1, xmm8",
+        "aesenclast xmm2, xmm8",
+        "aesenclast xmm3, xmm8",
+        "aesenclast xmm4, xmm8",
+        "aesenclast xmm5, xmm8",
+        "aesenclast xmm6, xmm8",
+        "aesenclast xmm7, xmm8",
+        "movdqu xmm8, [{s}]",
+        "pxor xmm0, xmm8",
+        "movdqu xmm8, [{s}+0x10]",
+        "pxor xmm1, xmm8",
+        "movdqu xmm8, [{s}+0x20]",
+        "pxor xmm2, xmm8",
+        "movdqu xmm8, [{s}+0x30]",
+        "pxor xmm3, xmm8",
+        "movdqu xmm8, [{s}+0x40]",
+        "pxor xmm4, xmm8",
+        "movdqu xmm8, [{s}+0x50]",
+        "pxor xmm5, xmm8",
+        "movdqu xmm8, [{s}+0x60]",
+        "pxor xmm6, xmm8",
+        "movdqu xmm8, [{s}+0x70]",
+        "pxor xmm7, xmm8",
+        "movdqu [{o}], xmm0",
+        "movdqu [{o}+0x10], xmm1",
+        "movdqu [{o}+0x20], xmm2",
+        "movdqu [{o}+0x30], xmm3",
+        "movdqu [{o}+0x40], xmm4",
+        "movdqu [{o}+0x50], xmm5",
+        "movdqu [{o}+0x60], xmm6",
+        "movdqu [{o}+0x70], xmm7",
+
+        "movdqa xmm13, xmm11",
+        "pslldq xmm13, 8",
+        "psrldq xmm11, 8",
+        "pxor xmm9, xmm13",
+        "pxor xmm10, xmm11",
+        "movdqu xmm14, [{c}+0x10]",
+        "movdqa xmm13, xmm9",
+        "pclmulqdq xmm13, xmm14, 0x10",
+        "pshufd xmm9, xmm9, 0x4e",
+        "pxor xmm9, xmm13",
+        "movdqa xmm13, xmm9",
+        "pclmulqdq xmm13, xmm14, 0x10",
+        "pshufd xmm9, xmm9, 0x4e",
+        "pxor xmm9, xmm13",
+        "pxor xmm9, xmm10",
+        "movdqu [{y}], xmm9",
+        k = in(reg) key,
+        l = in(reg) key_len - 0x10,
+        i = inout(reg) ctr => _,
+        j = out(reg) _,
+        g = inout(reg) g => _,
+        h = inout(reg) table.add(7) => _,
+        s = in(reg) src,
+        o = in(reg) dst,
+        y = in(reg) y,
+        c = in(reg) CONSTS.as_ptr(),
+        out("xmm0") _,
+        out("xmm1") _,
+        out("xmm2") _,
+        out("xmm3") _,
+        out("xmm4") _,
+        out("xmm5") _,
+        out("xmm6") _,
+        out("xmm7") _,
+        out("xmm8") _,
+        out("xmm9") _,
+        out("xmm10") _,
+        out("xmm11") _,
+        out("xmm12") _,
+        out("xmm13") _,
+        out("xmm14") _,
+        out("xmm15") _,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::array;
+
+    use crate::{aes::{AESkey, AES}, aes_ni::{support_aesni, support_pclmulqdq, AES_NI}, clmul::{ctr_ghash_x8, ghash, HTable}, ghash::gf_mul, mode::BlockCipher};
+
+    fn soft_ghash(mut y: u128, h: u128, data: &[u8]) -> u128 {
+        for c in data.chunks(16) {
+            y = gf_mul(y ^ u128::from_be_bytes(c.try_into().unwrap()), h);
+        }
+        y
+    }
+
+    #[test]
+    fn blocks() {
+        if !support_pclmulqdq() {
+            return;
+        }
+        let h = 0x66e94bd4ef8a2c3b884cfa59ca342b2e;
+        let t = HTable::new(h).unwrap();
+        let data: [u8; 16 * 21] = array::from_fn(|i| (i * 7 + 3) as u8);
+        for n in 0..=21 {
+            let mut y = 0x0123456789abcdef0011223344556677;
+            let ans = soft_ghash(y, h, &data[..16 * n]);
+            ghash(&mut y, &t, &data[..16 * n]);
+            assert_eq!(y, ans);
+        }
+    }
+
+    #[test]
+    fn stitched() {
+        if !support_aesni() || !support_pclmulqdq() {
+            return;
+        }
+        let h = 0x66e94bd4ef8a2c3b884cfa59ca342b2e;
+        let t = HTable::new(h).unwrap();
+        let src: [u8; 128] = array::from_fn(|i| (i * 7 + 3) as u8);
+        let g: [u8; 128] = array::from_fn(|i| (i * 5 + 1) as u8);
+        let ctr: [[u8; 16]; 8] = array::from_fn(|i| array::from_fn(|j| (i * 16 + j) as u8));
+        let keys = [
+            (AES::new(AESkey::K128([1; 16])), AES_NI::new(AESkey::K128([1; 16]))),
+            (AES::new(AESkey::K192([2; 24])), AES_NI::new(AESkey::K192([2; 24]))),
+            (AES::new(AESkey::K256([3; 32])), AES_NI::new(AESkey::K256([3; 32]))),
+        ];
+        for (a, ni) in keys {
+            let round_key = ni._aesni_round_key().unwrap();
+            let mut y = 0x0123456789abcdef0011223344556677;
+            let ans_y = soft_ghash(y, h, &g);
+            let mut dst = [0; 128];
+            ctr_ghash_x8(round_key, &ctr, &src, &mut dst, &mut y, &t, &g);
+            assert_eq!(y, ans_y);
+            for (i, c) in ctr.iter().enumerate() {
+                let e = a._encrypt(*c);
+                let ans: [u8; 16] = array::from_fn(|j| e[j] ^ src[i * 16 + j]);
+                assert_eq!(dst[i * 16..(i + 1) * 16], ans);
+            }
+        }
+    }
+}
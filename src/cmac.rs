@@ -0,0 +1,198 @@
+use crate::{aes::AESkey, auto::{AutoAES, Backend}, error::AesError, mode::{ct_eq, BlockCipher}};
+
+// GF(2^128)での2倍 (x^128 + x^7 + x^2 + x + 1)
+pub(crate) fn dbl(b: [u8; 16]) -> [u8; 16] {
+    let v = u128::from_be_bytes(b);
+    ((v << 1) ^ (0x87 & (v >> 127).wrapping_neg())).to_be_bytes()
+}
+
+// CMAC (NIST SP 800-38B / RFC 4493)
+pub struct Cmac<C: BlockCipher<16>> {
+    cipher: C,
+    k1: [u8; 16],
+    k2: [u8; 16],
+}
+
+impl<C: BlockCipher<16>> Cmac<C> {
+    pub fn new(cipher: C) -> Self {
+        let k1 = dbl(cipher._encrypt([0; 16]));
+        let k2 = dbl(k1);
+        Self { cipher, k1, k2 }
+    }
+
+    pub(crate) fn cipher(&self) -> &C {
+        &self.cipher
+    }
+
+    // サブ鍵K1, K2
+    pub fn subkeys(&self) -> ([u8; 16], [u8; 16]) {
+        (self.k1, self.k2)
+    }
+
+    // 少しずつ入力するときに使う
+    pub fn start(&self) -> CmacState<'_, C> {
+        CmacState { cmac: self, x: [0; 16], buf: [0; 16], len: 0 }
+    }
+
+    pub fn mac(&self, data: &[u8]) -> [u8; 16] {
+        let mut s = self.start();
+        s.update(data);
+        s.finalize()
+    }
+
+    pub fn verify(&self, data: &[u8], tag: &[u8]) -> Result<(), AesError> {
+        let mut s = self.start();
+        s.update(data);
+        s.verify(tag)
+    }
+}
+
+pub struct CmacState<'a, C: BlockCipher<16>> {
+    cmac: &'a Cmac<C>,
+    x: [u8; 16],
+    buf: [u8; 16],
+    len: usize,
+}
+
+impl<C: BlockCipher<16>> CmacState<'_, C> {
+    fn process(&mut self, block: &[u8]) {
+        for (a, b) in self.x.iter_mut().zip(block) {
+            *a ^= b;
+        }
+        self.x = self.cmac.cipher._encrypt(self.x);
+    }
+
+    // 最後のブロックはサブ鍵を混ぜるのでfinalizeまで残しておく
+    pub fn update(&mut self, mut data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        let n = (16 - self.len).min(data.len());
+        self.buf[self.len..self.len + n].copy_from_slice(&data[..n]);
+        self.len += n;
+        data = &data[n..];
+        if data.is_empty() {
+            return;
+        }
+        let buf = self.buf;
+        self.process(&buf);
+        while data.len() > 16 {
+            self.process(&data[..16]);
+            data = &data[16..];
+        }
+        self.buf[..data.len()].copy_from_slice(data);
+        self.len = data.len();
+    }
+
+    pub fn finalize(mut self) -> [u8; 16] {
+        let k = if self.len == 16 {
+            self.cmac.k1
+        } else {
+            self.buf[self.len] = 0x80;
+            self.buf[self.len + 1..].fill(0);
+            self.cmac.k2
+        };
+        for (a, b) in self.buf.iter_mut().zip(k) {
+            *a ^= b;
+        }
+        let buf = self.buf;
+        self.process(&buf);
+        self.x
+    }
+
+    // tagの長さ(1..=16バイト)で切り詰める
+    pub fn finalize_truncated(self, tag: &mut [u8]) -> Result<(), AesError> {
+        if !(1..=16).contains(&tag.len()) {
+            return Err(AesError::InvalidTagLength(tag.len()));
+        }
+        let t = self.finalize();
+        let n = tag.len();
+        tag.copy_from_slice(&t[..n]);
+        Ok(())
+    }
+
+    // 切り詰めたタグも受け付ける
+    pub fn verify(self, tag: &[u8]) -> Result<(), AesError> {
+        if !(1..=16).contains(&tag.len()) {
+            return Err(AesError::InvalidTagLength(tag.len()));
+        }
+        let t = self.finalize();
+        if !ct_eq(&t[..tag.len()], tag) {
+            return Err(AesError::AuthenticationFailed);
+        }
+        Ok(())
+    }
+}
+
+// AES-CMAC-PRF-128 (RFC 4615)。16バイトでない鍵は0鍵のCMACで16バイトにする
+// AES-NIがなければビットスライスで計算する
+pub fn cmac_prf_128(key: &[u8], data: &[u8]) -> [u8; 16] {
+    let backend = Backend::detect_constant_time();
+    let k = match key.try_into() {
+        Ok(k) => k,
+        Err(_) => Cmac::new(AutoAES::with_backend(AESkey::K128([0; 16]), backend)).mac(key),
+    };
+    Cmac::new(AutoAES::with_backend(AESkey::K128(k), backend)).mac(data)
+}
+
+#[cfg(test)]
+mod test {
+    use std::array;
+
+    use crate::{aes::{AESkey, AES}, cmac::{cmac_prf_128, Cmac}, error::AesError};
+
+    const K: &[u8; 16] = b"+~\x15\x16(\xae\xd2\xa6\xab\xf7\x15\x88\t\xcfO<";
+    const M: &[u8; 64] = b"k\xc1\xbe\xe2.@\x9f\x96\xe9=~\x11s\x93\x17*\xae-\x8aW\x1e\x03\xac\x9c\x9e\xb7o\xacE\xaf\x8eQ0\xc8\x1cF\xa3\\\xe4\x11\xe5\xfb\xc1\x19\x1a\nR\xef\xf6\x9f$E\xdfO\x9b\x17\xad+A{\xe6l7\x10";
+
+    // RFC 4493 4節
+    #[test]
+    fn mac() {
+        let c = Cmac::new(AES::new(AESkey::K128(*K)));
+        assert_eq!(c.subkeys(), (
+            *b"\xfb\xee\xd6\x18\x35\x71\x33\x66\x7c\x85\xe0\x8f\x72\x36\xa8\xde",
+            *b"\xf7\xdd\xac\x30\x6a\xe2\x66\xcc\xf9\x0b\xc1\x1e\xe4\x6d\x51\x3b",
+        ));
+        let vectors: [(usize, &[u8; 16]); 4] = [
+            (0, b"\xbb\x1di)\xe9Y7(\x7f\xa3}\x12\x9bugF"),
+            (16, b"\x07\n\x16\xb4kMAD\xf7\x9b\xdd\x9d\xd0J(|"),
+            (40, b"\xdf\xa6gG\xde\x9a\xe600\xca2a\x14\x97\xc8'"),
+            (64, b"Q\xf0\xbe\xbf~;\x9d\x92\xfcIt\x17y6<\xfe"),
+        ];
+        for (n, t) in vectors {
+            assert_eq!(c.mac(&M[..n]), *t);
+            // 分割して入力しても同じ
+            for split in [1, 7, 16, 17, 33] {
+                let mut s = c.start();
+                for part in M[..n].chunks(split) {
+                    s.update(part);
+                }
+                assert_eq!(s.finalize(), *t);
+            }
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        let c = Cmac::new(AES::new(AESkey::K128(*K)));
+        let full = c.mac(&M[..40]);
+        let mut s = c.start();
+        s.update(&M[..40]);
+        let mut tag = [0; 8];
+        s.finalize_truncated(&mut tag).unwrap();
+        assert_eq!(tag, full[..8]);
+        assert_eq!(c.verify(&M[..40], &full), Ok(()));
+        assert_eq!(c.verify(&M[..40], &full[..4]), Ok(()));
+        assert_eq!(c.verify(&M[..39], &full[..4]), Err(AesError::AuthenticationFailed));
+        assert_eq!(c.verify(&M[..40], &[]), Err(AesError::InvalidTagLength(0)));
+    }
+
+    // RFC 4615 4節
+    #[test]
+    fn prf() {
+        let m: [u8; 20] = array::from_fn(|i| i as u8);
+        let k: [u8; 18] = array::from_fn(|i| if i < 16 { i as u8 } else { [0xed, 0xcb][i - 16] });
+        assert_eq!(cmac_prf_128(&k, &m), *b"\x84\xa3H\xa4\xa4]#[\xab\xff\xfc\r+M\xa0\x9a");
+        assert_eq!(cmac_prf_128(&k[..16], &m), *b"\x98\n\xe8{_L\x9cR\x14\xf5\xb6\xa8E^L-");
+        assert_eq!(cmac_prf_128(&k[..10], &m), *b")\r\x9e\x11.\xdb\t\xee\x14\x1f\xcfd\xc0\xb7/=");
+    }
+}
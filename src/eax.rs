@@ -0,0 +1,115 @@
+use std::array;
+
+use crate::{cmac::Cmac, error::AesError, mode::{ct_eq, ctr128, BlockCipher}};
+
+// EAX (Bellare, Rogaway, Wagner)。OMACとCTRを同じ鍵で使う
+pub struct Eax<C: BlockCipher<16>> {
+    mac: Cmac<C>,
+}
+
+impl<C: BlockCipher<16>> Eax<C> {
+    pub fn new(cipher: C) -> Self {
+        Self { mac: Cmac::new(cipher) }
+    }
+
+    // 先頭にtを表すブロックを付けたCMAC
+    fn omac(&self, t: u8, data: &[u8]) -> [u8; 16] {
+        let mut b = [0; 16];
+        b[15] = t;
+        let mut s = self.mac.start();
+        s.update(&b);
+        s.update(data);
+        s.finalize()
+    }
+
+    fn check(&self, in_len: usize, out_len: usize, tag_len: usize) -> Result<(), AesError> {
+        if in_len != out_len {
+            return Err(AesError::LengthMismatch { in_len, out_len });
+        }
+        if !(1..=16).contains(&tag_len) {
+            return Err(AesError::InvalidTagLength(tag_len));
+        }
+        Ok(())
+    }
+
+    fn tag(&self, n: &[u8; 16], header: &[u8], ciphertext: &[u8]) -> [u8; 16] {
+        let h = self.omac(1, header);
+        let c = self.omac(2, ciphertext);
+        array::from_fn(|i| n[i] ^ h[i] ^ c[i])
+    }
+
+    // nonceとheaderの長さは任意。tagの長さ(1..=16バイト)で切り詰めたタグを書き込む
+    pub fn encrypt(&self, nonce: &[u8], header: &[u8], in_bytes: &[u8], out_bytes: &mut [u8], tag: &mut [u8]) -> Result<(), AesError> {
+        self.check(in_bytes.len(), out_bytes.len(), tag.len())?;
+        let n = self.omac(0, nonce);
+        ctr128(self.mac.cipher(), n, in_bytes, out_bytes);
+        let t = self.tag(&n, header, out_bytes);
+        let len = tag.len();
+        tag.copy_from_slice(&t[..len]);
+        Ok(())
+    }
+
+    // タグが一致しなければout_bytesには何も書き込まない
+    pub fn decrypt(&self, nonce: &[u8], header: &[u8], in_bytes: &[u8], tag: &[u8], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self.check(in_bytes.len(), out_bytes.len(), tag.len())?;
+        let n = self.omac(0, nonce);
+        let t = self.tag(&n, header, in_bytes);
+        if !ct_eq(&t[..tag.len()], tag) {
+            return Err(AesError::AuthenticationFailed);
+        }
+        ctr128(self.mac.cipher(), n, in_bytes, out_bytes);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::array;
+
+    use crate::{aes::{AESkey, AES}, eax::Eax, error::AesError};
+
+    // 鍵, nonce, header, 平文, 暗号文とタグ
+    type Vector = (&'static [u8; 16], &'static [u8], &'static [u8], &'static [u8], &'static [u8]);
+
+    // EAXの論文の付録のテストベクタ
+    const VECTORS: [Vector; 5] = [
+        (b"#9R\xde\xe4\xd5\xed_\x9b\x9cmo\xf8\x0f\xf4x", b"b\xecg\xf9\xc3\xa4\xa4\x07\xfc\xb2\xa8\xc4\x901\xa8\xb3", b"k\xfb\x91O\xd0~\xaek", b"", b"\xe07\x83\x0e\x83\x89\xf2{\x02Z-e'\xe7\x9d\x01"),
+        (b"\x91\x94]?M\xcb\xee\x0b\xf4^\xf5\"U\xf0\x95\xa4", b"\xbe\xca\xf0C\xb0\xa2=\x841\x94\xba\x97,f\xde\xbd", b"\xfa;\xfdH\x06\xebS\xfa", b"\xf7\xfb", b"\x19\xdd\\L\x931\x04\x9d\x0b\xda\xb0't\x08\xf6yg\xe5"),
+        (b"\x01\xf7J\xd6@w\xf2\xe7\x04\xc0\xf6\n\xda=\xd5#", b"p\xc3\xdbO\r&6\x84\x00\xa1\x0e\xd0]+\xff^", b"#J4c\xc1&J\xc6", b"\x1aG\xcbI3", b"\xd8Q\xd5\xba\xe0:Y\xf28\xa2>9\x19\x9d\xc9&f&\xc4\x0f\x80"),
+        (b"\xd0|\xf6\xcb\xb7\xf3\x13\xbd\xdef\xb7'\xaf\xd3\xc5\xe8", b"\x84\x08\xdf\xff<\x1a+\x12\x92\xdc\x19\x9eF\xb7\xd6\x17", b"3\xcc\xe2\xea\xbf\xf5\xa7\x9d", b"H\x1c\x9e9\xb1", b"c*\x9d\x13\x1a\xd4\xc1h\xa4\"]\x8e\x1f\xf7U\x93\x99t\xa7\xbe\xde"),
+        (b"5\xb6\xd0X\x00\x05\xbb\xc1+\x05\x87\x12EW\xd2\xc2", b"\xfd\xb6\xb0fv\xee\xdc\\a\xd7Bv\xe1\xf8\xe8\x16", b"\xae\xb9n\xae\xbe)p\xe9", b"@\xd0\xc0}\xa5\xe4", b"\x07\x1d\xfe\x16\xc6u\xcb\x06w\xe56\xf7:\xfej\x14\xb7N\xe4\x98D\xdd"),
+    ];
+
+    #[test]
+    fn encrypt() {
+        for (k, n, h, p, c) in VECTORS {
+            let e = Eax::new(AES::new(AESkey::K128(*k)));
+            let mut out = vec![0; p.len()];
+            let mut tag = [0; 16];
+            e.encrypt(n, h, p, &mut out, &mut tag).unwrap();
+            assert_eq!(out, c[..p.len()]);
+            assert_eq!(tag, c[p.len()..]);
+        }
+
+        // 長いnonceとheader、途中で切り詰めたタグ
+        let e = Eax::new(AES::new(AESkey::K128(array::from_fn(|i| i as u8))));
+        let h: [u8; 100] = array::from_fn(|i| i as u8);
+        let p: [u8; 1000] = array::from_fn(|i| (i * 7 + 3) as u8);
+        let mut out = [0; 1000];
+        let mut tag = [0; 10];
+        e.encrypt(b"nonce-nonce-nonce-", &h, &p, &mut out, &mut tag).unwrap();
+        assert_eq!(tag, b"\xad\xe8W~ax\xda\xfbEi\xb7\xe4%(9\x85"[..10]);
+    }
+
+    #[test]
+    fn decrypt() {
+        for (k, n, h, p, c) in VECTORS {
+            let e = Eax::new(AES::new(AESkey::K128(*k)));
+            let mut out = vec![0; p.len()];
+            e.decrypt(n, h, &c[..p.len()], &c[p.len()..], &mut out).unwrap();
+            assert_eq!(out, p);
+            assert_eq!(e.decrypt(n, &h[1..], &c[..p.len()], &c[p.len()..], &mut out), Err(AesError::AuthenticationFailed));
+            assert_eq!(e.decrypt(n, h, &c[..p.len()], &[], &mut out), Err(AesError::InvalidTagLength(0)));
+        }
+    }
+}
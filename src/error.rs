@@ -0,0 +1,78 @@
+use std::{fmt, io};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AesError {
+    // 入力の長さがブロック長の倍数でない
+    NotBlockAligned { len: usize, block_size: usize },
+    // 入力と出力の長さが違う
+    LengthMismatch { in_len: usize, out_len: usize },
+    // CFBのセグメント長(bit)が不正
+    InvalidSegmentSize(usize),
+    // CTRのnonceが長すぎる
+    InvalidNonceLength { len: usize, max: usize },
+    // CTRのカウンタが一周した
+    CounterOverflow,
+    // パディングが壊れている
+    InvalidPadding,
+    // 出力先が短すぎる
+    BufferTooSmall { needed: usize, len: usize },
+    // 入力が短すぎる
+    TooShort { len: usize, min: usize },
+    // 入力が長すぎる
+    TooLong { len: u64, max: u64 },
+    // IVの長さが不正
+    InvalidIvLength(usize),
+    // このモードでは使えない鍵長 (バイト)
+    InvalidKeyLength(usize),
+    // 認証タグの長さが不正
+    InvalidTagLength(usize),
+    // 認証タグが一致しない
+    AuthenticationFailed,
+    // AADの要素が多すぎる
+    TooManyAssociatedData { count: usize, max: usize },
+}
+
+impl fmt::Display for AesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AesError::NotBlockAligned { len, block_size } => {
+                write!(f, "input length {} is not a multiple of the block size {}", len, block_size)
+            }
+            AesError::LengthMismatch { in_len, out_len } => {
+                write!(f, "input length {} and output length {} differ", in_len, out_len)
+            }
+            AesError::InvalidSegmentSize(n) => write!(f, "invalid CFB segment size {} bits", n),
+            AesError::InvalidNonceLength { len, max } => {
+                write!(f, "nonce length {} exceeds the maximum of {}", len, max)
+            }
+            AesError::CounterOverflow => write!(f, "CTR counter overflow"),
+            AesError::InvalidPadding => write!(f, "invalid padding"),
+            AesError::BufferTooSmall { needed, len } => {
+                write!(f, "output buffer of {} bytes is too small, {} bytes needed", len, needed)
+            }
+            AesError::TooShort { len, min } => {
+                write!(f, "input length {} is shorter than the minimum of {}", len, min)
+            }
+            AesError::TooLong { len, max } => {
+                write!(f, "input length {} exceeds the maximum of {}", len, max)
+            }
+            AesError::InvalidIvLength(n) => write!(f, "invalid IV length {}", n),
+            AesError::InvalidKeyLength(n) => write!(f, "key length {} bytes is not supported", n),
+            AesError::InvalidTagLength(n) => write!(f, "invalid tag length {}", n),
+            AesError::AuthenticationFailed => write!(f, "authentication failed"),
+            AesError::TooManyAssociatedData { count, max } => {
+                write!(f, "{} associated data components exceed the maximum of {}", count, max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AesError {}
+
+// io::Read/Writeのアダプタから返すため
+impl From<AesError> for io::Error {
+    fn from(e: AesError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
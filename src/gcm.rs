@@ -0,0 +1,310 @@
+use std::array;
+
+use crate::{error::AesError, ghash::GHash, mode::{ct_eq, ctr_blocks, BlockCipher}};
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+use crate::clmul;
+
+// 平文は2^39-256bitまで
+pub(crate) const MAX_TEXT_LEN: u64 = (1 << 36) - 32;
+
+// 下位32bitだけを1増やす
+fn inc32(b: [u8; 16]) -> [u8; 16] {
+    add32(b, 1)
+}
+
+// AADと暗号文のbit長のブロック
+fn lengths(a_len: u64, c_len: u64) -> [u8; 16] {
+    (((a_len as u128 * 8) << 64) | (c_len as u128 * 8)).to_be_bytes()
+}
+
+fn add32(mut b: [u8; 16], n: u32) -> [u8; 16] {
+    let c = u32::from_be_bytes(b[12..].try_into().unwrap()).wrapping_add(n);
+    b[12..].copy_from_slice(&c.to_be_bytes());
+    b
+}
+
+pub struct Gcm<C: BlockCipher<16>> {
+    cipher: C,
+    ghash: GHash,
+}
+
+impl<C: BlockCipher<16>> Gcm<C> {
+    pub fn new(cipher: C) -> Self {
+        let ghash = GHash::new(cipher._encrypt([0; 16]));
+        Self { cipher, ghash }
+    }
+
+    fn j0(&self, iv: &[u8]) -> Result<[u8; 16], AesError> {
+        if iv.is_empty() {
+            return Err(AesError::InvalidIvLength(0));
+        }
+        if iv.len() == 12 {
+            return Ok(array::from_fn(|i| if i < 12 { iv[i] } else if i == 15 { 1 } else { 0 }));
+        }
+        let mut g = self.ghash.clone();
+        g.update_padded(iv);
+        g.update(&lengths(0, iv.len() as u64));
+        Ok(g.finalize())
+    }
+
+    fn check(&self, iv: &[u8], in_len: usize, out_len: usize, tag_len: usize) -> Result<[u8; 16], AesError> {
+        if in_len != out_len {
+            return Err(AesError::LengthMismatch { in_len, out_len });
+        }
+        if in_len as u64 > MAX_TEXT_LEN {
+            return Err(AesError::TooLong { len: in_len as u64, max: MAX_TEXT_LEN });
+        }
+        if !matches!(tag_len, 4 | 8 | 12..=16) {
+            return Err(AesError::InvalidTagLength(tag_len));
+        }
+        self.j0(iv)
+    }
+
+    fn gctr(&self, icb: [u8; 16], in_bytes: &[u8], out_bytes: &mut [u8]) {
+        let mut cb = icb;
+        let _ = ctr_blocks(&self.cipher, in_bytes, out_bytes, || {
+            let v = cb;
+            cb = inc32(cb);
+            Ok(v)
+        });
+    }
+
+    // AES-NIとPCLMULQDQが使えるときは128バイトずつCTRとGHASHをまとめて処理し、処理した長さを返す
+    // 暗号化では一つ前の128バイトの暗号文をGHASHにかける
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn gctr_ghash_stitched(&self, icb: [u8; 16], in_bytes: &[u8], out_bytes: &mut [u8], g: &mut GHash, is_encrypt: bool) -> usize {
+        let (Some(key), Some(t)) = (self.cipher._aesni_round_key(), &g.table) else {
+            return 0;
+        };
+        let n = in_bytes.len() / 128;
+        let mut cb = icb;
+        let mut ctr = [[0; 16]; 8];
+        for i in 0..n {
+            for v in ctr.iter_mut() {
+                *v = cb;
+                cb = inc32(cb);
+            }
+            let src = &in_bytes[i * 128..(i + 1) * 128];
+            let (prev, cur) = out_bytes.split_at_mut(i * 128);
+            let dst = &mut cur[..128];
+            if !is_encrypt {
+                clmul::ctr_ghash_x8(key, &ctr, src, dst, &mut g.y, t, src);
+            } else if i == 0 {
+                self.cipher._encrypt_blocks(&mut ctr);
+                for (j, (x, y)) in src.iter().zip(dst.iter_mut()).enumerate() {
+                    *y = *x ^ ctr[j / 16][j % 16];
+                }
+            } else {
+                clmul::ctr_ghash_x8(key, &ctr, src, dst, &mut g.y, t, &prev[(i - 1) * 128..]);
+            }
+        }
+        if is_encrypt && n > 0 {
+            g.update_blocks(&out_bytes[(n - 1) * 128..n * 128]);
+        }
+        n * 128
+    }
+
+    // GCTRで処理しながら暗号文をGHASHにかける
+    fn gctr_ghash(&self, icb: [u8; 16], in_bytes: &[u8], out_bytes: &mut [u8], g: &mut GHash, is_encrypt: bool) {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        let done = self.gctr_ghash_stitched(icb, in_bytes, out_bytes, g, is_encrypt);
+        #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+        let done = 0;
+        let (ib, ob) = (&in_bytes[done..], &mut out_bytes[done..]);
+        self.gctr(add32(icb, (done / 16) as u32), ib, ob);
+        g.update_padded(if is_encrypt { ob } else { ib });
+    }
+
+    // 暗号文まで渡したgに長さのブロックを足してタグを作る
+    fn tag(&self, j0: [u8; 16], mut g: GHash, aad_len: usize, c_len: u64) -> [u8; 16] {
+        g.update_padded(&[]);
+        g.update(&lengths(aad_len as u64, c_len));
+        let s = g.finalize();
+        let e = self.cipher._encrypt(j0);
+        array::from_fn(|i| s[i] ^ e[i])
+    }
+
+    // stream::GcmEncryptor/GcmDecryptor用。J0とAADまで処理したGHASHを返す
+    pub(crate) fn start(&self, iv: &[u8], aad: &[u8]) -> Result<([u8; 16], GHash), AesError> {
+        let j0 = self.j0(iv)?;
+        let mut g = self.ghash.clone();
+        g.update_padded(aad);
+        Ok((j0, g))
+    }
+
+    pub(crate) fn finish(&self, j0: [u8; 16], g: GHash, aad_len: usize, c_len: u64, tag_len: usize) -> Result<[u8; 16], AesError> {
+        if !matches!(tag_len, 4 | 8 | 12..=16) {
+            return Err(AesError::InvalidTagLength(tag_len));
+        }
+        Ok(self.tag(j0, g, aad_len, c_len))
+    }
+
+    pub(crate) fn cipher(&self) -> &C {
+        &self.cipher
+    }
+
+    // tagの長さ(4, 8, 12..=16バイト)で切り詰めたタグを書き込む
+    pub fn encrypt(&self, iv: &[u8], aad: &[u8], in_bytes: &[u8], out_bytes: &mut [u8], tag: &mut [u8]) -> Result<(), AesError> {
+        let j0 = self.check(iv, in_bytes.len(), out_bytes.len(), tag.len())?;
+        let mut g = self.ghash.clone();
+        g.update_padded(aad);
+        self.gctr_ghash(inc32(j0), in_bytes, out_bytes, &mut g, true);
+        let t = self.tag(j0, g, aad.len(), in_bytes.len() as u64);
+        let n = tag.len();
+        tag.copy_from_slice(&t[..n]);
+        Ok(())
+    }
+
+    // タグが一致しなければout_bytesは0で埋める
+    pub fn decrypt(&self, iv: &[u8], aad: &[u8], in_bytes: &[u8], tag: &[u8], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        let j0 = self.check(iv, in_bytes.len(), out_bytes.len(), tag.len())?;
+        let mut g = self.ghash.clone();
+        g.update_padded(aad);
+        self.gctr_ghash(inc32(j0), in_bytes, out_bytes, &mut g, false);
+        let t = self.tag(j0, g, aad.len(), in_bytes.len() as u64);
+        if !ct_eq(&t[..tag.len()], tag) {
+            out_bytes.fill(0);
+            return Err(AesError::AuthenticationFailed);
+        }
+        Ok(())
+    }
+}
+
+// GMAC: 平文のないGCMでAADだけを認証する
+pub struct Gmac<C: BlockCipher<16>>(Gcm<C>);
+
+impl<C: BlockCipher<16>> Gmac<C> {
+    pub fn new(cipher: C) -> Self {
+        Self(Gcm::new(cipher))
+    }
+
+    pub fn mac(&self, iv: &[u8], aad: &[u8], tag: &mut [u8]) -> Result<(), AesError> {
+        self.0.encrypt(iv, aad, &[], &mut [], tag)
+    }
+
+    pub fn verify(&self, iv: &[u8], aad: &[u8], tag: &[u8]) -> Result<(), AesError> {
+        self.0.decrypt(iv, aad, &[], tag, &mut [])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{aes::{AESkey, AES}, error::AesError, gcm::{Gcm, Gmac}};
+
+    const T1: &[u8; 16] = b"X\xe2\xfc\xce\xfa~0a6\x7f\x1dW\xa4\xe7EZ";
+    const C2: &[u8; 16] = b"\x03\x88\xda\xce`\xb6\xa3\x92\xf3(\xc2\xb9q\xb2\xfex";
+    const T2: &[u8; 16] = b"\xabnG\xd4,\xec\x13\xbd\xf5:g\xb2\x12W\xbd\xdf";
+    const K: &[u8; 16] = b"\xfe\xff\xe9\x92\x86es\x1cmj\x8f\x94g0\x83\x08";
+    const P: &[u8; 60] = b"\xd912%\xf8\x84\x06\xe5\xa5Y\t\xc5\xaf\xf5&\x9a\x86\xa7\xa9S\x154\xf7\xda.L0=\x8a1\x8ar\x1c<\x0c\x95\x95h\tS/\xcf\x0e$I\xa6\xb5%\xb1j\xed\xf5\xaa\r\xe6W\xbac{9";
+    const A: &[u8; 20] = b"\xfe\xed\xfa\xce\xde\xad\xbe\xef\xfe\xed\xfa\xce\xde\xad\xbe\xef\xab\xad\xda\xd2";
+    const IV4: &[u8; 12] = b"\xca\xfe\xba\xbe\xfa\xce\xdb\xad\xde\xca\xf8\x88";
+    const IV5: &[u8; 8] = b"\xca\xfe\xba\xbe\xfa\xce\xdb\xad";
+    const IV6: &[u8; 60] = b"\x93\x13\"]\xf8\x84\x06\xe5\xa5Y\t\xc5\xaf\xf5&\x9ajz\x958SO}\xa1\xe4\xc3\x03\xd2\xa3\x18\xa7(\xc3\xc0\xc9QV\x80\x959\xfc\xf0\xe2B\x9akRT\x16\xae\xdb\xf5\xa0\xdejW\xa67\xb3\x9b";
+    const C4: &[u8; 60] = b"B\x83\x1e\xc2!wt$Kr!\xb7\x84\xd0\xd4\x9c\xe3\xaa!/,\x02\xa4\xe05\xc1~#)\xac\xa1.!\xd5\x14\xb2Tf\x93\x1c}\x8fjZ\xac\x84\xaa\x05\x1b\xa3\x0b9j\n\xac\x97=X\xe0\x91";
+    const T4: &[u8; 16] = b"[\xc9O\xbc2!\xa5\xdb\x94\xfa\xe9Z\xe7\x12\x1aG";
+    const C5: &[u8; 60] = b"a5;L(\x06\x93Jw\x7f\xf5\x1f\xa2*GUi\x9b*qO\xcd\xc6\xf87f\xe5\xf9{lt#s\x80i\x00\xe4\x9f$\xb2+\tuD\xd4\x89kBI\x89\xb5\xe1\xeb\xac\x0f\x07\xc2?E\x98";
+    const T5: &[u8; 16] = b"6\x12\xd2\xe7\x9e;\x07\x85V\x1b\xe1J\xac\xa2\xfc\xcb";
+    const C6: &[u8; 60] = b"3~\xbc>\xe1Q\x04w\xee\x9c\x14cvr/\x10P\r\xc2\xbd\x9f\xa0]\xad\xf5B\x8bIe\x08\x05\x01\xe1\xe1\xa0\x9d\x12\x91i\x0f\x87\x18I\xf7:TB\x88\xc1H\x84s(J\xcf\x87\xe54\xf8\x06";
+    const T6: &[u8; 16] = b"\xcf(\xabd\x84\xe1\xd3\xbc\x88k0\x17V\x83~R";
+    const K256: &[u8; 32] = b"\xfe\xff\xe9\x92\x86es\x1cmj\x8f\x94g0\x83\x08\xfe\xff\xe9\x92\x86es\x1cmj\x8f\x94g0\x83\x08";
+    const C16: &[u8; 60] = b"R-\xc1\xf0\x99V}\x07\xf4\x7f7\xa3*\x84B}d:\x8c\xdc\xbf\xe5\xc0\xc9u\x98\xa2\xbd%U\xd1\xaa\x8c\xb0\x8eHY\r\xbb=\xa7\xb0\x8b\x10V\x82\x888\xc5\xf6\x1ec\x93\xbaz\n\xbc\xc9\xf6b";
+    const T16: &[u8; 16] = b"v\xfcn\xce\x0fN\x17h\xcd\xdf\x88S\xbb-U\x1b";
+
+    // The Galois/Counter Mode of Operation (McGrew, Viega) のテストケース
+    #[test]
+    fn encrypt() {
+        let g = Gcm::new(AES::new(AESkey::K128([0; 16])));
+        let mut tag = [0; 16];
+        g.encrypt(&[0; 12], &[], &[], &mut [], &mut tag).unwrap();
+        assert_eq!(tag, *T1);
+        let mut out = [0; 16];
+        g.encrypt(&[0; 12], &[], &[0; 16], &mut out, &mut tag).unwrap();
+        assert_eq!((out, tag), (*C2, *T2));
+
+        let g = Gcm::new(AES::new(AESkey::K128(*K)));
+        let mut out = [0; 60];
+        g.encrypt(IV4, A, P, &mut out, &mut tag).unwrap();
+        assert_eq!((out, tag), (*C4, *T4));
+        g.encrypt(IV5, A, P, &mut out, &mut tag).unwrap();
+        assert_eq!((out, tag), (*C5, *T5));
+        g.encrypt(IV6, A, P, &mut out, &mut tag).unwrap();
+        assert_eq!((out, tag), (*C6, *T6));
+
+        let g = Gcm::new(AES::new(AESkey::K256(*K256)));
+        g.encrypt(IV4, A, P, &mut out, &mut tag).unwrap();
+        assert_eq!((out, tag), (*C16, *T16));
+
+        let mut short = [0; 12];
+        g.encrypt(IV4, A, P, &mut out, &mut short).unwrap();
+        assert_eq!(short, T16[..12]);
+    }
+
+    // PCLMULQDQとAES-NIを使う経路がソフトウェアのGHASHと一致するか
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[test]
+    fn stitched() {
+        use std::array;
+
+        use crate::{aes_ni::{support_aesni, support_pclmulqdq, AES_NI}, ghash::GHash, mode::BlockCipher};
+
+        if !support_aesni() || !support_pclmulqdq() {
+            return;
+        }
+        let soft = {
+            let cipher = AES::new(AESkey::K128(*K));
+            let mut ghash = GHash::new(cipher._encrypt([0; 16]));
+            ghash.table = None;
+            Gcm { cipher, ghash }
+        };
+        let ni = Gcm::new(AES_NI::new(AESkey::K128(*K)));
+        assert!(ni.ghash.table.is_some());
+        let data: [u8; 16 * 50 + 7] = array::from_fn(|i| (i * 7 + 3) as u8);
+        for len in [0, 1, 16, 127, 128, 129, 255, 256, 300, 512, 16 * 50 + 7] {
+            for aad in [&A[..], &data[..130]] {
+                let mut ans = vec![0; len];
+                let mut out = vec![0; len];
+                let (mut t1, mut t2) = ([0; 16], [0; 16]);
+                soft.encrypt(IV4, aad, &data[..len], &mut ans, &mut t1).unwrap();
+                ni.encrypt(IV4, aad, &data[..len], &mut out, &mut t2).unwrap();
+                assert_eq!((&ans, t1), (&out, t2));
+                ni.encrypt(IV6, aad, &data[..len], &mut out, &mut t2).unwrap();
+                soft.decrypt(IV6, aad, &out, &t2, &mut ans).unwrap();
+                assert_eq!(ans, data[..len]);
+                ni.decrypt(IV6, aad, &out, &t2, &mut ans).unwrap();
+                assert_eq!(ans, data[..len]);
+            }
+        }
+    }
+
+    #[test]
+    fn decrypt() {
+        let g = Gcm::new(AES::new(AESkey::K128(*K)));
+        let mut out = [0; 60];
+        g.decrypt(IV4, A, C4, T4, &mut out).unwrap();
+        assert_eq!(out, *P);
+        g.decrypt(IV6, A, C6, &T6[..8], &mut out).unwrap();
+        assert_eq!(out, *P);
+
+        let mut out = [0; 60];
+        let mut c = *C4;
+        c[7] ^= 1;
+        assert_eq!(g.decrypt(IV4, A, &c, T4, &mut out), Err(AesError::AuthenticationFailed));
+        assert_eq!(g.decrypt(IV4, &A[1..], C4, T4, &mut out), Err(AesError::AuthenticationFailed));
+        assert_eq!(out, [0; 60]);
+        assert_eq!(g.decrypt(IV4, A, C4, &T4[..10], &mut out), Err(AesError::InvalidTagLength(10)));
+        assert_eq!(g.decrypt(&[], A, C4, T4, &mut out), Err(AesError::InvalidIvLength(0)));
+    }
+
+    // NIST CAVS gcmEncryptExtIV128 (PTlen = 0, AADlen = 128)
+    #[test]
+    fn gmac() {
+        let g = Gmac::new(AES::new(AESkey::K128(*b"w\xbecp\x89q\xc4\xe2@\xd1\xcby\xe8\xd7\x7f\xeb")));
+        let iv = b"\xe0\xe0\x0f\x19\xfe\xd7\xba\x016\xa7\x97\xf3";
+        let aad = b"zC\xec\x1d\x9c\nZx\xa0\xb1e3\xa6!<\xab";
+        let mut tag = [0; 16];
+        g.mac(iv, aad, &mut tag).unwrap();
+        assert_eq!(tag, *b" \x9f\xcc\x8d6u\xed\x93\x8e\x9cqfp\x9d\xd9F");
+        assert_eq!(g.verify(iv, aad, &tag), Ok(()));
+        assert_eq!(g.verify(iv, &aad[1..], &tag), Err(AesError::AuthenticationFailed));
+    }
+}
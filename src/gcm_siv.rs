@@ -0,0 +1,173 @@
+use std::array;
+
+use crate::{aes::AESkey, auto::{AutoAES, Backend}, error::AesError, ghash::Polyval, mode::{ct_eq, ctr_blocks, BlockCipher}};
+
+// 平文とAADは2^36バイトまで
+const MAX_LEN: u64 = 1 << 36;
+
+// AES-GCM-SIV (RFC 8452)。nonceごとに認証用と暗号化用の鍵を導出する
+pub struct GcmSiv {
+    key_gen: AutoAES,
+    is_256: bool,
+}
+
+impl GcmSiv {
+    // 192bitの鍵は使えない。AES-NIがなければビットスライスで計算する
+    pub fn new(key: AESkey) -> Result<Self, AesError> {
+        Self::with_backend(key, Backend::detect_constant_time())
+    }
+
+    // 導出した鍵もbackendで計算する
+    pub fn with_backend(key: AESkey, backend: Backend) -> Result<Self, AesError> {
+        let is_256 = match &key {
+            AESkey::K128(_) => false,
+            AESkey::K192(_) => return Err(AesError::InvalidKeyLength(24)),
+            AESkey::K256(_) => true,
+        };
+        Ok(Self { key_gen: AutoAES::with_backend(key, backend), is_256 })
+    }
+
+    // 各ブロックを暗号化した先頭8バイトをつなげて鍵にする
+    fn derive_keys(&self, nonce: &[u8; 12]) -> (Polyval, AutoAES) {
+        let mut b: [[u8; 16]; 6] = array::from_fn(|i| {
+            let mut v = [0; 16];
+            v[..4].copy_from_slice(&(i as u32).to_le_bytes());
+            v[4..].copy_from_slice(nonce);
+            v
+        });
+        let n = if self.is_256 { 6 } else { 4 };
+        self.key_gen._encrypt_blocks(&mut b[..n]);
+        let k: [u8; 48] = array::from_fn(|i| b[i / 8][i % 8]);
+        let auth = Polyval::new(k[..16].try_into().unwrap());
+        let enc = if self.is_256 {
+            AESkey::K256(k[16..48].try_into().unwrap())
+        } else {
+            AESkey::K128(k[16..32].try_into().unwrap())
+        };
+        // 検出済みのバックエンドをそのまま使う
+        (auth, AutoAES::with_backend(enc, self.key_gen.backend()))
+    }
+
+    fn check(&self, aad_len: usize, in_len: usize, out_len: usize) -> Result<(), AesError> {
+        if in_len != out_len {
+            return Err(AesError::LengthMismatch { in_len, out_len });
+        }
+        for len in [aad_len, in_len] {
+            if len as u64 > MAX_LEN {
+                return Err(AesError::TooLong { len: len as u64, max: MAX_LEN });
+            }
+        }
+        Ok(())
+    }
+
+    fn tag(nonce: &[u8; 12], mut auth: Polyval, enc: &AutoAES, aad: &[u8], plaintext: &[u8]) -> [u8; 16] {
+        auth.update_padded(aad);
+        auth.update_padded(plaintext);
+        let mut l = [0; 16];
+        l[..8].copy_from_slice(&(aad.len() as u64 * 8).to_le_bytes());
+        l[8..].copy_from_slice(&(plaintext.len() as u64 * 8).to_le_bytes());
+        auth.update_padded(&l);
+        let mut s = auth.finalize();
+        for (a, b) in s.iter_mut().zip(nonce) {
+            *a ^= b;
+        }
+        s[15] &= 0x7f;
+        enc._encrypt(s)
+    }
+
+    // カウンタは先頭32bitをリトルエンディアンで増やす
+    fn ctr(enc: &AutoAES, tag: &[u8; 16], in_bytes: &[u8], out_bytes: &mut [u8]) {
+        let mut cb = *tag;
+        cb[15] |= 0x80;
+        let _ = ctr_blocks(enc, in_bytes, out_bytes, || {
+            let v = cb;
+            let c = u32::from_le_bytes(cb[..4].try_into().unwrap()).wrapping_add(1);
+            cb[..4].copy_from_slice(&c.to_le_bytes());
+            Ok(v)
+        });
+    }
+
+    pub fn encrypt(&self, nonce: &[u8; 12], aad: &[u8], in_bytes: &[u8], out_bytes: &mut [u8], tag: &mut [u8; 16]) -> Result<(), AesError> {
+        self.check(aad.len(), in_bytes.len(), out_bytes.len())?;
+        let (auth, enc) = self.derive_keys(nonce);
+        *tag = GcmSiv::tag(nonce, auth, &enc, aad, in_bytes);
+        GcmSiv::ctr(&enc, tag, in_bytes, out_bytes);
+        Ok(())
+    }
+
+    // タグが一致しなければout_bytesは0で埋める
+    pub fn decrypt(&self, nonce: &[u8; 12], aad: &[u8], in_bytes: &[u8], tag: &[u8; 16], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self.check(aad.len(), in_bytes.len(), out_bytes.len())?;
+        let (auth, enc) = self.derive_keys(nonce);
+        GcmSiv::ctr(&enc, tag, in_bytes, out_bytes);
+        let t = GcmSiv::tag(nonce, auth, &enc, aad, out_bytes);
+        if !ct_eq(&t, tag) {
+            out_bytes.fill(0);
+            return Err(AesError::AuthenticationFailed);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{aes::AESkey, auto::Backend, error::AesError, gcm_siv::GcmSiv};
+
+    // 鍵, nonce, 平文, AAD, 暗号文とタグ
+    type Vector = (&'static [u8], &'static [u8; 12], &'static [u8], &'static [u8], &'static [u8]);
+
+    // RFC 8452 付録C
+    const VECTORS: [Vector; 8] = [
+        (b"\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00", b"\x03\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00", b"", b"", b"\xdc \xe2\xd8?%p[\xb4\x9eC\x9e\xcaV\xde%"),
+        (b"\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00", b"\x03\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00", b"\x01\x00\x00\x00\x00\x00\x00\x00", b"", b"\xb5\xd893\n\xc7\xb7\x86W\x87\x82\xff\xf6\x01;\x81[(|\"I:6L"),
+        (b"\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00", b"\x03\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00", b"\x02\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00", b"\x01", b")lx\x89\xfd\x99\xf4\x19\x17\xf4F \x08)\x9cQ\x02tZ\xaa:\x0cF\x9f\xad\x9e\x07Z"),
+        (b"\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00", b"\x03\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00", b"\x01\x00\x00\x00\x00\x00\x00\x00", b"", b"\xc2\xef2\x8e\\q\xc8;\x841\"\x13\x0fsd\xb7a\xe0\xb9t'\xe3\xdf("),
+        (b"\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00", b"\x03\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00", b"\x03\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x04\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x05\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x06\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00", b"\x01", b"\x82\xe6\xef\x8f\xe6\"\x94o\x8do%\x80<N\x1aHp\xd4\xca\xae1Z\x8f\xf0\xde\x93w\xadC\x80\x1c\xd8\xae\x9a\x92\xb9S\xf4\xe4\xdcPa\n\xb6\xa3|S\"?q:\xdd\xa8V\x10\xb2\xdb\xd4\xe1M\xcfkHbe\xc4r\x97\xf4L\x87w\xa1\x18\xe0\n\xa6N\xda\xfc"),
+        (b"\xe6`!\xd5\xeb\x8eO@f\xd4\xad\xb9\xc35`\xe4", b"\xf4nD\xbb=\xa0\x01\\\x94\xf7\x08\x87", b"", b"", b"\xa4\x19Ky\x07\x1b\x01\xa8}e\xf7\x06\xe3\x94\x95x"),
+        (b"\xee\x8e\x1e\xd9\xff%@\xae\x8f+\xa9\xf5\x0b\xc2\xf2|", b"u*\xba\xd3\xe0\xaf\xb5\xf44\xdcC\x10", b"Hello world", b"example", b"]4\x9e\xad\x17^\xf6\xb1\xde\xf6\xfdO\xbc\xde\xb7\xe4y?J\x1d~O\xaap\x10\n\xf1"),
+        // カウンタが一周する場合
+        (&[0; 32], &[0; 12], b"\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00M\xb9#\xdcy>\xe6I|v\xdc\xc0:\x98\xe1\x08", b"", b"\xf3\xf8\x0f,\xf0\xcb-\xd9\xc5\x98O\xcd\xa9\x08El\xc57p;[\xa7\x03$\xa6y:{\xf2\x18\xd3\xea\xff\xff\xff\xff\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00"),
+    ];
+
+    fn key(k: &[u8]) -> AESkey {
+        match k.len() {
+            16 => AESkey::K128(k.try_into().unwrap()),
+            _ => AESkey::K256(k.try_into().unwrap()),
+        }
+    }
+
+    #[test]
+    fn encrypt() {
+        for (k, nonce, p, a, c) in VECTORS {
+            for s in [GcmSiv::new(key(k)).unwrap(), GcmSiv::with_backend(key(k), Backend::Bitslice).unwrap()] {
+                let mut out = vec![0; p.len()];
+                let mut tag = [0; 16];
+                s.encrypt(nonce, a, p, &mut out, &mut tag).unwrap();
+                assert_eq!(out, c[..p.len()]);
+                assert_eq!(tag, c[p.len()..]);
+            }
+        }
+    }
+
+    #[test]
+    fn decrypt() {
+        for (k, nonce, p, a, c) in VECTORS {
+            let s = GcmSiv::new(key(k)).unwrap();
+            let (ct, tag) = c.split_at(p.len());
+            let tag: &[u8; 16] = tag.try_into().unwrap();
+            let mut out = vec![0; p.len()];
+            s.decrypt(nonce, a, ct, tag, &mut out).unwrap();
+            assert_eq!(out, p);
+
+            let mut bad = *tag;
+            bad[0] ^= 1;
+            assert_eq!(s.decrypt(nonce, a, ct, &bad, &mut out), Err(AesError::AuthenticationFailed));
+            assert!(out.iter().all(|&x| x == 0));
+        }
+    }
+
+    #[test]
+    fn key_192() {
+        assert_eq!(GcmSiv::new(AESkey::K192([0; 24])).err(), Some(AesError::InvalidKeyLength(24)));
+    }
+}
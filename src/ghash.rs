@@ -0,0 +1,206 @@
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+use crate::clmul::{self, HTable};
+
+const R: u128 = 0xe1 << 120;
+
+// GF(2^128)上での掛け算 (GCMのビット順序、秘密に依存する分岐をしない)
+pub(crate) fn gf_mul(x: u128, y: u128) -> u128 {
+    let mut z = 0;
+    let mut v = y;
+    for i in (0..128).rev() {
+        z ^= v & ((x >> i) & 1).wrapping_neg();
+        v = (v >> 1) ^ (R & (v & 1).wrapping_neg());
+    }
+    z
+}
+
+// GHASH (NIST SP 800-38D)。PCLMULQDQが使えればそちらで計算する
+#[derive(Clone)]
+pub struct GHash {
+    h: u128,
+    pub(crate) y: u128,
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    pub(crate) table: Option<HTable>,
+    // POLYVALとして使うときは各ブロックのバイト順を反転する
+    reversed: bool,
+    buf: [u8; 16],
+    len: usize,
+}
+
+impl GHash {
+    pub fn new(h: [u8; 16]) -> Self {
+        let h = u128::from_be_bytes(h);
+        Self {
+            h,
+            y: 0,
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            table: HTable::new(h),
+            reversed: false,
+            buf: [0; 16],
+            len: 0,
+        }
+    }
+
+    fn hash_blocks(&mut self, data: &[u8]) {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        if let Some(t) = &self.table {
+            clmul::ghash(&mut self.y, t, data);
+            return;
+        }
+        for c in data.chunks_exact(16) {
+            self.y = gf_mul(self.y ^ u128::from_be_bytes(c.try_into().unwrap()), self.h);
+        }
+    }
+
+    // dataの長さは16の倍数で、端数を持っていないときだけ呼べる
+    pub(crate) fn update_blocks(&mut self, data: &[u8]) {
+        debug_assert!(self.len == 0 && data.len().is_multiple_of(16));
+        if !self.reversed {
+            self.hash_blocks(data);
+            return;
+        }
+        let mut buf = [0; 16 * 8];
+        for c in data.chunks(16 * 8) {
+            let b = &mut buf[..c.len()];
+            b.copy_from_slice(c);
+            for x in b.chunks_exact_mut(16) {
+                x.reverse();
+            }
+            self.hash_blocks(b);
+        }
+    }
+
+    // 16バイトに満たない端数は次の入力とつなげる
+    pub fn update(&mut self, mut data: &[u8]) {
+        if self.len > 0 {
+            let n = (16 - self.len).min(data.len());
+            self.buf[self.len..self.len + n].copy_from_slice(&data[..n]);
+            self.len += n;
+            data = &data[n..];
+            if self.len < 16 {
+                return;
+            }
+            let b = self.buf;
+            self.len = 0;
+            self.update_blocks(&b);
+        }
+        let full = data.len() / 16 * 16;
+        self.update_blocks(&data[..full]);
+        self.buf[..data.len() - full].copy_from_slice(&data[full..]);
+        self.len = data.len() - full;
+    }
+
+    // 入力の後ろを0で埋めて16バイトの倍数にする
+    pub fn update_padded(&mut self, data: &[u8]) {
+        self.update(data);
+        if self.len > 0 {
+            self.buf[self.len..].fill(0);
+            let b = self.buf;
+            self.len = 0;
+            self.update_blocks(&b);
+        }
+    }
+
+    // 端数は0で埋める
+    pub fn finalize(mut self) -> [u8; 16] {
+        self.update_padded(&[]);
+        self.y.to_be_bytes()
+    }
+}
+
+// POLYVAL (RFC 8452)。バイト順を反転してGHASHで計算する
+#[derive(Clone)]
+pub struct Polyval(GHash);
+
+impl Polyval {
+    pub fn new(h: [u8; 16]) -> Self {
+        // mulX_GHASH(ByteReverse(H))
+        let h = u128::from_le_bytes(h);
+        let h = (h >> 1) ^ (R & (h & 1).wrapping_neg());
+        let mut g = GHash::new(h.to_be_bytes());
+        g.reversed = true;
+        Self(g)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    pub fn update_padded(&mut self, data: &[u8]) {
+        self.0.update_padded(data);
+    }
+
+    pub fn finalize(self) -> [u8; 16] {
+        let mut s = self.0.finalize();
+        s.reverse();
+        s
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::array;
+
+    use crate::ghash::{GHash, Polyval};
+
+    const H: &[u8; 16] = b"\x66\xe9\x4b\xd4\xef\x8a\x2c\x3b\x88\x4c\xfa\x59\xca\x34\x2b\x2e";
+
+    // GCMの仕様書 Test Case 2 の暗号文と長さのブロック
+    #[test]
+    fn ghash() {
+        let mut g = GHash::new(*H);
+        g.update(b"\x03\x88\xda\xce\x60\xb6\xa3\x92\xf3\x28\xc2\xb9\x71\xb2\xfe\x78");
+        g.update(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x80]);
+        assert_eq!(g.finalize(), *b"\xf3\x8c\xbb\x1a\xd6\x92\x23\xdc\xc3\x45\x7a\xe5\xb6\xb0\xf8\x85");
+    }
+
+    #[test]
+    fn streaming() {
+        let data: [u8; 300] = array::from_fn(|i| (i * 7 + 3) as u8);
+        let ans = *b"h\x87\x19\xca\x00\x08\xbe\x0fi\xc2\xe2s\xd3\xd2Iw";
+        let mut g = GHash::new(*H);
+        g.update(&data);
+        assert_eq!(g.finalize(), ans);
+        // PCLMULQDQを使わない場合
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            let mut g = GHash::new(*H);
+            g.table = None;
+            g.update(&data);
+            assert_eq!(g.finalize(), ans);
+        }
+        for split in [1, 5, 16, 33, 129] {
+            let mut g = GHash::new(*H);
+            for c in data.chunks(split) {
+                g.update(c);
+            }
+            assert_eq!(g.finalize(), ans);
+        }
+
+        // update_paddedは区切りごとに0で埋める
+        let mut a = GHash::new(*H);
+        a.update_padded(&data[..20]);
+        a.update_padded(&data[20..]);
+        let mut b = GHash::new(*H);
+        b.update(&data[..20]);
+        b.update(&[0; 12]);
+        b.update(&data[20..]);
+        assert_eq!(a.finalize(), b.finalize());
+    }
+
+    // RFC 8452 付録A
+    #[test]
+    fn polyval() {
+        let h = *b"\x25\x62\x93\x47\x58\x92\x42\x76\x1d\x31\xf8\x26\xba\x4b\x75\x7b";
+        let x = b"\x4f\x4f\x95\x66\x8c\x83\xdf\xb6\x40\x17\x62\xbb\x2d\x01\xa2\x62\xd1\xa2\x4d\xdd\x27\x21\xd0\x06\xbb\xe4\x5f\x20\xd3\xc9\xf3\x62";
+        let ans = *b"\xf7\xa3\xb4\x7b\x84\x61\x19\xfa\xe5\xb7\x86\x6c\xf5\xe5\xb7\x7e";
+        let mut p = Polyval::new(h);
+        p.update(x);
+        assert_eq!(p.finalize(), ans);
+        let mut p = Polyval::new(h);
+        for c in x.chunks(7) {
+            p.update(c);
+        }
+        assert_eq!(p.finalize(), ans);
+    }
+}
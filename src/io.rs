@@ -0,0 +1,421 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::{error::AesError, mode::BlockCipher, padding::Padding, stream::{CbcDecryptor, CbcEncryptor, Ctr}};
+
+// 一度に暗号化・復号するバイト数
+const BUF_SIZE: usize = 8192;
+
+// CTRで暗号化してからinnerに書き込む
+pub struct CtrWriter<'a, C: BlockCipher<T>, W: Write, const T: usize> {
+    ctr: Ctr<'a, C, T>,
+    inner: W,
+    buf: Vec<u8>,
+}
+
+impl<'a, C: BlockCipher<T>, W: Write, const T: usize> CtrWriter<'a, C, W, T> {
+    pub fn new(cipher: &'a C, nonce: &[u8], inner: W) -> Result<Self, AesError> {
+        Ok(Self { ctr: Ctr::new(cipher, nonce)?, inner, buf: vec![0; BUF_SIZE] })
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<'a, C: BlockCipher<T>, W: Write, const T: usize> Write for CtrWriter<'a, C, W, T> {
+    // innerに書き込めなかった分は鍵ストリームを戻して、次のwriteでやり直せるようにする
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let n = data.len().min(BUF_SIZE);
+        let start = self.ctr.position();
+        self.ctr.update(&data[..n], &mut self.buf[..n])?;
+        match self.inner.write(&self.buf[..n]) {
+            Ok(m) => {
+                if m < n {
+                    self.ctr.seek(start + m as u64)?;
+                }
+                Ok(m)
+            }
+            Err(e) => {
+                // innerのエラーをそのまま返す。startは一度通った位置なので戻せる
+                let _ = self.ctr.seek(start);
+                Err(e)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// innerの位置0から暗号文が始まるものとして、鍵ストリームも同じ位置に合わせる
+// 鍵ストリームが届かない位置ならinnerを元の位置に戻す
+impl<'a, C: BlockCipher<T>, W: Write + Seek, const T: usize> Seek for CtrWriter<'a, C, W, T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let start = self.ctr.position();
+        let n = self.inner.seek(pos)?;
+        if let Err(e) = self.ctr.seek(n) {
+            self.inner.seek(SeekFrom::Start(start))?;
+            return Err(e.into());
+        }
+        Ok(n)
+    }
+}
+
+// innerから読んだものをCTRで復号する
+pub struct CtrReader<'a, C: BlockCipher<T>, R: Read, const T: usize> {
+    ctr: Ctr<'a, C, T>,
+    inner: R,
+    buf: Vec<u8>,
+}
+
+impl<'a, C: BlockCipher<T>, R: Read, const T: usize> CtrReader<'a, C, R, T> {
+    pub fn new(cipher: &'a C, nonce: &[u8], inner: R) -> Result<Self, AesError> {
+        Ok(Self { ctr: Ctr::new(cipher, nonce)?, inner, buf: vec![0; BUF_SIZE] })
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<'a, C: BlockCipher<T>, R: Read, const T: usize> Read for CtrReader<'a, C, R, T> {
+    // 鍵ストリームの残りより多くはinnerから読まない。尽きていれば1バイトだけ読んで終わりか確かめる
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let mut len = out.len().min(BUF_SIZE);
+        if let Some(r) = self.ctr.remaining() {
+            len = len.min(r.max(1).try_into().unwrap_or(usize::MAX));
+        }
+        let n = self.inner.read(&mut self.buf[..len])?;
+        self.ctr.update(&self.buf[..n], &mut out[..n])?;
+        Ok(n)
+    }
+}
+
+// CtrWriterと同じくinnerの位置0が暗号文の先頭。任意の位置から読める
+impl<'a, C: BlockCipher<T>, R: Read + Seek, const T: usize> Seek for CtrReader<'a, C, R, T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let start = self.ctr.position();
+        let n = self.inner.seek(pos)?;
+        if let Err(e) = self.ctr.seek(n) {
+            self.inner.seek(SeekFrom::Start(start))?;
+            return Err(e.into());
+        }
+        Ok(n)
+    }
+}
+
+// CBCで暗号化してからinnerに書き込む。最後に必ずfinishを呼んでパディングを書き込む
+pub struct CbcWriter<'a, C: BlockCipher<T>, W: Write, const T: usize> {
+    enc: CbcEncryptor<'a, C, T>,
+    inner: W,
+    // 暗号化したがまだinnerに書き込んでいないのはbuf[pos..end]
+    buf: Vec<u8>,
+    pos: usize,
+    end: usize,
+}
+
+impl<'a, C: BlockCipher<T>, W: Write, const T: usize> CbcWriter<'a, C, W, T> {
+    pub fn new(cipher: &'a C, iv: [u8; T], padding: Padding, inner: W) -> Self {
+        Self { enc: CbcEncryptor::new_padded(cipher, iv, padding), inner, buf: vec![0; BUF_SIZE + T], pos: 0, end: 0 }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    // 残っている暗号文と最後のブロックを書き込んでinnerを返す
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_pending()?;
+        let n = self.enc.finalize(&mut self.buf)?;
+        self.inner.write_all(&self.buf[..n])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_pending(&mut self) -> io::Result<()> {
+        while self.pos < self.end {
+            match self.inner.write(&self.buf[self.pos..self.end]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(m) => self.pos += m,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, C: BlockCipher<T>, W: Write, const T: usize> Write for CbcWriter<'a, C, W, T> {
+    // 前回の暗号文を書き込み終えるまで新しい平文は受け取らない
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.write_pending()?;
+        let n = data.len().min(BUF_SIZE);
+        self.end = self.enc.update(&data[..n], &mut self.buf)?;
+        self.pos = 0;
+        // 書き込めなかった分は次のwrite, flush, finishで書き込む
+        let _ = self.write_pending();
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_pending()?;
+        self.inner.flush()
+    }
+}
+
+// innerから読んだものをCBCで復号する。パディングが壊れていればInvalidDataのエラーを返す
+pub struct CbcReader<'a, C: BlockCipher<T>, R: Read, const T: usize> {
+    // innerを読み切ってfinalizeした後はNone
+    dec: Option<CbcDecryptor<'a, C, T>>,
+    // 復号に失敗したら以降のreadは同じエラーを返す
+    error: Option<AesError>,
+    inner: R,
+    buf: Vec<u8>,
+    // 復号したがまだ返していないのはout[pos..end]
+    out: Vec<u8>,
+    pos: usize,
+    end: usize,
+}
+
+impl<'a, C: BlockCipher<T>, R: Read, const T: usize> CbcReader<'a, C, R, T> {
+    pub fn new(cipher: &'a C, iv: [u8; T], padding: Padding, inner: R) -> Self {
+        Self { dec: Some(CbcDecryptor::new_padded(cipher, iv, padding)), error: None, inner, buf: vec![0; BUF_SIZE], out: vec![0; BUF_SIZE + T], pos: 0, end: 0 }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        let n = self.inner.read(&mut self.buf)?;
+        let m = match (n, self.dec.take()) {
+            (_, None) => Ok(0),
+            (0, Some(dec)) => dec.finalize(&mut self.out),
+            (_, Some(mut dec)) => {
+                let m = dec.update(&self.buf[..n], &mut self.out);
+                self.dec = Some(dec);
+                m
+            }
+        };
+        self.pos = 0;
+        self.end = 0;
+        match m {
+            Ok(m) => self.end = m,
+            Err(e) => {
+                self.error = Some(e);
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, C: BlockCipher<T>, R: Read, const T: usize> Read for CbcReader<'a, C, R, T> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if let Some(e) = self.error {
+            return Err(e.into());
+        }
+        while self.pos == self.end && self.dec.is_some() {
+            self.fill()?;
+        }
+        let n = out.len().min(self.end - self.pos);
+        out[..n].copy_from_slice(&self.out[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{array, io::{self, Cursor, Read, Seek, SeekFrom, Write}};
+
+    use crate::{aes::{AESkey, AES}, mode::BlockCipher, padding::Padding};
+
+    use super::{CbcReader, CbcWriter, CtrReader, CtrWriter};
+
+    // 1バイトずつしか返さないReader
+    struct Slow<'a>(&'a [u8]);
+
+    impl Read for Slow<'_> {
+        fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+            let n = out.len().min(self.0.len()).min(1);
+            out[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    // 5バイトずつしか書き込まず、最初の10回は一回おきにエラーを返すWriter
+    #[derive(Default)]
+    struct Flaky {
+        out: Vec<u8>,
+        calls: usize,
+    }
+
+    impl Write for Flaky {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.calls += 1;
+            if self.calls <= 20 && self.calls.is_multiple_of(2) {
+                return Err(io::Error::other("flaky"));
+            }
+            let n = data.len().min(5);
+            self.out.extend_from_slice(&data[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Flakyのエラーが返っても書き込めるまで続ける
+    fn write_retry(w: &mut impl Write, mut data: &[u8]) {
+        while !data.is_empty() {
+            match w.write(data) {
+                Ok(n) => data = &data[n..],
+                Err(e) => assert_eq!(e.kind(), io::ErrorKind::Other),
+            }
+        }
+    }
+
+    #[test]
+    fn ctr() {
+        let c = AES::new(AESkey::K128([5; 16]));
+        let nonce = [1, 2, 3, 4, 5, 6, 7, 8];
+        let p: Vec<u8> = (0..20000).map(|i| (i * 11) as u8).collect();
+        let mut expect = vec![0; p.len()];
+        c.encrypt_ctr(&p, &nonce, &mut expect);
+
+        let mut w = CtrWriter::new(&c, &nonce, vec![]).unwrap();
+        w.write_all(&p[..7]).unwrap();
+        w.write_all(&p[7..]).unwrap();
+        assert_eq!(w.into_inner(), expect);
+
+        let mut r = CtrReader::new(&c, &nonce, &expect[..]).unwrap();
+        let mut out = vec![];
+        r.read_to_end(&mut out).unwrap();
+        assert_eq!(out, p);
+
+        let mut r = CtrReader::new(&c, &nonce, Slow(&expect[..100])).unwrap();
+        let mut out = vec![];
+        r.read_to_end(&mut out).unwrap();
+        assert_eq!(out, p[..100]);
+
+        // 途中から読み書きする
+        let mut r = CtrReader::new(&c, &nonce, Cursor::new(&expect)).unwrap();
+        let mut out = [0; 100];
+        assert_eq!(r.seek(SeekFrom::Start(12345)).unwrap(), 12345);
+        r.read_exact(&mut out).unwrap();
+        assert_eq!(out, p[12345..12445]);
+        r.seek(SeekFrom::End(-7)).unwrap();
+        r.read_exact(&mut out[..7]).unwrap();
+        assert_eq!(out[..7], p[p.len() - 7..]);
+        r.seek(SeekFrom::Current(-50)).unwrap();
+        r.read_exact(&mut out[..50]).unwrap();
+        assert_eq!(out[..50], p[p.len() - 50..]);
+
+        let mut w = CtrWriter::new(&c, &nonce, Cursor::new(vec![])).unwrap();
+        w.seek(SeekFrom::Start(1000)).unwrap();
+        w.write_all(&p[1000..]).unwrap();
+        w.seek(SeekFrom::Start(0)).unwrap();
+        w.write_all(&p[..1000]).unwrap();
+        assert_eq!(w.into_inner().into_inner(), expect);
+
+        // 鍵ストリームの外にはseekできず、位置も変わらない
+        let nonce15 = [7; 15];
+        let mut w = CtrWriter::new(&c, &nonce15, Cursor::new(vec![])).unwrap();
+        w.write_all(&p[..32]).unwrap();
+        assert_eq!(w.seek(SeekFrom::Start(5000)).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(w.get_ref().position(), 32);
+        w.write_all(&p[32..48]).unwrap();
+        w.seek(SeekFrom::Start(4)).unwrap();
+        assert!(w.seek(SeekFrom::Start(5000)).is_err());
+        w.write_all(&p[4..20]).unwrap();
+        let mut all = vec![0; 48];
+        c.encrypt_ctr(&p[..48], &nonce15, &mut all);
+        assert_eq!(w.into_inner().into_inner(), all);
+
+        // 鍵ストリームをちょうど使い切る暗号文の終わりにもseekできる
+        let mut all = vec![0; 4096];
+        c.encrypt_ctr(&[0; 4096], &nonce15, &mut all);
+        let mut r = CtrReader::new(&c, &nonce15, Cursor::new(&all)).unwrap();
+        assert_eq!(r.seek(SeekFrom::End(0)).unwrap(), 4096);
+        assert_eq!(r.read(&mut [0; 16]).unwrap(), 0);
+        assert!(r.seek(SeekFrom::Start(5000)).is_err());
+        assert_eq!(r.get_ref().position(), 4096);
+        r.seek(SeekFrom::Start(4000)).unwrap();
+        let mut out = vec![];
+        r.read_to_end(&mut out).unwrap();
+        assert_eq!(out, [0; 96]);
+
+        // 鍵ストリームの残りが短くても読めるだけ読む
+        let mut r = CtrReader::new(&c, &nonce15, Cursor::new([&all[..], &[1; 10]].concat())).unwrap();
+        r.seek(SeekFrom::Start(4090)).unwrap();
+        let mut out = [0; 100];
+        assert_eq!(r.read(&mut out).unwrap(), 6);
+        assert_eq!(out[..6], [0; 6]);
+        assert_eq!(r.get_ref().position(), 4096);
+        assert_eq!(r.read(&mut out).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let mut w = CtrWriter::new(&c, &nonce, Flaky::default()).unwrap();
+        write_retry(&mut w, &p[..300]);
+        assert_eq!(w.into_inner().out, expect[..300]);
+    }
+
+    #[test]
+    fn cbc() {
+        let c = AES::new(AESkey::K256([9; 32]));
+        let iv = array::from_fn(|i| i as u8);
+        let p: Vec<u8> = (0..9000).map(|i| (i * 7) as u8).collect();
+        for len in [0, 1, 16, 8191, 8192, 9000] {
+            let expect = c.encrypt_cbc_padded(&p[..len], iv, Padding::Pkcs7);
+            let mut w = CbcWriter::new(&c, iv, Padding::Pkcs7, vec![]);
+            io::copy(&mut Slow(&p[..len.min(50)]), &mut w).unwrap();
+            w.write_all(&p[len.min(50)..len]).unwrap();
+            assert_eq!(w.finish().unwrap(), expect);
+
+            let mut r = CbcReader::new(&c, iv, Padding::Pkcs7, &expect[..]);
+            let mut out = vec![];
+            r.read_to_end(&mut out).unwrap();
+            assert_eq!(out, p[..len]);
+
+            let mut r = CbcReader::new(&c, iv, Padding::Pkcs7, Slow(&expect));
+            let mut out = vec![];
+            r.read_to_end(&mut out).unwrap();
+            assert_eq!(out, p[..len]);
+        }
+
+        let expect = c.encrypt_cbc_padded(&p[..300], iv, Padding::Pkcs7);
+        let mut w = CbcWriter::new(&c, iv, Padding::Pkcs7, Flaky::default());
+        write_retry(&mut w, &p[..7]);
+        write_retry(&mut w, &p[7..300]);
+        while w.flush().is_err() {}
+        assert_eq!(w.finish().unwrap().out, expect);
+
+        // パディングが壊れている
+        let mut bad = c.encrypt_cbc_padded(&p[..40], iv, Padding::Pkcs7);
+        let n = bad.len();
+        bad[n - 17] ^= 1;
+        let mut r = CbcReader::new(&c, iv, Padding::Pkcs7, &bad[..]);
+        let mut out = vec![];
+        assert_eq!(r.read_to_end(&mut out).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        // 一度失敗したら読み続けてもエラーのまま
+        assert_eq!(r.read(&mut [0; 16]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let mut r = CbcReader::new(&c, iv, Padding::Pkcs7, &bad[..n - 3]);
+        assert_eq!(r.read_to_end(&mut out).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(r.read(&mut [0; 16]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,236 @@
+use crate::{error::AesError, mode::{ct_eq, BlockCipher}};
+
+// RFC 3394 のデフォルトのIV
+pub const DEFAULT_IV: [u8; 8] = [0xa6; 8];
+// RFC 5649 のデフォルトのAIVの上位32bit
+pub const DEFAULT_AIV: [u8; 4] = [0xa6, 0x59, 0x59, 0xa6];
+
+// AES Key Wrap (KW) と Key Wrap with Padding (KWP) (NIST SP 800-38F)
+pub struct KeyWrap<C: BlockCipher<16>> {
+    cipher: C,
+}
+
+impl<C: BlockCipher<16>> KeyWrap<C> {
+    pub fn new(cipher: C) -> Self {
+        Self { cipher }
+    }
+
+    // 64bitずつのRに対する6回の変換
+    fn w(&self, a: [u8; 8], r: &mut [u8]) -> [u8; 8] {
+        let n = r.len() / 8;
+        let mut a = a;
+        for j in 0..6 {
+            for (i, ri) in r.chunks_exact_mut(8).enumerate() {
+                let mut b = [0; 16];
+                b[..8].copy_from_slice(&a);
+                b[8..].copy_from_slice(ri);
+                let b = self.cipher._encrypt(b);
+                let t = (n * j + i + 1) as u64;
+                a = (u64::from_be_bytes(b[..8].try_into().unwrap()) ^ t).to_be_bytes();
+                ri.copy_from_slice(&b[8..]);
+            }
+        }
+        a
+    }
+
+    fn w_inv(&self, a: [u8; 8], r: &mut [u8]) -> [u8; 8] {
+        let n = r.len() / 8;
+        let mut a = a;
+        for j in (0..6).rev() {
+            for (i, ri) in r.chunks_exact_mut(8).enumerate().rev() {
+                let t = (n * j + i + 1) as u64;
+                let mut b = [0; 16];
+                b[..8].copy_from_slice(&(u64::from_be_bytes(a) ^ t).to_be_bytes());
+                b[8..].copy_from_slice(ri);
+                let b = self.cipher._decrypt(b);
+                a.copy_from_slice(&b[..8]);
+                ri.copy_from_slice(&b[8..]);
+            }
+        }
+        a
+    }
+
+    pub fn wrap(&self, key: &[u8]) -> Result<Vec<u8>, AesError> {
+        self.wrap_with_iv(DEFAULT_IV, key)
+    }
+
+    // keyは16バイト以上の8の倍数
+    pub fn wrap_with_iv(&self, iv: [u8; 8], key: &[u8]) -> Result<Vec<u8>, AesError> {
+        if !key.len().is_multiple_of(8) {
+            return Err(AesError::NotBlockAligned { len: key.len(), block_size: 8 });
+        }
+        if key.len() < 16 {
+            return Err(AesError::TooShort { len: key.len(), min: 16 });
+        }
+        let mut out = vec![0; key.len() + 8];
+        out[8..].copy_from_slice(key);
+        let a = self.w(iv, &mut out[8..]);
+        out[..8].copy_from_slice(&a);
+        Ok(out)
+    }
+
+    pub fn unwrap(&self, wrapped: &[u8]) -> Result<Vec<u8>, AesError> {
+        self.unwrap_with_iv(DEFAULT_IV, wrapped)
+    }
+
+    // IVが一致しなければAuthenticationFailedを返す
+    pub fn unwrap_with_iv(&self, iv: [u8; 8], wrapped: &[u8]) -> Result<Vec<u8>, AesError> {
+        if !wrapped.len().is_multiple_of(8) {
+            return Err(AesError::NotBlockAligned { len: wrapped.len(), block_size: 8 });
+        }
+        if wrapped.len() < 24 {
+            return Err(AesError::TooShort { len: wrapped.len(), min: 24 });
+        }
+        let mut out = wrapped[8..].to_vec();
+        let a = self.w_inv(wrapped[..8].try_into().unwrap(), &mut out);
+        if !ct_eq(&a, &iv) {
+            out.fill(0);
+            return Err(AesError::AuthenticationFailed);
+        }
+        Ok(out)
+    }
+
+    pub fn wrap_pad(&self, key: &[u8]) -> Result<Vec<u8>, AesError> {
+        self.wrap_pad_with_aiv(DEFAULT_AIV, key)
+    }
+
+    // keyは1バイト以上。AIVの下位32bitはkeyの長さになる
+    pub fn wrap_pad_with_aiv(&self, aiv: [u8; 4], key: &[u8]) -> Result<Vec<u8>, AesError> {
+        if key.is_empty() {
+            return Err(AesError::TooShort { len: 0, min: 1 });
+        }
+        let len = u32::try_from(key.len()).map_err(|_| AesError::TooLong { len: key.len() as u64, max: u32::MAX as u64 })?;
+        let mut a = [0; 8];
+        a[..4].copy_from_slice(&aiv);
+        a[4..].copy_from_slice(&len.to_be_bytes());
+        let mut out = vec![0; key.len().div_ceil(8) * 8 + 8];
+        out[8..8 + key.len()].copy_from_slice(key);
+        if out.len() == 16 {
+            // 1ブロックだけのときはそのまま暗号化する
+            out[..8].copy_from_slice(&a);
+            let b = self.cipher._encrypt(out[..].try_into().unwrap());
+            out.copy_from_slice(&b);
+        } else {
+            let a = self.w(a, &mut out[8..]);
+            out[..8].copy_from_slice(&a);
+        }
+        Ok(out)
+    }
+
+    pub fn unwrap_pad(&self, wrapped: &[u8]) -> Result<Vec<u8>, AesError> {
+        self.unwrap_pad_with_aiv(DEFAULT_AIV, wrapped)
+    }
+
+    // AIV、長さ、埋めた0のどれかが正しくなければAuthenticationFailedを返す
+    pub fn unwrap_pad_with_aiv(&self, aiv: [u8; 4], wrapped: &[u8]) -> Result<Vec<u8>, AesError> {
+        if !wrapped.len().is_multiple_of(8) {
+            return Err(AesError::NotBlockAligned { len: wrapped.len(), block_size: 8 });
+        }
+        if wrapped.len() < 16 {
+            return Err(AesError::TooShort { len: wrapped.len(), min: 16 });
+        }
+        let mut out = wrapped[8..].to_vec();
+        let a = if wrapped.len() == 16 {
+            let b = self.cipher._decrypt(wrapped.try_into().unwrap());
+            out.copy_from_slice(&b[8..]);
+            b[..8].try_into().unwrap()
+        } else {
+            self.w_inv(wrapped[..8].try_into().unwrap(), &mut out)
+        };
+        let len = u32::from_be_bytes(a[4..].try_into().unwrap()) as usize;
+        let valid_len = len + 8 > out.len() && len <= out.len();
+        // 長さが正しくないときも埋めた部分の確認はする
+        let pad_start = if valid_len { len } else { out.len() };
+        let pad_ok = out[pad_start..].iter().fold(0, |acc, x| acc | x) == 0;
+        if !(ct_eq(&a[..4], &aiv) & valid_len & pad_ok) {
+            out.fill(0);
+            return Err(AesError::AuthenticationFailed);
+        }
+        out.truncate(len);
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::array;
+
+    use crate::{aes::{AESkey, AES}, error::AesError, kw::KeyWrap};
+
+    const KEY: &[u8; 32] = b"\x00\x11\"3DUfw\x88\x99\xaa\xbb\xcc\xdd\xee\xff\x00\x01\x02\x03\x04\x05\x06\x07\x08\t\n\x0b\x0c\r\x0e\x0f";
+
+    fn kek(len: usize) -> AES {
+        let k: [u8; 32] = array::from_fn(|i| i as u8);
+        match len {
+            16 => AES::new(AESkey::K128(k[..16].try_into().unwrap())),
+            24 => AES::new(AESkey::K192(k[..24].try_into().unwrap())),
+            _ => AES::new(AESkey::K256(k)),
+        }
+    }
+
+    // RFC 3394 4節
+    #[test]
+    fn wrap() {
+        let vectors: [(usize, usize, &[u8]); 6] = [
+            (16, 16, b"\x1f\xa6\x8b\n\x81\x12\xb4G\xae\xf3K\xd8\xfbZ{\x82\x9d>\x86#q\xd2\xcf\xe5"),
+            (24, 16, b"\x96w\x8b%\xael\xa45\xf9+[\x97\xc0P\xae\xd2F\x8a\xb8\xa1z\xd8N]"),
+            (32, 16, b"d\xe8\xc3\xf9\xce\x0f[\xa2c\xe9wy\x05\x81\x8a*\x93\xc8\x19\x1e}n\x8a\xe7"),
+            (24, 24, b"\x03\x1d3&N\x15\xd32h\xf2N\xc2`t>\xdc\xe1\xc6\xc7\xdd\xeerZ\x93k\xa8\x14\x91\\gb\xd2"),
+            (32, 24, b"\xa8\xf9\xbc\x16\x12\xc6\x8b?\xf6\xe6\xf4\xfb\xe3\x0eq\xe4v\x9c\x8b\x80\xa3,\xb8\x95\x8c\xd5\xd1}k%M\xa1"),
+            (32, 32, b"(\xc9\xf4\x04\xc4\xb8\x10\xf4\xcb\xcc\xb3\\\xfb\x87\xf8&?W\x86\xe2\xd8\x0e\xd3&\xcb\xc7\xf0\xe7\x1a\x99\xf4;\xfb\x98\x8b\x9bz\x02\xdd!"),
+        ];
+        for (kek_len, key_len, ans) in vectors {
+            let w = KeyWrap::new(kek(kek_len));
+            assert_eq!(w.wrap(&KEY[..key_len]).unwrap(), ans);
+            assert_eq!(w.unwrap(ans).unwrap(), KEY[..key_len]);
+
+            let mut bad = ans.to_vec();
+            bad[3] ^= 1;
+            assert_eq!(w.unwrap(&bad), Err(AesError::AuthenticationFailed));
+        }
+
+        // 別のIV
+        let w = KeyWrap::new(kek(16));
+        let iv = *b"\x01\x02\x03\x04\x05\x06\x07\x08";
+        let c = w.wrap_with_iv(iv, &KEY[..16]).unwrap();
+        assert_eq!(w.unwrap_with_iv(iv, &c).unwrap(), KEY[..16]);
+        assert_eq!(w.unwrap(&c), Err(AesError::AuthenticationFailed));
+
+        assert_eq!(w.wrap(&KEY[..8]), Err(AesError::TooShort { len: 8, min: 16 }));
+        assert_eq!(w.wrap(&KEY[..20]), Err(AesError::NotBlockAligned { len: 20, block_size: 8 }));
+    }
+
+    // RFC 5649 6節
+    #[test]
+    fn wrap_pad() {
+        let w = KeyWrap::new(AES::new(AESkey::K192(*b"X@\xdfn)\xb0*\xf1\xabI;p[\xf1n\xa1\xae\x838\xf4\xdc\xc1v\xa8")));
+        let vectors: [(&[u8], &[u8]); 2] = [
+            (b"\xc3{~d\x92XC@\xbe\xd1\"\x07\x80\x89A\x15Ph\xf78", b"\x13\x8b\xde\xaa\x9b\x8f\xa7\xfca\xf9wB\xe7\"H\xeeZ\xe6\xaeS`\xd1\xaej_T\xf3s\xfaT;j"),
+            (b"ForPasi", b"\xaf\xbe\xb0\xf0}\xfb\xf5A\x92\x00\xf2\xcc\xb5\x0b\xb2O"),
+        ];
+        for (key, ans) in vectors {
+            assert_eq!(w.wrap_pad(key).unwrap(), ans);
+            assert_eq!(w.unwrap_pad(ans).unwrap(), key);
+            let mut bad = ans.to_vec();
+            bad[9] ^= 1;
+            assert_eq!(w.unwrap_pad(&bad), Err(AesError::AuthenticationFailed));
+        }
+
+        // 長さが8の倍数のときは埋めない
+        for len in [1, 8, 9, 16, 17, 32] {
+            let c = w.wrap_pad(&KEY[..len]).unwrap();
+            assert_eq!(c.len(), len.div_ceil(8) * 8 + 8);
+            assert_eq!(w.unwrap_pad(&c).unwrap(), KEY[..len]);
+        }
+
+        let aiv = [1, 2, 3, 4];
+        let c = w.wrap_pad_with_aiv(aiv, &KEY[..20]).unwrap();
+        assert_eq!(w.unwrap_pad_with_aiv(aiv, &c).unwrap(), KEY[..20]);
+        assert_eq!(w.unwrap_pad(&c), Err(AesError::AuthenticationFailed));
+
+        // KWでラップしたものはKWPとしては通らない
+        let c = w.wrap(&KEY[..16]).unwrap();
+        assert_eq!(w.unwrap_pad(&c), Err(AesError::AuthenticationFailed));
+        assert_eq!(w.wrap_pad(&[]), Err(AesError::TooShort { len: 0, min: 1 }));
+    }
+}
@@ -1,7 +1,29 @@
 #![cfg_attr(feature = "use_nightly", feature(portable_simd))]
 
 pub mod aes;
+pub mod auto;
+pub mod bitslice;
+pub mod ccm;
+pub mod cmac;
+pub mod eax;
+pub mod error;
+pub mod gcm;
+pub mod gcm_siv;
+pub mod ghash;
+pub mod io;
+pub mod kw;
 pub mod mode;
+pub mod ocb;
+pub mod padding;
+pub mod siv;
+pub mod stream;
+pub mod ttable;
+pub mod xts;
+
+#[cfg(feature = "use_nightly")]
+pub mod simd;
 
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 pub mod aes_ni;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod clmul;
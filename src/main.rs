@@ -1,11 +1,11 @@
-use aes_rs::{aes::AESkey, mode::BlockCipher};
+use aes_rs::{aes::AESkey, auto::AutoAES, mode::BlockCipher};
 
 fn main() {
     let i = AESkey::K128([34,244,2,242,91,26,15,215,34,184,49,105,225,5,9,248]);
     let mut k = [115u8,223,255,87,254,36,232,7,189,79,177,188,78,7,205,115];
 
-    assert!(aes_rs::aes_ni::support_aesni());
-    let c = aes_rs::aes_ni::AES_NI::new(i);
+    let c = AutoAES::new(i);
+    println!("{:?}", c.backend());
     let mut j = [0u8; 16];
     c.encrypt_ecb(&k, &mut j);
     println!("{:?}", j);
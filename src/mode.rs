@@ -1,60 +1,267 @@
 use std::array;
 
+use crate::{error::AesError, padding::Padding};
+
+// encrypt_ctrで一度に_encrypt_blocksへ渡すブロック数
+pub(crate) const PARALLEL_BLOCKS: usize = 32;
+
+// CBCの暗号文窃取 (NIST SP 800-38A Addendum) で最後の2ブロックをどう並べるか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsVariant {
+    // C1 .. Cn-2 || Cn-1* || Cn
+    Cs1,
+    // 最後のブロックが半端なときだけCS3と同じく入れ替える
+    Cs2,
+    // C1 .. Cn-2 || Cn || Cn-1*
+    Cs3,
+}
+
+impl CsVariant {
+    fn swap(self, d: usize, text_size: usize) -> bool {
+        match self {
+            CsVariant::Cs1 => false,
+            CsVariant::Cs2 => d != text_size,
+            CsVariant::Cs3 => true,
+        }
+    }
+}
+
 pub trait BlockCipher<const TEXT_SIZE: usize> {
     fn _encrypt(&self, in_bytes: [u8; TEXT_SIZE]) -> [u8; TEXT_SIZE];
     fn _decrypt(&self, in_bytes: [u8; TEXT_SIZE]) -> [u8; TEXT_SIZE];
 
+    // 複数ブロックをまとめて処理できる実装はこれらを上書きする
+    fn _encrypt_blocks(&self, blocks: &mut [[u8; TEXT_SIZE]]) {
+        for b in blocks.iter_mut() {
+            *b = self._encrypt(*b);
+        }
+    }
+    fn _decrypt_blocks(&self, blocks: &mut [[u8; TEXT_SIZE]]) {
+        for b in blocks.iter_mut() {
+            *b = self._decrypt(*b);
+        }
+    }
+
+    // AES-NIの拡大鍵。GCMでCTRとGHASHをまとめて処理するのに使う
+    #[doc(hidden)]
+    fn _aesni_round_key(&self) -> Option<&[u8]> {
+        None
+    }
+
     #[doc(hidden)]
-    fn _assert1(&self, ib_len: usize) {
-        if ib_len % TEXT_SIZE != 0 {
-            panic!("in_bytesの大きさは{}の倍数である必要があります", TEXT_SIZE);
+    fn _check1(&self, ib_len: usize) -> Result<(), AesError> {
+        if !ib_len.is_multiple_of(TEXT_SIZE) {
+            return Err(AesError::NotBlockAligned { len: ib_len, block_size: TEXT_SIZE });
         }
+        Ok(())
     }
 
     #[doc(hidden)]
-    fn _assert2(&self, ib_len: usize, ob_len: usize) {
+    fn _check2(&self, ib_len: usize, ob_len: usize) -> Result<(), AesError> {
         if ib_len != ob_len {
-            panic!("in_bytesとout_bytesの長さは同じである必要があります");
+            return Err(AesError::LengthMismatch { in_len: ib_len, out_len: ob_len });
         }
+        Ok(())
     }
 
+    fn try_encrypt_ecb(&self, in_bytes: &[u8], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self._check1(in_bytes.len())?;
+        self._check2(in_bytes.len(), out_bytes.len())?;
+        out_bytes.copy_from_slice(in_bytes);
+        self._encrypt_blocks(out_bytes.as_chunks_mut::<TEXT_SIZE>().0);
+        Ok(())
+    }
+    fn try_decrypt_ecb(&self, in_bytes: &[u8], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self._check1(in_bytes.len())?;
+        self._check2(in_bytes.len(), out_bytes.len())?;
+        out_bytes.copy_from_slice(in_bytes);
+        self._decrypt_blocks(out_bytes.as_chunks_mut::<TEXT_SIZE>().0);
+        Ok(())
+    }
     fn encrypt_ecb(&self, in_bytes: &[u8], out_bytes: &mut [u8]) {
-        self._assert1(in_bytes.len());
-        self._assert2(in_bytes.len(), out_bytes.len());
-        for (i, v) in in_bytes.windows(TEXT_SIZE).step_by(TEXT_SIZE).enumerate() {
-            out_bytes[TEXT_SIZE * i..TEXT_SIZE * (i + 1)].copy_from_slice(&self._encrypt(v.try_into().unwrap()))
-        }
+        self.try_encrypt_ecb(in_bytes, out_bytes).unwrap_or_else(|e| panic!("{}", e))
     }
     fn decrypt_ecb(&self, in_bytes: &[u8], out_bytes: &mut [u8]) {
-        self._assert1(in_bytes.len());
-        self._assert2(in_bytes.len(), out_bytes.len());
-        for (i, v) in in_bytes.windows(TEXT_SIZE).step_by(TEXT_SIZE).enumerate() {
-            out_bytes[TEXT_SIZE * i..TEXT_SIZE * (i + 1)].copy_from_slice(&self._decrypt(v.try_into().unwrap()))
-        }
+        self.try_decrypt_ecb(in_bytes, out_bytes).unwrap_or_else(|e| panic!("{}", e))
     }
 
-    fn encrypt_cbc(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8]) {
-        self._assert1(in_bytes.len());
-        self._assert2(in_bytes.len(), out_bytes.len());
-        out_bytes[0..TEXT_SIZE].copy_from_slice(&self._encrypt(array::from_fn(|i| in_bytes[i] ^ iv[i])));
-        for (i, v) in in_bytes[TEXT_SIZE..].windows(TEXT_SIZE).step_by(TEXT_SIZE).enumerate() {
-            let (l, r) = out_bytes.split_at_mut((i + 1) * TEXT_SIZE);
-            r[0..TEXT_SIZE].copy_from_slice(&self._encrypt(array::from_fn(|j| v[j] ^ l[i * TEXT_SIZE + j])))
+    fn try_encrypt_cbc(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self._check1(in_bytes.len())?;
+        self._check2(in_bytes.len(), out_bytes.len())?;
+        let mut prev = iv;
+        for (ib, ob) in in_bytes.chunks_exact(TEXT_SIZE).zip(out_bytes.chunks_exact_mut(TEXT_SIZE)) {
+            prev = self._encrypt(array::from_fn(|i| ib[i] ^ prev[i]));
+            ob.copy_from_slice(&prev);
+        }
+        Ok(())
+    }
+    fn try_decrypt_cbc(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self._check1(in_bytes.len())?;
+        self._check2(in_bytes.len(), out_bytes.len())?;
+        out_bytes.copy_from_slice(in_bytes);
+        let blocks = out_bytes.as_chunks_mut::<TEXT_SIZE>().0;
+        self._decrypt_blocks(blocks);
+        let prev = std::iter::once(&iv[..]).chain(in_bytes.chunks_exact(TEXT_SIZE));
+        for (b, p) in blocks.iter_mut().zip(prev) {
+            for (x, y) in b.iter_mut().zip(p) {
+                *x ^= y;
+            }
         }
+        Ok(())
+    }
+    fn encrypt_cbc(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8]) {
+        self.try_encrypt_cbc(in_bytes, iv, out_bytes).unwrap_or_else(|e| panic!("{}", e))
     }
     fn decrypt_cbc(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8]) {
-        self._assert1(in_bytes.len());
-        self._assert2(in_bytes.len(), out_bytes.len());
-        let mut dec = self._decrypt(in_bytes[0..TEXT_SIZE].try_into().unwrap());
-        out_bytes[0..TEXT_SIZE].copy_from_slice(&array::from_fn::<u8, TEXT_SIZE, _>(|i| dec[i] ^ iv[i]));
-        for (i, v) in in_bytes.windows(TEXT_SIZE * 2).step_by(TEXT_SIZE).enumerate() {
-            dec = self._decrypt(v[TEXT_SIZE..TEXT_SIZE * 2].try_into().unwrap());
-            out_bytes[(i + 1) * TEXT_SIZE..(i + 2) * TEXT_SIZE].copy_from_slice(&array::from_fn::<u8, TEXT_SIZE, _>(|j| dec[j] ^ v[j]));
+        self.try_decrypt_cbc(in_bytes, iv, out_bytes).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // in_bytesのうちそのまま暗号化できる長さと、埋めた最後のブロックを返す
+    #[doc(hidden)]
+    fn _pad_last(&self, in_bytes: &[u8], padding: Padding, ob_len: usize) -> Result<(usize, Option<[u8; TEXT_SIZE]>), AesError> {
+        let n = padding.padded_len(in_bytes.len(), TEXT_SIZE);
+        if ob_len < n {
+            return Err(AesError::BufferTooSmall { needed: n, len: ob_len });
         }
+        let full = in_bytes.len() - in_bytes.len() % TEXT_SIZE;
+        if n == full {
+            return Ok((full, None));
+        }
+        let mut last = [0; TEXT_SIZE];
+        last[..in_bytes.len() - full].copy_from_slice(&in_bytes[full..]);
+        padding.pad(&mut last, in_bytes.len() - full)?;
+        Ok((full, Some(last)))
+    }
+    #[doc(hidden)]
+    fn _unpadded<F>(&self, in_bytes: &[u8], padding: Padding, out_bytes: &mut [u8], mut f: F) -> Result<usize, AesError>
+        where F: FnMut(&[u8], &mut [u8]) -> Result<(), AesError>
+    {
+        if out_bytes.len() < in_bytes.len() {
+            return Err(AesError::BufferTooSmall { needed: in_bytes.len(), len: out_bytes.len() });
+        }
+        let out_bytes = &mut out_bytes[..in_bytes.len()];
+        f(in_bytes, out_bytes)?;
+        padding.unpad(out_bytes, TEXT_SIZE).inspect_err(|_| out_bytes.fill(0))
     }
 
-    fn encrypt_ofb(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8]) {
-        self._assert2(in_bytes.len(), out_bytes.len());
+    // out_bytesにはpadding.padded_len(in_bytes.len(), TEXT_SIZE)バイト以上必要
+    fn try_encrypt_ecb_padded_into(&self, in_bytes: &[u8], padding: Padding, out_bytes: &mut [u8]) -> Result<usize, AesError> {
+        let (full, last) = self._pad_last(in_bytes, padding, out_bytes.len())?;
+        self.try_encrypt_ecb(&in_bytes[..full], &mut out_bytes[..full])?;
+        match last {
+            Some(l) => {
+                self.try_encrypt_ecb(&l, &mut out_bytes[full..full + TEXT_SIZE])?;
+                Ok(full + TEXT_SIZE)
+            }
+            None => Ok(full),
+        }
+    }
+    // out_bytesにはin_bytes.len()バイト以上必要
+    fn try_decrypt_ecb_padded_into(&self, in_bytes: &[u8], padding: Padding, out_bytes: &mut [u8]) -> Result<usize, AesError> {
+        self._unpadded(in_bytes, padding, out_bytes, |i, o| self.try_decrypt_ecb(i, o))
+    }
+    fn encrypt_ecb_padded(&self, in_bytes: &[u8], padding: Padding) -> Vec<u8> {
+        let mut out = vec![0; padding.padded_len(in_bytes.len(), TEXT_SIZE)];
+        self.try_encrypt_ecb_padded_into(in_bytes, padding, &mut out).unwrap_or_else(|e| panic!("{}", e));
+        out
+    }
+    fn decrypt_ecb_padded(&self, in_bytes: &[u8], padding: Padding) -> Result<Vec<u8>, AesError> {
+        let mut out = vec![0; in_bytes.len()];
+        let n = self.try_decrypt_ecb_padded_into(in_bytes, padding, &mut out)?;
+        out.truncate(n);
+        Ok(out)
+    }
+
+    fn try_encrypt_cbc_padded_into(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], padding: Padding, out_bytes: &mut [u8]) -> Result<usize, AesError> {
+        let (full, last) = self._pad_last(in_bytes, padding, out_bytes.len())?;
+        self.try_encrypt_cbc(&in_bytes[..full], iv, &mut out_bytes[..full])?;
+        match last {
+            Some(l) => {
+                let iv = if full == 0 { iv } else { out_bytes[full - TEXT_SIZE..full].try_into().unwrap() };
+                self.try_encrypt_cbc(&l, iv, &mut out_bytes[full..full + TEXT_SIZE])?;
+                Ok(full + TEXT_SIZE)
+            }
+            None => Ok(full),
+        }
+    }
+    fn try_decrypt_cbc_padded_into(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], padding: Padding, out_bytes: &mut [u8]) -> Result<usize, AesError> {
+        self._unpadded(in_bytes, padding, out_bytes, |i, o| self.try_decrypt_cbc(i, iv, o))
+    }
+    fn encrypt_cbc_padded(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], padding: Padding) -> Vec<u8> {
+        let mut out = vec![0; padding.padded_len(in_bytes.len(), TEXT_SIZE)];
+        self.try_encrypt_cbc_padded_into(in_bytes, iv, padding, &mut out).unwrap_or_else(|e| panic!("{}", e));
+        out
+    }
+    fn decrypt_cbc_padded(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], padding: Padding) -> Result<Vec<u8>, AesError> {
+        let mut out = vec![0; in_bytes.len()];
+        let n = self.try_decrypt_cbc_padded_into(in_bytes, iv, padding, &mut out)?;
+        out.truncate(n);
+        Ok(out)
+    }
+
+    // in_bytesはTEXT_SIZE以上の任意の長さでよい
+    fn try_encrypt_cbc_cs(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8], variant: CsVariant) -> Result<(), AesError> {
+        self._check2(in_bytes.len(), out_bytes.len())?;
+        if in_bytes.len() < TEXT_SIZE {
+            return Err(AesError::TooShort { len: in_bytes.len(), min: TEXT_SIZE });
+        }
+        // 最後のブロックの長さ (1..=TEXT_SIZE)
+        let d = in_bytes.len() - (in_bytes.len() - 1) / TEXT_SIZE * TEXT_SIZE;
+        let head = in_bytes.len() - d;
+        if head == 0 {
+            return self.try_encrypt_cbc(in_bytes, iv, out_bytes);
+        }
+        self.try_encrypt_cbc(&in_bytes[..head], iv, &mut out_bytes[..head])?;
+        let start = head - TEXT_SIZE;
+        let prev: [u8; TEXT_SIZE] = out_bytes[start..head].try_into().unwrap();
+        let last = self._encrypt(array::from_fn(|i| if i < d { in_bytes[head + i] ^ prev[i] } else { prev[i] }));
+        if variant.swap(d, TEXT_SIZE) {
+            out_bytes[start..head].copy_from_slice(&last);
+            out_bytes[head..].copy_from_slice(&prev[..d]);
+        } else {
+            out_bytes[start + d..].copy_from_slice(&last);
+        }
+        Ok(())
+    }
+    fn try_decrypt_cbc_cs(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8], variant: CsVariant) -> Result<(), AesError> {
+        self._check2(in_bytes.len(), out_bytes.len())?;
+        if in_bytes.len() < TEXT_SIZE {
+            return Err(AesError::TooShort { len: in_bytes.len(), min: TEXT_SIZE });
+        }
+        let d = in_bytes.len() - (in_bytes.len() - 1) / TEXT_SIZE * TEXT_SIZE;
+        let head = in_bytes.len() - d;
+        if head == 0 {
+            return self.try_decrypt_cbc(in_bytes, iv, out_bytes);
+        }
+        let start = head - TEXT_SIZE;
+        let (prev_head, last) = if variant.swap(d, TEXT_SIZE) {
+            (&in_bytes[head..], &in_bytes[start..head])
+        } else {
+            (&in_bytes[start..start + d], &in_bytes[start + d..])
+        };
+        // D(Cn) = Cn-1 ^ (Pn || 0) なので、Cn-1の後ろ半分はD(Cn)から分かる
+        let z = self._decrypt(last.try_into().unwrap());
+        let prev: [u8; TEXT_SIZE] = array::from_fn(|i| if i < d { prev_head[i] } else { z[i] });
+        for i in 0..d {
+            out_bytes[head + i] = z[i] ^ prev[i];
+        }
+        self.try_decrypt_cbc(&in_bytes[..start], iv, &mut out_bytes[..start])?;
+        let chain: [u8; TEXT_SIZE] = if start == 0 { iv } else { in_bytes[start - TEXT_SIZE..start].try_into().unwrap() };
+        let p = self._decrypt(prev);
+        for i in 0..TEXT_SIZE {
+            out_bytes[start + i] = p[i] ^ chain[i];
+        }
+        Ok(())
+    }
+    fn encrypt_cbc_cs(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8], variant: CsVariant) {
+        self.try_encrypt_cbc_cs(in_bytes, iv, out_bytes, variant).unwrap_or_else(|e| panic!("{}", e))
+    }
+    fn decrypt_cbc_cs(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8], variant: CsVariant) {
+        self.try_decrypt_cbc_cs(in_bytes, iv, out_bytes, variant).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn try_encrypt_ofb(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self._check2(in_bytes.len(), out_bytes.len())?;
         let mut e = iv;
         for (i, (ib, ob)) in in_bytes.iter().zip(out_bytes.iter_mut()).enumerate() {
             if i % TEXT_SIZE == 0 {
@@ -62,16 +269,23 @@ pub trait BlockCipher<const TEXT_SIZE: usize> {
             }
             *ob = *ib ^ e[i % TEXT_SIZE];
         }
+        Ok(())
+    }
+    fn try_decrypt_ofb(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self.try_encrypt_ofb(in_bytes, iv, out_bytes)
+    }
+    fn encrypt_ofb(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8]) {
+        self.try_encrypt_ofb(in_bytes, iv, out_bytes).unwrap_or_else(|e| panic!("{}", e))
     }
     fn decrypt_ofb(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8]) {
         self.encrypt_ofb(in_bytes, iv, out_bytes)
     }
 
     #[doc(hidden)]
-    fn _cfb_n(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8], n: usize, is_encrypt: bool) {
-        self._assert2(in_bytes.len(), out_bytes.len());
-        if n % 8 != 0 {
-            panic!("nは8の倍数にしてください");
+    fn _cfb_n(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8], n: usize, is_encrypt: bool) -> Result<(), AesError> {
+        self._check2(in_bytes.len(), out_bytes.len())?;
+        if n == 0 || !n.is_multiple_of(8) || n > TEXT_SIZE * 8 {
+            return Err(AesError::InvalidSegmentSize(n));
         }
         let mut e = iv;
         let mut e1 = iv;
@@ -84,60 +298,299 @@ pub trait BlockCipher<const TEXT_SIZE: usize> {
             *ob = e1[i % block] ^ *ib;
             e[TEXT_SIZE - block + (i % block)] = if is_encrypt { *ob } else { *ib };
         }
+        Ok(())
+    }
+    fn try_encrypt_cfb(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self._cfb_n(in_bytes, iv, out_bytes, TEXT_SIZE * 8, true)
+    }
+    fn try_decrypt_cfb(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self._cfb_n(in_bytes, iv, out_bytes, TEXT_SIZE * 8, false)
+    }
+    fn try_encrypt_cfb_n(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8], n: usize) -> Result<(), AesError> {
+        self._cfb_n(in_bytes, iv, out_bytes, n, true)
+    }
+    fn try_decrypt_cfb_n(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8], n: usize) -> Result<(), AesError> {
+        self._cfb_n(in_bytes, iv, out_bytes, n, false)
     }
     fn encrypt_cfb(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8]) {
-        self._cfb_n(in_bytes, iv, out_bytes, TEXT_SIZE * 8, true);
+        self.try_encrypt_cfb(in_bytes, iv, out_bytes).unwrap_or_else(|e| panic!("{}", e))
     }
     fn decrypt_cfb(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8]) {
-        self._cfb_n(in_bytes, iv, out_bytes, TEXT_SIZE * 8, false);
+        self.try_decrypt_cfb(in_bytes, iv, out_bytes).unwrap_or_else(|e| panic!("{}", e))
     }
     fn encrypt_cfb_n(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8], n: usize) {
-        self._cfb_n(in_bytes, iv, out_bytes, n, true);
+        self.try_encrypt_cfb_n(in_bytes, iv, out_bytes, n).unwrap_or_else(|e| panic!("{}", e))
     }
     fn decrypt_cfb_n(&self, in_bytes: &[u8], iv: [u8; TEXT_SIZE], out_bytes: &mut [u8], n: usize) {
-        self._cfb_n(in_bytes, iv, out_bytes, n, false)
+        self.try_decrypt_cfb_n(in_bytes, iv, out_bytes, n).unwrap_or_else(|e| panic!("{}", e))
     }
 
-    fn encrypt_ctr(&self, in_bytes: &[u8], nonce: &[u8], out_bytes: &mut [u8]) {
-        self._assert2(in_bytes.len(), out_bytes.len());
+    fn try_encrypt_ctr(&self, in_bytes: &[u8], nonce: &[u8], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self._check2(in_bytes.len(), out_bytes.len())?;
         if nonce.len() >= TEXT_SIZE {
-            panic!("nonceは長さを{}未満にしてください", TEXT_SIZE);
-        }
-        let mut e = [0; TEXT_SIZE];
-        let mut c: DefaultCounter<TEXT_SIZE> = DefaultCounter::new(nonce);
-        for (i, (ib, ob)) in in_bytes.iter().zip(out_bytes.iter_mut()).enumerate() {
-            if i % TEXT_SIZE == 0 {
-                e = self._encrypt(c.inner);
-                c.next();
-            }
-            *ob = *ib ^ e[i % TEXT_SIZE];
+            return Err(AesError::InvalidNonceLength { len: nonce.len(), max: TEXT_SIZE - 1 });
         }
+        self.try_encrypt_ctr_with(in_bytes, &mut DefaultCounter::new(nonce), out_bytes)
+    }
+    fn try_decrypt_ctr(&self, in_bytes: &[u8], nonce: &[u8], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self.try_encrypt_ctr(in_bytes, nonce, out_bytes)
+    }
+    fn encrypt_ctr(&self, in_bytes: &[u8], nonce: &[u8], out_bytes: &mut [u8]) {
+        self.try_encrypt_ctr(in_bytes, nonce, out_bytes).unwrap_or_else(|e| panic!("{}", e))
     }
     fn decrypt_ctr(&self, in_bytes: &[u8], nonce: &[u8], out_bytes: &mut [u8]) {
         self.encrypt_ctr(in_bytes, nonce, out_bytes);
     }
+
+    // counterから作ったカウンタブロックを使う。counterは使った分だけ進む
+    // カウンタが足りなければCounterOverflowを返し、out_bytesに暗号文を残さない
+    fn try_encrypt_ctr_with(&self, in_bytes: &[u8], counter: &mut dyn CtrCounter<TEXT_SIZE>, out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self._check2(in_bytes.len(), out_bytes.len())?;
+        if counter.remaining().is_some_and(|r| in_bytes.len().div_ceil(TEXT_SIZE) as u128 > r) {
+            return Err(AesError::CounterOverflow);
+        }
+        ctr_blocks(self, in_bytes, out_bytes, || counter.next())
+    }
+    fn try_decrypt_ctr_with(&self, in_bytes: &[u8], counter: &mut dyn CtrCounter<TEXT_SIZE>, out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self.try_encrypt_ctr_with(in_bytes, counter, out_bytes)
+    }
+    fn encrypt_ctr_with(&self, in_bytes: &[u8], counter: &mut dyn CtrCounter<TEXT_SIZE>, out_bytes: &mut [u8]) {
+        self.try_encrypt_ctr_with(in_bytes, counter, out_bytes).unwrap_or_else(|e| panic!("{}", e))
+    }
+    fn decrypt_ctr_with(&self, in_bytes: &[u8], counter: &mut dyn CtrCounter<TEXT_SIZE>, out_bytes: &mut [u8]) {
+        self.encrypt_ctr_with(in_bytes, counter, out_bytes);
+    }
+}
+
+// CTRのカウンタブロックを順に作る
+pub trait CtrCounter<const T: usize> {
+    // 現在のカウンタブロックを返して1進める。一周してエラーにする場合はCounterOverflow
+    fn next(&mut self) -> Result<[u8; T], AesError>;
+    // 最初からblock個進めた位置に移動する
+    fn seek(&mut self, block: u128) -> Result<(), AesError>;
+    // あと何ブロック作れるか。制限がないかu128に収まらなければNone
+    fn remaining(&self) -> Option<u128> {
+        None
+    }
 }
 
-struct DefaultCounter<const T: usize> {
+// encrypt_ctrのカウンタ。nonceの後ろ全体をビッグエンディアンで増やし、一周したらエラー
+pub(crate) struct DefaultCounter<const T: usize> {
     inner: [u8; T],
     nonce_len: usize,
+    exhausted: bool,
 }
 
 impl<const T: usize> DefaultCounter<T> {
-    fn new(nonce: &[u8]) -> Self {
-        Self { inner: array::from_fn(|i| if i < nonce.len() { nonce[i] } else {0}), nonce_len: nonce.len() }
+    pub(crate) fn new(nonce: &[u8]) -> Self {
+        Self { inner: array::from_fn(|i| if i < nonce.len() { nonce[i] } else {0}), nonce_len: nonce.len(), exhausted: false }
     }
-    fn next(&mut self) {
+}
+
+impl<const T: usize> CtrCounter<T> for DefaultCounter<T> {
+    fn next(&mut self) -> Result<[u8; T], AesError> {
+        if self.exhausted {
+            return Err(AesError::CounterOverflow);
+        }
+        let v = self.inner;
+        self.exhausted = true;
         for i in 1..=T - self.nonce_len {
             let b;
             (self.inner[T - i], b) = self.inner[T - i].overflowing_add(1);
-            if !b { return; }
+            if !b {
+                self.exhausted = false;
+                break;
+            }
+        }
+        Ok(v)
+    }
+    // nonceの後ろをblockにする
+    fn seek(&mut self, block: u128) -> Result<(), AesError> {
+        let len = T - self.nonce_len;
+        if len < 16 && block >> (8 * len) != 0 {
+            return Err(AesError::CounterOverflow);
+        }
+        let b = block.to_be_bytes();
+        for i in 1..=len {
+            self.inner[T - i] = if i <= 16 { b[16 - i] } else { 0 };
+        }
+        self.exhausted = false;
+        Ok(())
+    }
+    fn remaining(&self) -> Option<u128> {
+        let len = T - self.nonce_len;
+        if self.exhausted {
+            return Some(0);
+        }
+        if len > 16 {
+            return None;
+        }
+        let mut b = [0; 16];
+        b[16 - len..].copy_from_slice(&self.inner[self.nonce_len..]);
+        let v = u128::from_be_bytes(b);
+        if len == 16 {
+            // vが0なら2^128で収まらない
+            return (v != 0).then(|| v.wrapping_neg());
+        }
+        Some((1 << (8 * len)) - v)
+    }
+}
+
+// カウンタブロックのどこをどう増やすか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CounterFormat {
+    // 最後の4バイトをビッグエンディアンで増やす (GCM, RFC 3686)
+    Be32,
+    // 最後の8バイトをビッグエンディアンで増やす
+    Be64,
+    // 先頭16バイトをリトルエンディアンで増やす (WinZip AESなど)
+    Le128,
+    // ブロック全体をビッグエンディアンで増やす (SP 800-38Aの標準的な増やし方)
+    FullBlock,
+}
+
+impl CounterFormat {
+    // 増やす部分のバイト数。ブロックより長くはならず、128bitまで
+    fn width(self, text_size: usize) -> usize {
+        match self {
+            CounterFormat::Be32 => 4,
+            CounterFormat::Be64 => 8,
+            CounterFormat::Le128 | CounterFormat::FullBlock => 16,
+        }.min(text_size)
+    }
+}
+
+// 増やす部分が一周したときの扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wraparound {
+    // CounterOverflowを返す
+    Error,
+    // 0に戻って続ける (GCMのinc32と同じ)
+    Wrap,
+}
+
+// 初期カウンタブロックからformatに従って増やしていくカウンタ
+pub struct Counter<const T: usize> {
+    block: [u8; T],
+    format: CounterFormat,
+    wraparound: Wraparound,
+    // 初期カウンタブロックの増やす部分の値
+    start: u128,
+    // 次に返すのは何ブロック目か
+    count: u128,
+}
+
+impl<const T: usize> Counter<T> {
+    pub fn new(icb: [u8; T], format: CounterFormat, wraparound: Wraparound) -> Self {
+        let mut c = Self { block: icb, format, wraparound, start: 0, count: 0 };
+        c.start = c.load();
+        c
+    }
+
+    fn max(&self) -> u128 {
+        u128::MAX >> (128 - 8 * self.format.width(T))
+    }
+
+    fn load(&self) -> u128 {
+        let w = self.format.width(T);
+        let mut b = [0; 16];
+        match self.format {
+            CounterFormat::Le128 => {
+                b[..w].copy_from_slice(&self.block[..w]);
+                u128::from_le_bytes(b)
+            }
+            _ => {
+                b[16 - w..].copy_from_slice(&self.block[T - w..]);
+                u128::from_be_bytes(b)
+            }
+        }
+    }
+
+    fn store(&mut self, v: u128) {
+        let w = self.format.width(T);
+        match self.format {
+            CounterFormat::Le128 => self.block[..w].copy_from_slice(&v.to_le_bytes()[..w]),
+            _ => self.block[T - w..].copy_from_slice(&v.to_be_bytes()[16 - w..]),
         }
-        panic!("counter overflow");
     }
 }
 
+impl<const T: usize> CtrCounter<T> for Counter<T> {
+    fn next(&mut self) -> Result<[u8; T], AesError> {
+        let max = self.max();
+        if self.wraparound == Wraparound::Error && self.count > max - self.start {
+            return Err(AesError::CounterOverflow);
+        }
+        self.store(self.start.wrapping_add(self.count) & max);
+        self.count = self.count.wrapping_add(1);
+        Ok(self.block)
+    }
+    fn seek(&mut self, block: u128) -> Result<(), AesError> {
+        if self.wraparound == Wraparound::Error && block > self.max() - self.start {
+            return Err(AesError::CounterOverflow);
+        }
+        self.count = block;
+        Ok(())
+    }
+    fn remaining(&self) -> Option<u128> {
+        if self.wraparound == Wraparound::Wrap {
+            return None;
+        }
+        match (self.max() - self.start).checked_sub(self.count) {
+            Some(v) => v.checked_add(1),
+            None => Some(0),
+        }
+    }
+}
+
+// 一致しない位置によって時間が変わらない比較
+pub(crate) fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub(crate) fn xor(a: &mut [u8; 16], b: &[u8; 16]) {
+    for (x, y) in a.iter_mut().zip(b) {
+        *x ^= y;
+    }
+}
+
+// nextで作ったカウンタブロックを暗号化してin_bytesにXORする。nextが失敗したらout_bytesを0で埋める
+pub(crate) fn ctr_blocks<C: BlockCipher<T> + ?Sized, const T: usize>(
+    cipher: &C, in_bytes: &[u8], out_bytes: &mut [u8], mut next: impl FnMut() -> Result<[u8; T], AesError>,
+) -> Result<(), AesError> {
+    let mut e = [[0; T]; PARALLEL_BLOCKS];
+    for (ib, ob) in in_bytes.chunks(T * PARALLEL_BLOCKS).zip(out_bytes.chunks_mut(T * PARALLEL_BLOCKS)) {
+        let n = ib.len().div_ceil(T);
+        for v in e[..n].iter_mut() {
+            match next() {
+                Ok(c) => *v = c,
+                Err(err) => {
+                    out_bytes.fill(0);
+                    return Err(err);
+                }
+            }
+        }
+        cipher._encrypt_blocks(&mut e[..n]);
+        for (i, (x, y)) in ib.iter().zip(ob.iter_mut()).enumerate() {
+            *y = *x ^ e[i / T][i % T];
+        }
+    }
+    Ok(())
+}
+
+// ブロック全体を128bitのビッグエンディアンのカウンタとして増やすCTR (SIV, EAX)
+pub(crate) fn ctr128<C: BlockCipher<16> + ?Sized>(cipher: &C, icb: [u8; 16], in_bytes: &[u8], out_bytes: &mut [u8]) {
+    let mut cb = u128::from_be_bytes(icb);
+    let _ = ctr_blocks(cipher, in_bytes, out_bytes, || {
+        let v = cb.to_be_bytes();
+        cb = cb.wrapping_add(1);
+        Ok(v)
+    });
+}
+
+#[cfg(test)]
 struct BlockCipherTester;
+#[cfg(test)]
 impl BlockCipher<4> for BlockCipherTester {
     fn _encrypt(&self, in_bytes: [u8; 4]) -> [u8; 4] {
         array::from_fn(|i| in_bytes[(i + 1) % 4] ^ 0xff)
@@ -152,7 +605,9 @@ impl BlockCipher<4> for BlockCipherTester {
 mod test {
     use std::array;
 
-    use super::{BlockCipherTester, BlockCipher, DefaultCounter};
+    use crate::{error::AesError, padding::Padding};
+
+    use super::{ctr_blocks, BlockCipherTester, BlockCipher, Counter, CounterFormat, CsVariant, CtrCounter, Wraparound};
 
     #[test]
     fn test_ecb() {
@@ -224,4 +679,137 @@ mod test {
         b.decrypt_ctr(&out_bytes, &nonce, &mut out_out_bytes);
         assert!(out_out_bytes == res);
     }
+
+    #[test]
+    fn test_errors() {
+        let b = BlockCipherTester;
+        let mut out_bytes = [0; 16];
+        assert_eq!(b.try_encrypt_ecb(&[0; 15], &mut out_bytes[..15]), Err(AesError::NotBlockAligned { len: 15, block_size: 4 }));
+        assert_eq!(b.try_decrypt_cbc(&[0; 16], [0; 4], &mut out_bytes[..12]), Err(AesError::LengthMismatch { in_len: 16, out_len: 12 }));
+        assert_eq!(b.try_encrypt_cfb_n(&[0; 16], [0; 4], &mut out_bytes, 12), Err(AesError::InvalidSegmentSize(12)));
+        assert_eq!(b.try_encrypt_cfb_n(&[0; 16], [0; 4], &mut out_bytes, 0), Err(AesError::InvalidSegmentSize(0)));
+        assert_eq!(b.try_encrypt_ctr(&[0; 16], &[1, 2, 3, 4], &mut out_bytes), Err(AesError::InvalidNonceLength { len: 4, max: 3 }));
+
+        // 1バイトのカウンタは256ブロックまで
+        let mut out_bytes = [0; 4 * 257];
+        assert_eq!(b.try_encrypt_ctr(&[0; 4 * 256], &[1, 2, 3], &mut out_bytes[..4 * 256]), Ok(()));
+        // 足りないときは何も書き込まない
+        out_bytes.fill(0x55);
+        assert_eq!(b.try_encrypt_ctr(&[0; 4 * 257], &[1, 2, 3], &mut out_bytes), Err(AesError::CounterOverflow));
+        assert_eq!(out_bytes, [0x55; 4 * 257]);
+
+        // remainingが分からないカウンタが途中で失敗したら0で埋める
+        let mut k = 0u32;
+        let r = ctr_blocks(&b, &[1; 4 * 100], &mut out_bytes[..4 * 100], || {
+            k += 1;
+            if k > 40 { Err(AesError::CounterOverflow) } else { Ok(k.to_be_bytes()) }
+        });
+        assert_eq!(r, Err(AesError::CounterOverflow));
+        assert_eq!(out_bytes[..4 * 100], [0; 4 * 100]);
+    }
+
+    #[test]
+    fn test_padded() {
+        let b = BlockCipherTester;
+        let res: [u8; 13] = array::from_fn(|i| i as u8);
+        let iv = [11, 12, 13, 14];
+        let out_bytes = b.encrypt_ecb_padded(&res, Padding::Pkcs7);
+        assert_eq!(out_bytes.len(), 16);
+        assert_eq!(b.decrypt_ecb_padded(&out_bytes, Padding::Pkcs7).unwrap(), res);
+
+        let out_bytes = b.encrypt_cbc_padded(&res[..12], iv, Padding::Iso7816);
+        assert_eq!(out_bytes.len(), 16);
+        assert_eq!(b.decrypt_cbc_padded(&out_bytes, iv, Padding::Iso7816).unwrap(), res[..12]);
+        assert_eq!(b.decrypt_cbc_padded(&out_bytes, iv, Padding::Pkcs7), Err(AesError::InvalidPadding));
+
+        let mut small = [0; 15];
+        assert_eq!(b.try_encrypt_cbc_padded_into(&res, iv, Padding::Pkcs7, &mut small), Err(AesError::BufferTooSmall { needed: 16, len: 15 }));
+        let mut out_out_bytes = [0; 16];
+        assert_eq!(b.try_decrypt_cbc_padded_into(&out_bytes, iv, Padding::Iso7816, &mut out_out_bytes), Ok(12));
+        assert_eq!(out_out_bytes[..12], res[..12]);
+    }
+
+    #[test]
+    fn test_cbc_cs() {
+        let b = BlockCipherTester;
+        let iv = [11, 12, 13, 14];
+        for v in [CsVariant::Cs1, CsVariant::Cs2, CsVariant::Cs3] {
+            for len in 4..=13 {
+                let res: Vec<u8> = (0..len as u8).collect();
+                let mut out_bytes = vec![0; len];
+                let mut out_out_bytes = vec![0; len];
+                b.encrypt_cbc_cs(&res, iv, &mut out_bytes, v);
+                b.decrypt_cbc_cs(&out_bytes, iv, &mut out_out_bytes, v);
+                assert_eq!(out_out_bytes, res);
+            }
+        }
+        let mut out_bytes = [0; 3];
+        assert_eq!(b.try_encrypt_cbc_cs(&[0; 3], iv, &mut out_bytes, CsVariant::Cs1), Err(AesError::TooShort { len: 3, min: 4 }));
+    }
+
+    #[test]
+    fn test_counter() {
+        let icb: [u8; 16] = array::from_fn(|i| 0xf0 + i as u8);
+        let mut c = Counter::new(icb, CounterFormat::FullBlock, Wraparound::Error);
+        assert_eq!(c.next().unwrap(), icb);
+        let mut second = icb;
+        second[14..].copy_from_slice(&[0xff, 0x00]);
+        assert_eq!(c.next().unwrap(), second);
+
+        // 増やす部分だけが一周する
+        let mut icb = [0x11; 16];
+        icb[12..].fill(0xff);
+        let mut c = Counter::new(icb, CounterFormat::Be32, Wraparound::Wrap);
+        c.next().unwrap();
+        let mut wrapped = [0x11; 16];
+        wrapped[12..].fill(0);
+        assert_eq!(c.next().unwrap(), wrapped);
+        let mut c = Counter::new(icb, CounterFormat::Be32, Wraparound::Error);
+        c.next().unwrap();
+        assert_eq!(c.next(), Err(AesError::CounterOverflow));
+
+        icb[8..12].fill(0xff);
+        let mut c = Counter::new(icb, CounterFormat::Be64, Wraparound::Error);
+        c.seek(1).unwrap_err();
+        c.next().unwrap();
+        assert_eq!(c.next(), Err(AesError::CounterOverflow));
+
+        let mut icb = [0; 16];
+        icb[..2].fill(0xff);
+        let mut c = Counter::new(icb, CounterFormat::Le128, Wraparound::Error);
+        c.seek(1).unwrap();
+        let mut third = [0; 16];
+        third[2] = 1;
+        assert_eq!(c.next().unwrap(), third);
+        c.seek(0x1_0000).unwrap();
+        third[..2].fill(0xff);
+        assert_eq!(c.next().unwrap(), third);
+    }
+
+    #[test]
+    fn test_ctr_with() {
+        let b = BlockCipherTester;
+        let res: [u8; 13] = array::from_fn(|i| i as u8);
+        let mut out_bytes = [0; 13];
+        let mut c = Counter::new([1, 2, 0xff, 0xfe], CounterFormat::Be32, Wraparound::Wrap);
+        b.encrypt_ctr_with(&res, &mut c, &mut out_bytes);
+        let blocks = [[1, 2, 0xff, 0xfe], [1, 2, 0xff, 0xff], [1, 3, 0, 0], [1, 3, 0, 1]];
+        for (i, v) in out_bytes.iter().enumerate() {
+            assert_eq!(*v, res[i] ^ b._encrypt(blocks[i / 4])[i % 4]);
+        }
+        // counterは使った分だけ進んでいる
+        assert_eq!(c.next().unwrap(), [1, 3, 0, 2]);
+
+        let mut c = Counter::new([0xff; 4], CounterFormat::FullBlock, Wraparound::Error);
+        assert_eq!(c.remaining(), Some(1));
+        assert_eq!(b.try_encrypt_ctr_with(&res, &mut c, &mut out_bytes), Err(AesError::CounterOverflow));
+        // 失敗したときはcounterも進まない
+        assert_eq!(c.next(), Ok([0xff; 4]));
+        let mut c = Counter::new([0xff; 4], CounterFormat::FullBlock, Wraparound::Wrap);
+        let mut out_out_bytes = [0; 13];
+        b.encrypt_ctr_with(&res, &mut c, &mut out_bytes);
+        c.seek(0).unwrap();
+        b.decrypt_ctr_with(&out_bytes, &mut c, &mut out_out_bytes);
+        assert_eq!(out_out_bytes, res);
+    }
 }
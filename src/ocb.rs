@@ -0,0 +1,280 @@
+use std::array;
+
+use crate::{cmac::dbl, error::AesError, mode::{ct_eq, xor, BlockCipher, PARALLEL_BLOCKS}};
+
+// nonceは120bitまで
+const MAX_NONCE_LEN: usize = 15;
+
+// OCB3 (RFC 7253)
+pub struct Ocb<C: BlockCipher<16>> {
+    cipher: C,
+    l_star: [u8; 16],
+    l_dollar: [u8; 16],
+    // L_i = 2^i・L_$。ブロック番号のntzは64未満
+    l: [[u8; 16]; 64],
+}
+
+impl<C: BlockCipher<16>> Ocb<C> {
+    pub fn new(cipher: C) -> Self {
+        let l_star = cipher._encrypt([0; 16]);
+        let l_dollar = dbl(l_star);
+        let mut l = [dbl(l_dollar); 64];
+        for i in 1..64 {
+            l[i] = dbl(l[i - 1]);
+        }
+        Self { cipher, l_star, l_dollar, l }
+    }
+
+    fn check(&self, nonce: &[u8], in_len: usize, out_len: usize, tag_len: usize) -> Result<(), AesError> {
+        if in_len != out_len {
+            return Err(AesError::LengthMismatch { in_len, out_len });
+        }
+        if nonce.len() > MAX_NONCE_LEN {
+            return Err(AesError::InvalidNonceLength { len: nonce.len(), max: MAX_NONCE_LEN });
+        }
+        if !(1..=16).contains(&tag_len) {
+            return Err(AesError::InvalidTagLength(tag_len));
+        }
+        Ok(())
+    }
+
+    fn offset0(&self, nonce: &[u8], tag_len: usize) -> [u8; 16] {
+        let mut n = [0; 16];
+        n[0] = ((tag_len * 8 % 128) as u8) << 1;
+        n[15 - nonce.len()] |= 1;
+        n[16 - nonce.len()..].copy_from_slice(nonce);
+        let bottom = (n[15] & 0x3f) as u32;
+        n[15] &= 0xc0;
+        let ktop = self.cipher._encrypt(n);
+        // Stretch = Ktop || (Ktop[1..64] ^ Ktop[9..72]) の bottom bit目から128bit
+        let top = u128::from_be_bytes(ktop);
+        let low = u64::from_be_bytes(ktop[..8].try_into().unwrap()) ^ u64::from_be_bytes(ktop[1..9].try_into().unwrap());
+        let off = if bottom == 0 { top } else { (top << bottom) | ((low as u128) >> (64 - bottom)) };
+        off.to_be_bytes()
+    }
+
+    // first番目(1始まり)のブロックから順にオフセットを進めてoffsに書き込む
+    fn next_offsets(&self, offset: &mut [u8; 16], first: usize, offs: &mut [[u8; 16]]) {
+        for (j, o) in offs.iter_mut().enumerate() {
+            xor(offset, &self.l[(first + j).trailing_zeros() as usize]);
+            *o = *offset;
+        }
+    }
+
+    fn hash(&self, aad: &[u8]) -> [u8; 16] {
+        let mut sum = [0; 16];
+        let mut offset = [0; 16];
+        let mut buf = [[0; 16]; PARALLEL_BLOCKS];
+        let (blocks, rest) = aad.as_chunks::<16>();
+        for (k, c) in blocks.chunks(PARALLEL_BLOCKS).enumerate() {
+            self.next_offsets(&mut offset, k * PARALLEL_BLOCKS + 1, &mut buf[..c.len()]);
+            for (b, a) in buf.iter_mut().zip(c) {
+                xor(b, a);
+            }
+            self.cipher._encrypt_blocks(&mut buf[..c.len()]);
+            for b in &buf[..c.len()] {
+                xor(&mut sum, b);
+            }
+        }
+        if !rest.is_empty() {
+            xor(&mut offset, &self.l_star);
+            let mut b = [0; 16];
+            b[..rest.len()].copy_from_slice(rest);
+            b[rest.len()] = 0x80;
+            xor(&mut b, &offset);
+            xor(&mut sum, &self.cipher._encrypt(b));
+        }
+        sum
+    }
+
+    // 平文のチェックサムと最後のオフセットからタグを作る
+    fn crypt(&self, nonce: &[u8], aad: &[u8], in_bytes: &[u8], out_bytes: &mut [u8], tag_len: usize, is_encrypt: bool) -> [u8; 16] {
+        let mut offset = self.offset0(nonce, tag_len);
+        let mut checksum = [0; 16];
+        let mut offs = [[0; 16]; PARALLEL_BLOCKS];
+        let mut buf = [[0; 16]; PARALLEL_BLOCKS];
+        let full = in_bytes.len() / 16 * 16;
+        for (k, (ib, ob)) in in_bytes[..full].chunks(16 * PARALLEL_BLOCKS).zip(out_bytes[..full].chunks_mut(16 * PARALLEL_BLOCKS)).enumerate() {
+            let n = ib.len() / 16;
+            self.next_offsets(&mut offset, k * PARALLEL_BLOCKS + 1, &mut offs[..n]);
+            for (j, b) in buf[..n].iter_mut().enumerate() {
+                *b = array::from_fn(|i| ib[j * 16 + i] ^ offs[j][i]);
+            }
+            if is_encrypt {
+                self.cipher._encrypt_blocks(&mut buf[..n]);
+            } else {
+                self.cipher._decrypt_blocks(&mut buf[..n]);
+            }
+            for (j, b) in buf[..n].iter().enumerate() {
+                for i in 0..16 {
+                    ob[j * 16 + i] = b[i] ^ offs[j][i];
+                }
+            }
+            let plain = if is_encrypt { ib } else { &*ob };
+            for p in plain.as_chunks::<16>().0 {
+                xor(&mut checksum, p);
+            }
+        }
+        let rest = in_bytes.len() - full;
+        if rest > 0 {
+            xor(&mut offset, &self.l_star);
+            let pad = self.cipher._encrypt(offset);
+            for i in 0..rest {
+                out_bytes[full + i] = in_bytes[full + i] ^ pad[i];
+            }
+            let plain = if is_encrypt { &in_bytes[full..] } else { &out_bytes[full..] };
+            let mut b = [0; 16];
+            b[..rest].copy_from_slice(plain);
+            b[rest] = 0x80;
+            xor(&mut checksum, &b);
+        }
+        xor(&mut checksum, &offset);
+        xor(&mut checksum, &self.l_dollar);
+        let mut tag = self.cipher._encrypt(checksum);
+        xor(&mut tag, &self.hash(aad));
+        tag
+    }
+
+    // tagの長さ(1..=16バイト)で切り詰めたタグを書き込む
+    pub fn encrypt(&self, nonce: &[u8], aad: &[u8], in_bytes: &[u8], out_bytes: &mut [u8], tag: &mut [u8]) -> Result<(), AesError> {
+        self.check(nonce, in_bytes.len(), out_bytes.len(), tag.len())?;
+        let t = self.crypt(nonce, aad, in_bytes, out_bytes, tag.len(), true);
+        let n = tag.len();
+        tag.copy_from_slice(&t[..n]);
+        Ok(())
+    }
+
+    // タグが一致しなければout_bytesは0で埋める
+    pub fn decrypt(&self, nonce: &[u8], aad: &[u8], in_bytes: &[u8], tag: &[u8], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self.check(nonce, in_bytes.len(), out_bytes.len(), tag.len())?;
+        let t = self.crypt(nonce, aad, in_bytes, out_bytes, tag.len(), false);
+        if !ct_eq(&t[..tag.len()], tag) {
+            out_bytes.fill(0);
+            return Err(AesError::AuthenticationFailed);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::array;
+
+    use crate::{aes::{AESkey, AES}, auto::AutoAES, error::AesError, ocb::Ocb};
+
+    // nonceの最後のバイト, AADの長さ, 平文の長さ, 暗号文とタグ
+    const VECTORS: [(u8, usize, usize, &[u8]); 16] = [
+        (0, 0, 0, b"xT\x07\xbf\xff\xc8\xad\x9e\xdc\xc5R\n\xc9\x11\x1e\xe6"),
+        (1, 8, 8, b"h \xb3e{oaZW%\xbd\xa0\xd3\xb4\xeb:%|\x9a\xf1\xf8\xf00\t"),
+        (2, 8, 0, b"\x81\x01\x7f\x82\x03\xf0\x81'qR\xfa\xdeiJ\n\x00"),
+        (3, 0, 8, b"E\xddi\xf8\xf5\xaa\xe7$\x14\x05L\xd1\xf3]\x82v\x0b,\xd0\r/\x99\xbf\xa9"),
+        (4, 16, 16, b"W\x1dS[`\xb2w\x18\x8b\xe5\x14qp\xa9\xa2,:\xd7\xa4\xff85\xb8\xc5p\x1c\x1c\xce\xc8\xfc3X"),
+        (5, 16, 0, b"\x8c\xf7a\xb6\x90.\xf7dF*\xd8d\x98\xcak\x97"),
+        (6, 0, 16, b"\\\xe8\x8e\xc2\xe0i'\x06\xa9\x15\xc0\n\xeb\x8b#\x96\xf4\x0e\x1ct?RCk\xdf\x06\xd8\xfa\x1e\xca4="),
+        (7, 24, 24, b"\x1c\xa2 s\x08\xc8|\x01\x07V\x10M\x88@\xce\x19R\xf0\x96s\xa4H\xa1\"\xc9,b$\x10Q\xf5sV\xd7\xf3\xc9\x0b\xb0\xe0\x7f"),
+        (8, 24, 0, b"m\xc2%\xa0q\xfc\x1b\x9f|i\xf9;\x0f\x1e\x10\xde"),
+        (9, 0, 24, b"\"\x1b\xd0\xde\x7f\xa6\xfe\x99>\xcc\xd7iF\n\n\xf2\xd6\xcd\xed\x0c9[\x1c<\xe7%\xf3$\x94\xb9\xf9\x14\xd8\\\x0b\x1e\xb3\x83W\xff"),
+        (10, 32, 32, b"\xbdolIb\x01\xc6\x92\x96\xc1\x1e\xfd\x13\x8aFz\xbd<py$\xb9d\xde\xaf\xfc@1\x9a\xf5\xa4\x85@\xfb\xba\x18lUS\xc6\x8a\xd9\xf5\x92\xa7\x9aB@"),
+        (11, 32, 0, b"\xfe\x80i\x0b\xee\x8aH]\x11\xf3)e\xbc\x9d*2"),
+        (12, 0, 32, b")B\xbf\xc7s\xbd\xa2<\xab\xc6\xac\xfd\x9b\xfdX5\xbd0\x0f\tsy.\xf4`@\xc5?\x142\xbc\xdf\xb5\xe1\xdd\xe3\xbc\x18\xa5\xf8@\xb5.e4D\xd5\xdf"),
+        (13, 40, 40, b"\xd5\xca\x91t\x84\x10\xc1u\x1f\xf8\xa2\xf6\x18%[h\xa0\xa1.\t?\xf4T`nY\xf9\xc1\xd0\xdd\xc5Ke\xe8b\x8eV\x8b\xadz\xed\x07\xba\x06\xa4\xa6\x94\x83\xa7\x03T\x90\xc5v\x9e`"),
+        (14, 40, 0, b"\xc5\xcd\x9d\x18P\xc1A\xe3Xd\x99\x94\xeep\x1bh"),
+        (15, 0, 40, b"D\x12\x924\x93\xc5}]\xe0\xd7\x00\xf7S\xcc\xe0\xd1\xd2\xd9P`\x12.\x9f\x15\xa5\xdd\xbf\xc5x~P\xb5\xccU\xeeP{\xcb\x08NG\x9a\xd3c\xac6k\x95\xa9\x8c\xa5\xf3\x00\x0b\x14y"),
+    ];
+
+    fn nonce(last: u8) -> [u8; 12] {
+        array::from_fn(|i| if i == 11 { last } else { 0xbb - 0x11 * i as u8 })
+    }
+
+    // RFC 7253 付録A
+    #[test]
+    fn encrypt() {
+        let o = Ocb::new(AES::new(AESkey::K128(array::from_fn(|i| i as u8))));
+        let data: [u8; 40] = array::from_fn(|i| i as u8);
+        for (n, a, p, c) in VECTORS {
+            let mut out = vec![0; p];
+            let mut tag = [0; 16];
+            o.encrypt(&nonce(n), &data[..a], &data[..p], &mut out, &mut tag).unwrap();
+            assert_eq!(out, c[..p]);
+            assert_eq!(tag, c[p..]);
+        }
+
+        // 96bitのタグ
+        let o = Ocb::new(AES::new(AESkey::K128(array::from_fn(|i| 15 - i as u8))));
+        let mut out = [0; 40];
+        let mut tag = [0; 12];
+        o.encrypt(&nonce(0x0d), &data, &data, &mut out, &mut tag).unwrap();
+        assert_eq!(out, *b"\x17\x92\xa4\xe3\x1e\x07U\xfb\x03\xe3\x1b\"\x11nl-\xdf\x9e\xfdn3\xd56\xf1\xa0\x12K\nU\xba\xe8\x84\xed\x93H\x15)\xc7kj");
+        assert_eq!(tag, *b"\xd0\xc5\x15\xf4\xd1\xcd\xd4\xfd\xacO\x02\xaa");
+    }
+
+    // RFC 7253 付録A の最後の方法で全ての鍵長とタグ長を確かめる
+    #[test]
+    fn iterated() {
+        let expected: [(usize, &[u8]); 9] = [
+            (16, b"\x67\xe9\x44\xd2\x32\x56\xc5\xe0\xb6\xc6\x1f\xa2\x2f\xdf\x1e\xa2"),
+            (24, b"\xf6\x73\xf2\xc3\xe7\x17\x4a\xae\x7b\xae\x98\x6c\xa9\xf2\x9e\x17"),
+            (32, b"\xd9\x0e\xb8\xe9\xc9\x77\xc8\x8b\x79\xdd\x79\x3d\x7f\xfa\x16\x1c"),
+            (16, b"\x77\xa3\xd8\xe7\x35\x89\x15\x8d\x25\xd0\x12\x09"),
+            (24, b"\x05\xd5\x6e\xad\x27\x52\xc8\x6b\xe6\x93\x2c\x5e"),
+            (32, b"\x54\x58\x35\x9a\xc2\x3b\x0c\xba\x9e\x63\x30\xdd"),
+            (16, b"\x19\x2c\x9b\x7b\xd9\x0b\xa0\x6a"),
+            (24, b"\x00\x66\xbc\x6e\x0e\xf3\x4e\x24"),
+            (32, b"\x7d\x4e\xa5\xd4\x45\x50\x1c\xbe"),
+        ];
+        for (key_len, ans) in expected {
+            let t = ans.len();
+            let mut k = [0; 32];
+            k[key_len - 1] = (t * 8) as u8;
+            let key = match key_len {
+                16 => AESkey::K128(k[..16].try_into().unwrap()),
+                24 => AESkey::K192(k[..24].try_into().unwrap()),
+                _ => AESkey::K256(k),
+            };
+            let o = Ocb::new(AutoAES::new(key));
+            let n = |i: usize| -> [u8; 12] { array::from_fn(|j| if j < 4 { 0 } else { (i as u64).to_be_bytes()[j - 4] }) };
+            let mut c = Vec::new();
+            let s = [0; 127];
+            let mut enc = |n: [u8; 12], a: &[u8], p: &[u8]| {
+                let mut out = vec![0; p.len()];
+                let mut tag = vec![0; t];
+                o.encrypt(&n, a, p, &mut out, &mut tag).unwrap();
+                c.extend_from_slice(&out);
+                c.extend_from_slice(&tag);
+            };
+            for i in 0..128 {
+                enc(n(3 * i + 1), &s[..i], &s[..i]);
+                enc(n(3 * i + 2), &[], &s[..i]);
+                enc(n(3 * i + 3), &s[..i], &[]);
+            }
+            let mut tag = vec![0; t];
+            o.encrypt(&n(385), &c, &[], &mut [], &mut tag).unwrap();
+            assert_eq!(tag, ans);
+        }
+    }
+
+    #[test]
+    fn decrypt() {
+        let o = Ocb::new(AES::new(AESkey::K128(array::from_fn(|i| i as u8))));
+        let data: [u8; 40] = array::from_fn(|i| i as u8);
+        for (n, a, p, c) in VECTORS {
+            let mut out = vec![0; p];
+            o.decrypt(&nonce(n), &data[..a], &c[..p], &c[p..], &mut out).unwrap();
+            assert_eq!(out, data[..p]);
+            assert_eq!(o.decrypt(&nonce(n ^ 1), &data[..a], &c[..p], &c[p..], &mut out), Err(AesError::AuthenticationFailed));
+            assert!(out.iter().all(|&x| x == 0));
+        }
+
+        // 複数回のバッチにまたがる長さ
+        let long: [u8; 16 * 70 + 5] = array::from_fn(|i| (i * 7 + 3) as u8);
+        let mut ct = [0; 16 * 70 + 5];
+        let mut dec = [0; 16 * 70 + 5];
+        let mut tag = [0; 16];
+        o.encrypt(&[1, 2, 3], &long, &long, &mut ct, &mut tag).unwrap();
+        o.decrypt(&[1, 2, 3], &long, &ct, &tag, &mut dec).unwrap();
+        assert_eq!(dec, long);
+
+        assert_eq!(o.decrypt(&[0; 16], &[], &[], &tag, &mut []), Err(AesError::InvalidNonceLength { len: 16, max: 15 }));
+        assert_eq!(o.decrypt(&[0; 12], &[], &[], &[], &mut []), Err(AesError::InvalidTagLength(0)));
+    }
+}
@@ -0,0 +1,186 @@
+use std::hash::{BuildHasher, Hasher, RandomState};
+
+use crate::error::AesError;
+
+// aが0なら0xff、それ以外は0
+fn ct_is_zero(a: usize) -> u8 {
+    ((((a as u64 | (a as u64).wrapping_neg()) >> 63) as u8) ^ 1).wrapping_neg()
+}
+
+// a < bなら0xff、それ以外は0。aとbは2^63未満
+fn ct_lt(a: usize, b: usize) -> u8 {
+    (((a as u64).wrapping_sub(b as u64) >> 63) as u8).wrapping_neg()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Padding {
+    // 埋めたバイト数nをn個並べる
+    Pkcs7,
+    // 0を並べて最後の1バイトだけn
+    AnsiX923,
+    // 0x80の後に0を並べる
+    Iso7816,
+    // ランダムなバイトを並べて最後の1バイトだけn
+    Iso10126,
+    // 0を並べる (ブロック長の倍数なら何もしない、末尾の0は区別できない)
+    Zero,
+}
+
+impl Padding {
+    // lenバイトのデータを埋めた後の長さ
+    pub fn padded_len(&self, len: usize, block_size: usize) -> usize {
+        match self {
+            Padding::Zero => len.next_multiple_of(block_size),
+            _ => (len / block_size + 1) * block_size,
+        }
+    }
+
+    // block[..len]をデータとしてblock[len..]を埋める
+    // Zero以外は1バイト以上埋めるので、lenがblock.len()以上ならBufferTooSmall
+    pub fn pad(&self, block: &mut [u8], len: usize) -> Result<(), AesError> {
+        let needed = if *self == Padding::Zero { len } else { len + 1 };
+        if block.len() < needed {
+            return Err(AesError::BufferTooSmall { needed, len: block.len() });
+        }
+        let n = block.len() - len;
+        let tail = &mut block[len..];
+        match self {
+            Padding::Pkcs7 => tail.fill(n as u8),
+            Padding::AnsiX923 => {
+                tail.fill(0);
+                tail[n - 1] = n as u8;
+            }
+            Padding::Iso7816 => {
+                tail.fill(0);
+                tail[0] = 0x80;
+            }
+            Padding::Iso10126 => {
+                // 値は何でもよいので、暗号論的な乱数でなくてよい
+                let s = RandomState::new();
+                for (i, v) in tail.iter_mut().enumerate() {
+                    let mut h = s.build_hasher();
+                    h.write_usize(i);
+                    *v = h.finish() as u8;
+                }
+                tail[n - 1] = n as u8;
+            }
+            Padding::Zero => tail.fill(0),
+        }
+        Ok(())
+    }
+
+    // 埋めた部分を除いたデータの長さ
+    pub fn unpad(&self, data: &[u8], block_size: usize) -> Result<usize, AesError> {
+        if !data.len().is_multiple_of(block_size) {
+            return Err(AesError::NotBlockAligned { len: data.len(), block_size });
+        }
+        if *self == Padding::Zero {
+            let last = &data[data.len().saturating_sub(block_size)..];
+            let n = last.iter().rev().take_while(|v| **v == 0).count();
+            return Ok(data.len() - n);
+        }
+        if data.is_empty() {
+            return Err(AesError::InvalidPadding);
+        }
+        let last = &data[data.len() - block_size..];
+        let n = match self {
+            // パディングオラクルにならないよう、nによらず最後のブロック全体を見てから判定する
+            Padding::Pkcs7 | Padding::AnsiX923 | Padding::Iso10126 => {
+                let n = last[block_size - 1] as usize;
+                let mut bad = (ct_is_zero(n) | ct_lt(block_size, n)) as usize;
+                for (i, v) in last[..block_size - 1].iter().enumerate() {
+                    let diff = match self {
+                        Padding::Pkcs7 => v ^ n as u8,
+                        Padding::AnsiX923 => *v,
+                        _ => 0,
+                    };
+                    // i + n >= block_sizeならパディングの中
+                    bad |= (!ct_lt(i + n, block_size) & diff) as usize;
+                }
+                if bad != 0 {
+                    return Err(AesError::InvalidPadding);
+                }
+                n
+            }
+            Padding::Iso7816 => {
+                // 後ろから見て最初の0でないバイトが0x80でなければならない
+                let mut found = 0u8;
+                let mut bad = 0u8;
+                let mut z = 0;
+                for v in last.iter().rev() {
+                    let is_zero = ct_is_zero(*v as usize);
+                    let first = !found & !is_zero;
+                    bad |= first & (v ^ 0x80);
+                    z += (!found & is_zero & 1) as usize;
+                    found |= first;
+                }
+                if bad | !found != 0 {
+                    return Err(AesError::InvalidPadding);
+                }
+                z + 1
+            }
+            Padding::Zero => unreachable!(),
+        };
+        Ok(data.len() - n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{error::AesError, padding::Padding};
+
+    #[test]
+    fn pad() {
+        let mut b = [1u8; 8];
+        Padding::Pkcs7.pad(&mut b, 5).unwrap();
+        assert_eq!(b, [1, 1, 1, 1, 1, 3, 3, 3]);
+        Padding::AnsiX923.pad(&mut b, 5).unwrap();
+        assert_eq!(b, [1, 1, 1, 1, 1, 0, 0, 3]);
+        Padding::Iso7816.pad(&mut b, 5).unwrap();
+        assert_eq!(b, [1, 1, 1, 1, 1, 0x80, 0, 0]);
+        Padding::Iso10126.pad(&mut b, 5).unwrap();
+        assert_eq!((b[..5].to_vec(), b[7]), (vec![1; 5], 3));
+        Padding::Zero.pad(&mut b, 5).unwrap();
+        assert_eq!(b, [1, 1, 1, 1, 1, 0, 0, 0]);
+
+        assert_eq!(Padding::Pkcs7.padded_len(16, 8), 24);
+        assert_eq!(Padding::Iso7816.padded_len(15, 8), 16);
+        assert_eq!(Padding::Zero.padded_len(16, 8), 16);
+        assert_eq!(Padding::Zero.padded_len(17, 8), 24);
+
+        // ブロックがデータで埋まっていると埋める場所がない
+        for p in [Padding::Pkcs7, Padding::AnsiX923, Padding::Iso7816, Padding::Iso10126] {
+            assert_eq!(p.pad(&mut b, 8), Err(AesError::BufferTooSmall { needed: 9, len: 8 }));
+        }
+        assert_eq!(Padding::Zero.pad(&mut b, 8), Ok(()));
+        assert_eq!(Padding::Zero.pad(&mut b, 9), Err(AesError::BufferTooSmall { needed: 9, len: 8 }));
+    }
+
+    #[test]
+    fn unpad() {
+        for p in [Padding::Pkcs7, Padding::AnsiX923, Padding::Iso7816, Padding::Iso10126] {
+            for len in 0..=8 {
+                let mut b = [7u8; 16];
+                let n = p.padded_len(len, 8);
+                p.pad(&mut b[..n], len).unwrap();
+                assert_eq!(p.unpad(&b[..n], 8), Ok(len));
+            }
+            assert_eq!(p.unpad(&[], 8), Err(AesError::InvalidPadding));
+            assert_eq!(p.unpad(&[0; 7], 8), Err(AesError::NotBlockAligned { len: 7, block_size: 8 }));
+        }
+        assert_eq!(Padding::Pkcs7.unpad(&[1, 2, 3, 4, 5, 2, 3, 3], 8), Err(AesError::InvalidPadding));
+        assert_eq!(Padding::Pkcs7.unpad(&[1, 2, 3, 4, 5, 6, 7, 9], 8), Err(AesError::InvalidPadding));
+        assert_eq!(Padding::Pkcs7.unpad(&[1, 2, 3, 4, 5, 6, 7, 0], 8), Err(AesError::InvalidPadding));
+        assert_eq!(Padding::AnsiX923.unpad(&[1, 2, 3, 4, 5, 1, 0, 3], 8), Err(AesError::InvalidPadding));
+        assert_eq!(Padding::Iso7816.unpad(&[1, 2, 3, 4, 5, 0x81, 0, 0], 8), Err(AesError::InvalidPadding));
+        assert_eq!(Padding::Iso7816.unpad(&[0; 8], 8), Err(AesError::InvalidPadding));
+        assert_eq!(Padding::Pkcs7.unpad(&[9, 8, 8, 8, 8, 8, 8, 8], 8), Err(AesError::InvalidPadding));
+        assert_eq!(Padding::Pkcs7.unpad(&[8; 8], 8), Ok(0));
+        assert_eq!(Padding::AnsiX923.unpad(&[1, 0, 0, 0, 0, 0, 0, 8], 8), Err(AesError::InvalidPadding));
+        assert_eq!(Padding::Iso7816.unpad(&[0x80, 0, 0, 0, 0, 0, 0, 0], 8), Ok(0));
+        assert_eq!(Padding::Iso7816.unpad(&[0x80, 0, 0, 1, 0x80, 0, 0, 0], 8), Ok(4));
+        assert_eq!(Padding::Iso7816.unpad(&[0, 0, 0, 0, 0, 0, 0, 0x80], 8), Ok(7));
+        assert_eq!(Padding::Zero.unpad(&[1, 2, 3, 0, 5, 0, 0, 0], 8), Ok(5));
+        assert_eq!(Padding::Zero.unpad(&[], 8), Ok(0));
+    }
+}
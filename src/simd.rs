@@ -0,0 +1,128 @@
+use std::simd::u64x4;
+
+use crate::{aes::AESkey, bitslice::{decrypt_state, encrypt_state, load, store, AESBitslice, Lanes, State}, mode::BlockCipher};
+
+impl Lanes for u64x4 {
+    fn splat(v: u64) -> Self {
+        u64x4::splat(v)
+    }
+    fn shl(self, n: u32) -> Self {
+        self << n as u64
+    }
+    fn shr(self, n: u32) -> Self {
+        self >> n as u64
+    }
+}
+
+// ビットスライスの各u64をu64x4の1レーンとして並べ、16ブロックずつ処理する
+// レーンの数以外はbitslice::AESBitsliceと同じ回路を使う
+pub struct AESSimd {
+    inner: AESBitslice,
+}
+
+impl AESSimd {
+    pub fn new(key: AESkey) -> Self {
+        Self { inner: AESBitslice::new(key) }
+    }
+
+    fn load16(blocks: &[[u8; 16]; 16]) -> [u64x4; 8] {
+        let q: [State; 4] = std::array::from_fn(|i| load(blocks[4 * i..4 * i + 4].try_into().unwrap()));
+        std::array::from_fn(|j| u64x4::from_array([q[0][j], q[1][j], q[2][j], q[3][j]]))
+    }
+
+    fn store16(q: [u64x4; 8], blocks: &mut [[u8; 16]; 16]) {
+        for (i, b) in blocks.chunks_exact_mut(4).enumerate() {
+            store(q.map(|v| v[i]), b.try_into().unwrap());
+        }
+    }
+
+    fn each16(&self, blocks: &mut [[u8; 16]], f: impl Fn(&[State], &mut [u64x4; 8])) {
+        let keys = &self.inner.round_keys;
+        let mut chunks = blocks.chunks_exact_mut(16);
+        for c in &mut chunks {
+            let c: &mut [[u8; 16]; 16] = c.try_into().unwrap();
+            let mut q = Self::load16(c);
+            f(keys, &mut q);
+            Self::store16(q, c);
+        }
+        let rest = chunks.into_remainder();
+        if !rest.is_empty() {
+            let mut tmp = [[0; 16]; 16];
+            tmp[..rest.len()].copy_from_slice(rest);
+            let mut q = Self::load16(&tmp);
+            f(keys, &mut q);
+            Self::store16(q, &mut tmp);
+            rest.copy_from_slice(&tmp[..rest.len()]);
+        }
+    }
+}
+
+impl BlockCipher<16> for AESSimd {
+    // 1ブロックだけならレーンを広げても意味がない
+    fn _encrypt(&self, in_bytes: [u8; 16]) -> [u8; 16] {
+        self.inner._encrypt(in_bytes)
+    }
+
+    fn _decrypt(&self, in_bytes: [u8; 16]) -> [u8; 16] {
+        self.inner._decrypt(in_bytes)
+    }
+
+    fn _encrypt_blocks(&self, blocks: &mut [[u8; 16]]) {
+        self.each16(blocks, encrypt_state);
+    }
+
+    fn _decrypt_blocks(&self, blocks: &mut [[u8; 16]]) {
+        self.each16(blocks, decrypt_state);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::array;
+
+    use crate::aes::{AESkey, AES};
+    use crate::mode::BlockCipher;
+    use crate::simd::AESSimd;
+
+    #[test]
+    fn encrypt() {
+        let a = AESSimd::new(
+            AESkey::K128(*b"!\xf4\x02\xf2[\x1a\x0f\xd7\"\xb81i\xe1\x05\t\xf8")
+        );
+        let mut b = [*b"s\xdf\xffW\xfe$\xe8\x07\xbdO\xb1\xbcN\x07\xcds"; 17];
+        a._encrypt_blocks(&mut b);
+        assert!(b.iter().all(|v| *v == *b"\x9c)\xe4l\xf1\xce\x04\xe8=:k\x16{{\xe1J"));
+        a._decrypt_blocks(&mut b);
+        assert!(b.iter().all(|v| *v == *b"s\xdf\xffW\xfe$\xe8\x07\xbdO\xb1\xbcN\x07\xcds"));
+
+        let c = AESSimd::new(
+            AESkey::K256(*b"\xa8\x19@\x8c\xe5\x01\x0c\xa2\xe0\x9e\xf5\x9a\xc3\xd8\x9f_\xf8Y]\x02\xb5$\xe6\x1b\xf8\xaf\xa8\x94\xa9]YO")
+        );
+        let mut b = [*b"s\xdf\xffW\xfe$\xe8\x07\xbdO\xb1\xbcN\x07\xcds"; 17];
+        c._encrypt_blocks(&mut b);
+        assert!(b.iter().all(|v| *v == *b"e\x13\xa2\xa4\xc7R\xca@3\xc0\xde\xf6\xab:\xe8\xcb"));
+    }
+
+    #[test]
+    fn block_cipher() {
+        let key = *b"\x01kG\xc4\xa2XI\nRA\xea\xc9m\xde\x81\xb8\"\xbd \xd5_\xa2A\x0e";
+        let soft = AES::new(AESkey::K192(key));
+        let s = AESSimd::new(AESkey::K192(key));
+        let data: [u8; 16 * 37] = array::from_fn(|i| (i * 7 + 3) as u8);
+        let iv: [u8; 16] = array::from_fn(|i| i as u8);
+        let mut ans = [0u8; 16 * 37];
+        let mut out = [0u8; 16 * 37];
+        let mut dec = [0u8; 16 * 37];
+        soft.encrypt_ecb(&data, &mut ans);
+        s.encrypt_ecb(&data, &mut out);
+        assert_eq!(ans, out);
+
+        soft.encrypt_cbc(&data, iv, &mut ans);
+        s.decrypt_cbc(&ans, iv, &mut dec);
+        assert_eq!(data, dec);
+
+        soft.encrypt_ctr(&data[1..], b"0123", &mut ans[1..]);
+        s.encrypt_ctr(&data[1..], b"0123", &mut out[1..]);
+        assert_eq!(ans[1..], out[1..]);
+    }
+}
@@ -0,0 +1,152 @@
+use crate::{cmac::{dbl, Cmac}, error::AesError, mode::{ct_eq, ctr128, BlockCipher}};
+
+// S2Vに渡せるAADの要素数
+const MAX_AD: usize = 126;
+
+// AES-SIV (RFC 5297)。鍵の前半をS2V(CMAC)に、後半をCTRに使う
+pub struct Siv<C: BlockCipher<16>> {
+    mac: Cmac<C>,
+    ctr: C,
+}
+
+impl<C: BlockCipher<16>> Siv<C> {
+    pub fn new(mac_cipher: C, ctr_cipher: C) -> Self {
+        Self { mac: Cmac::new(mac_cipher), ctr: ctr_cipher }
+    }
+
+    fn s2v(&self, ad: &[&[u8]], plaintext: &[u8]) -> [u8; 16] {
+        let mut d = self.mac.mac(&[0; 16]);
+        for a in ad {
+            let m = self.mac.mac(a);
+            d = dbl(d);
+            for (x, y) in d.iter_mut().zip(m) {
+                *x ^= y;
+            }
+        }
+        let mut s = self.mac.start();
+        if plaintext.len() >= 16 {
+            // 平文の最後の16バイトにDを混ぜる
+            let (head, tail) = plaintext.split_at(plaintext.len() - 16);
+            s.update(head);
+            for (x, y) in d.iter_mut().zip(tail) {
+                *x ^= y;
+            }
+        } else {
+            d = dbl(d);
+            d[plaintext.len()] ^= 0x80;
+            for (x, y) in d.iter_mut().zip(plaintext) {
+                *x ^= y;
+            }
+        }
+        s.update(&d);
+        s.finalize()
+    }
+
+    fn check(&self, ad: &[&[u8]], in_len: usize, out_len: usize) -> Result<(), AesError> {
+        if in_len != out_len {
+            return Err(AesError::LengthMismatch { in_len, out_len });
+        }
+        if ad.len() > MAX_AD {
+            return Err(AesError::TooManyAssociatedData { count: ad.len(), max: MAX_AD });
+        }
+        Ok(())
+    }
+
+    // 31bit目と63bit目を落としたSIVをカウンタの初期値にする
+    fn ctr(&self, siv: &[u8; 16], in_bytes: &[u8], out_bytes: &mut [u8]) {
+        let mut q = *siv;
+        q[8] &= 0x7f;
+        q[12] &= 0x7f;
+        ctr128(&self.ctr, q, in_bytes, out_bytes);
+    }
+
+    // 決定的暗号化。同じ入力からは同じ暗号文になる
+    pub fn encrypt(&self, ad: &[&[u8]], in_bytes: &[u8], out_bytes: &mut [u8], siv: &mut [u8; 16]) -> Result<(), AesError> {
+        self.check(ad, in_bytes.len(), out_bytes.len())?;
+        *siv = self.s2v(ad, in_bytes);
+        self.ctr(siv, in_bytes, out_bytes);
+        Ok(())
+    }
+
+    // SIVが一致しなければout_bytesは0で埋める
+    pub fn decrypt(&self, ad: &[&[u8]], in_bytes: &[u8], siv: &[u8; 16], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self.check(ad, in_bytes.len(), out_bytes.len())?;
+        self.ctr(siv, in_bytes, out_bytes);
+        if !ct_eq(&self.s2v(ad, out_bytes), siv) {
+            out_bytes.fill(0);
+            return Err(AesError::AuthenticationFailed);
+        }
+        Ok(())
+    }
+
+    // nonceをAADの最後の要素として使う
+    pub fn encrypt_with_nonce(&self, nonce: &[u8], aad: &[u8], in_bytes: &[u8], out_bytes: &mut [u8], siv: &mut [u8; 16]) -> Result<(), AesError> {
+        self.encrypt(&[aad, nonce], in_bytes, out_bytes, siv)
+    }
+
+    pub fn decrypt_with_nonce(&self, nonce: &[u8], aad: &[u8], in_bytes: &[u8], siv: &[u8; 16], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self.decrypt(&[aad, nonce], in_bytes, siv, out_bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{aes::{AESkey, AES}, error::AesError, siv::Siv};
+
+    const K1: &[u8; 32] = b"\xff\xfe\xfd\xfc\xfb\xfa\xf9\xf8\xf7\xf6\xf5\xf4\xf3\xf2\xf1\xf0\xf0\xf1\xf2\xf3\xf4\xf5\xf6\xf7\xf8\xf9\xfa\xfb\xfc\xfd\xfe\xff";
+    const AD1: &[u8; 24] = b"\x10\x11\x12\x13\x14\x15\x16\x17\x18\x19\x1a\x1b\x1c\x1d\x1e\x1f !\"#$%&'";
+    const P1: &[u8; 14] = b"\x11\"3DUfw\x88\x99\xaa\xbb\xcc\xdd\xee";
+    const C1: &[u8; 30] = b"\x85c-\x07\xc6\xe8\xf3\x7f\x95\n\xcd2\n.\xcc\x93@\xc0+\x96\x90\xc4\xdc\x04\xda\xef\x7fj\xfe\\";
+
+    const K2: &[u8; 32] = b"\x7f~}|{zyxwvutsrqp@ABCDEFGHIJKLMNO";
+    const AD2: [&[u8]; 2] = [
+        b"\x00\x11\"3DUfw\x88\x99\xaa\xbb\xcc\xdd\xee\xff\xde\xad\xda\xda\xde\xad\xda\xda\xff\xee\xdd\xcc\xbb\xaa\x99\x88wfUD3\"\x11\x00",
+        b"\x10 0@P`p\x80\x90\xa0",
+    ];
+    const N2: &[u8; 16] = b"\t\xf9\x11\x02\x9dt\xe3[\xd8AV\xc5cV\x88\xc0";
+    const P2: &[u8; 47] = b"this is some plaintext to encrypt using SIV-AES";
+    const C2: &[u8; 63] = b"{\xdbn;C&g\xeb\x06\xf4\xd1K\xff/\xbd\x0f\xcb\x90\x0f/\xdd\xbe@C&`\x19e\xc8\x89\xbf\x17\xdb\xa7|\xeb\tO\xa6c\xb7\xa3\xf7H\xba\x8a\xf8)\xead\xadTJ'.\x9cH[b\xa3\xfd\\\r";
+
+    fn siv128(k: &[u8; 32]) -> Siv<AES> {
+        Siv::new(AES::new(AESkey::K128(k[..16].try_into().unwrap())), AES::new(AESkey::K128(k[16..].try_into().unwrap())))
+    }
+
+    // RFC 5297 付録A
+    #[test]
+    fn encrypt() {
+        let s = siv128(K1);
+        let mut out = [0; 14];
+        let mut siv = [0; 16];
+        s.encrypt(&[AD1], P1, &mut out, &mut siv).unwrap();
+        assert_eq!(siv, C1[..16]);
+        assert_eq!(out, C1[16..]);
+
+        let s = siv128(K2);
+        let mut out = [0; 47];
+        s.encrypt(&[AD2[0], AD2[1], N2], P2, &mut out, &mut siv).unwrap();
+        assert_eq!(siv, C2[..16]);
+        assert_eq!(out, C2[16..]);
+
+        // 256bit鍵、空のAADと16バイト未満の平文
+        let k: [u8; 64] = std::array::from_fn(|i| i as u8);
+        let s = Siv::new(AES::new(AESkey::K256(k[..32].try_into().unwrap())), AES::new(AESkey::K256(k[32..].try_into().unwrap())));
+        let mut out = [0; 3];
+        s.encrypt_with_nonce(&[b'x'; 40], b"", b"abc", &mut out, &mut siv).unwrap();
+        assert_eq!(siv, *b"\xb6\x05\x81\xc5+\x8dT!b\x02i\xc5\xb4\xad\xccV");
+        assert_eq!(out, *b"\xd9\x13|");
+    }
+
+    #[test]
+    fn decrypt() {
+        let s = siv128(K2);
+        let mut out = [0; 47];
+        let siv = C2[..16].try_into().unwrap();
+        s.decrypt(&[AD2[0], AD2[1], N2], &C2[16..], &siv, &mut out).unwrap();
+        assert_eq!(out, *P2);
+
+        assert_eq!(s.decrypt(&[AD2[0], N2], &C2[16..], &siv, &mut out), Err(AesError::AuthenticationFailed));
+        assert_eq!(out, [0; 47]);
+        let many = [&b""[..]; 127];
+        assert_eq!(s.decrypt(&many, &C2[16..], &siv, &mut out), Err(AesError::TooManyAssociatedData { count: 127, max: 126 }));
+    }
+}
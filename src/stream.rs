@@ -0,0 +1,658 @@
+use crate::{error::AesError, gcm::{Gcm, MAX_TEXT_LEN}, ghash::GHash, mode::{ct_eq, ctr_blocks, BlockCipher, Counter, CounterFormat, CtrCounter, DefaultCounter, Wraparound}, padding::Padding};
+
+// 半端なブロックをためておきながら少しずつ処理するCBC
+struct Cbc<'a, C: BlockCipher<T>, const T: usize> {
+    cipher: &'a C,
+    prev: [u8; T],
+    buf: [u8; T],
+    len: usize,
+    total: u64,
+    padding: Option<Padding>,
+    is_encrypt: bool,
+}
+
+impl<'a, C: BlockCipher<T>, const T: usize> Cbc<'a, C, T> {
+    fn blocks(&mut self, in_bytes: &[u8], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        if in_bytes.is_empty() {
+            return Ok(());
+        }
+        let last = in_bytes.len() - T;
+        if self.is_encrypt {
+            self.cipher.try_encrypt_cbc(in_bytes, self.prev, out_bytes)?;
+            self.prev.copy_from_slice(&out_bytes[last..]);
+        } else {
+            self.cipher.try_decrypt_cbc(in_bytes, self.prev, out_bytes)?;
+            self.prev.copy_from_slice(&in_bytes[last..]);
+        }
+        Ok(())
+    }
+
+    fn update(&mut self, in_bytes: &[u8], out_bytes: &mut [u8]) -> Result<usize, AesError> {
+        let avail = self.len + in_bytes.len();
+        let mut n = avail / T * T;
+        // パディングを外すまで最後のブロックは出力しない
+        if !self.is_encrypt && self.padding.is_some() && n == avail && n > 0 {
+            n -= T;
+        }
+        if out_bytes.len() < n {
+            return Err(AesError::BufferTooSmall { needed: n, len: out_bytes.len() });
+        }
+        self.total += in_bytes.len() as u64;
+        if n == 0 {
+            self.buf[self.len..avail].copy_from_slice(in_bytes);
+            self.len = avail;
+            return Ok(0);
+        }
+        let take = T - self.len;
+        self.buf[self.len..].copy_from_slice(&in_bytes[..take]);
+        let first = self.buf;
+        self.blocks(&first, &mut out_bytes[..T])?;
+        let end = take + n - T;
+        self.blocks(&in_bytes[take..end], &mut out_bytes[T..n])?;
+        self.len = in_bytes.len() - end;
+        self.buf[..self.len].copy_from_slice(&in_bytes[end..]);
+        Ok(n)
+    }
+
+    fn finalize(mut self, out_bytes: &mut [u8]) -> Result<usize, AesError> {
+        let not_aligned = AesError::NotBlockAligned { len: self.total as usize, block_size: T };
+        let Some(padding) = self.padding else {
+            return if self.len == 0 { Ok(0) } else { Err(not_aligned) };
+        };
+        if self.is_encrypt {
+            let n = padding.padded_len(self.len, T);
+            if out_bytes.len() < n {
+                return Err(AesError::BufferTooSmall { needed: n, len: out_bytes.len() });
+            }
+            if n == 0 {
+                return Ok(0);
+            }
+            let mut last = self.buf;
+            padding.pad(&mut last, self.len)?;
+            self.blocks(&last, &mut out_bytes[..T])?;
+            return Ok(T);
+        }
+        if self.len != 0 && self.len != T {
+            return Err(not_aligned);
+        }
+        let mut last = [0; T];
+        let buf = self.buf;
+        self.blocks(&buf[..self.len], &mut last[..self.len])?;
+        let n = padding.unpad(&last[..self.len], T)?;
+        if out_bytes.len() < n {
+            return Err(AesError::BufferTooSmall { needed: n, len: out_bytes.len() });
+        }
+        out_bytes[..n].copy_from_slice(&last[..n]);
+        Ok(n)
+    }
+}
+
+// 任意の長さに分けて渡せるCBC暗号化。ブロックの半端は次のupdateかfinalizeまでためておく
+pub struct CbcEncryptor<'a, C: BlockCipher<T>, const T: usize>(Cbc<'a, C, T>);
+
+impl<'a, C: BlockCipher<T>, const T: usize> CbcEncryptor<'a, C, T> {
+    // 入力全体の長さがTの倍数でなければfinalizeでエラーになる
+    pub fn new(cipher: &'a C, iv: [u8; T]) -> Self {
+        Self(Cbc { cipher, prev: iv, buf: [0; T], len: 0, total: 0, padding: None, is_encrypt: true })
+    }
+    pub fn new_padded(cipher: &'a C, iv: [u8; T], padding: Padding) -> Self {
+        Self(Cbc { cipher, prev: iv, buf: [0; T], len: 0, total: 0, padding: Some(padding), is_encrypt: true })
+    }
+
+    // 書き込んだバイト数を返す。out_bytesはin_bytes.len() + Tバイトあれば足りる
+    pub fn update(&mut self, in_bytes: &[u8], out_bytes: &mut [u8]) -> Result<usize, AesError> {
+        self.0.update(in_bytes, out_bytes)
+    }
+
+    // 残りを埋めて暗号化する。out_bytesはTバイトあれば足りる
+    pub fn finalize(self, out_bytes: &mut [u8]) -> Result<usize, AesError> {
+        self.0.finalize(out_bytes)
+    }
+}
+
+// 任意の長さに分けて渡せるCBC復号。パディングありでは最後のブロックをfinalizeまで出力しない
+pub struct CbcDecryptor<'a, C: BlockCipher<T>, const T: usize>(Cbc<'a, C, T>);
+
+impl<'a, C: BlockCipher<T>, const T: usize> CbcDecryptor<'a, C, T> {
+    pub fn new(cipher: &'a C, iv: [u8; T]) -> Self {
+        Self(Cbc { cipher, prev: iv, buf: [0; T], len: 0, total: 0, padding: None, is_encrypt: false })
+    }
+    pub fn new_padded(cipher: &'a C, iv: [u8; T], padding: Padding) -> Self {
+        Self(Cbc { cipher, prev: iv, buf: [0; T], len: 0, total: 0, padding: Some(padding), is_encrypt: false })
+    }
+
+    // 書き込んだバイト数を返す。out_bytesはin_bytes.len() + Tバイトあれば足りる
+    pub fn update(&mut self, in_bytes: &[u8], out_bytes: &mut [u8]) -> Result<usize, AesError> {
+        self.0.update(in_bytes, out_bytes)
+    }
+
+    // パディングを外した残りを書き込む。out_bytesはTバイトあれば足りる
+    pub fn finalize(self, out_bytes: &mut [u8]) -> Result<usize, AesError> {
+        self.0.finalize(out_bytes)
+    }
+}
+
+// セグメントの途中で区切られても続きから処理できるCFB
+struct Cfb<'a, C: BlockCipher<T>, const T: usize> {
+    cipher: &'a C,
+    e: [u8; T],
+    e1: [u8; T],
+    block: usize,
+    pos: usize,
+    is_encrypt: bool,
+}
+
+impl<'a, C: BlockCipher<T>, const T: usize> Cfb<'a, C, T> {
+    fn new(cipher: &'a C, iv: [u8; T], n: usize, is_encrypt: bool) -> Result<Self, AesError> {
+        if n == 0 || !n.is_multiple_of(8) || n > T * 8 {
+            return Err(AesError::InvalidSegmentSize(n));
+        }
+        Ok(Self { cipher, e: iv, e1: iv, block: n / 8, pos: 0, is_encrypt })
+    }
+
+    fn update(&mut self, in_bytes: &[u8], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self.cipher._check2(in_bytes.len(), out_bytes.len())?;
+        let block = self.block;
+        for (ib, ob) in in_bytes.iter().zip(out_bytes.iter_mut()) {
+            if self.pos == 0 {
+                self.e1 = self.cipher._encrypt(self.e);
+                self.e.copy_within(block.., 0);
+            }
+            *ob = self.e1[self.pos] ^ *ib;
+            self.e[T - block + self.pos] = if self.is_encrypt { *ob } else { *ib };
+            self.pos = (self.pos + 1) % block;
+        }
+        Ok(())
+    }
+}
+
+pub struct CfbEncryptor<'a, C: BlockCipher<T>, const T: usize>(Cfb<'a, C, T>);
+
+impl<'a, C: BlockCipher<T>, const T: usize> CfbEncryptor<'a, C, T> {
+    pub fn new(cipher: &'a C, iv: [u8; T]) -> Self {
+        Self(Cfb { cipher, e: iv, e1: iv, block: T, pos: 0, is_encrypt: true })
+    }
+    // nはセグメント長(bit)
+    pub fn new_n(cipher: &'a C, iv: [u8; T], n: usize) -> Result<Self, AesError> {
+        Cfb::new(cipher, iv, n, true).map(Self)
+    }
+
+    pub fn update(&mut self, in_bytes: &[u8], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self.0.update(in_bytes, out_bytes)
+    }
+}
+
+pub struct CfbDecryptor<'a, C: BlockCipher<T>, const T: usize>(Cfb<'a, C, T>);
+
+impl<'a, C: BlockCipher<T>, const T: usize> CfbDecryptor<'a, C, T> {
+    pub fn new(cipher: &'a C, iv: [u8; T]) -> Self {
+        Self(Cfb { cipher, e: iv, e1: iv, block: T, pos: 0, is_encrypt: false })
+    }
+    pub fn new_n(cipher: &'a C, iv: [u8; T], n: usize) -> Result<Self, AesError> {
+        Cfb::new(cipher, iv, n, false).map(Self)
+    }
+
+    pub fn update(&mut self, in_bytes: &[u8], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self.0.update(in_bytes, out_bytes)
+    }
+}
+
+// OFBの鍵ストリーム。暗号化と復号は同じ
+pub struct Ofb<'a, C: BlockCipher<T>, const T: usize> {
+    cipher: &'a C,
+    e: [u8; T],
+    pos: usize,
+}
+
+impl<'a, C: BlockCipher<T>, const T: usize> Ofb<'a, C, T> {
+    pub fn new(cipher: &'a C, iv: [u8; T]) -> Self {
+        Self { cipher, e: iv, pos: T }
+    }
+
+    pub fn update(&mut self, in_bytes: &[u8], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self.cipher._check2(in_bytes.len(), out_bytes.len())?;
+        for (ib, ob) in in_bytes.iter().zip(out_bytes.iter_mut()) {
+            if self.pos == T {
+                self.e = self.cipher._encrypt(self.e);
+                self.pos = 0;
+            }
+            *ob = *ib ^ self.e[self.pos];
+            self.pos += 1;
+        }
+        Ok(())
+    }
+}
+
+// CTRの鍵ストリーム
+pub struct Ctr<'a, C: BlockCipher<T>, const T: usize> {
+    cipher: &'a C,
+    counter: Box<dyn CtrCounter<T> + 'a>,
+    e: [u8; T],
+    pos: usize,
+    // 先頭からのバイト位置
+    offset: u64,
+}
+
+impl<'a, C: BlockCipher<T>, const T: usize> Ctr<'a, C, T> {
+    // カウンタはencrypt_ctrと同じくnonceの後ろ全体を使う
+    pub fn new(cipher: &'a C, nonce: &[u8]) -> Result<Self, AesError> {
+        if nonce.len() >= T {
+            return Err(AesError::InvalidNonceLength { len: nonce.len(), max: T - 1 });
+        }
+        Ok(Self::with_counter(cipher, DefaultCounter::new(nonce)))
+    }
+    pub fn with_counter(cipher: &'a C, counter: impl CtrCounter<T> + 'a) -> Self {
+        Self { cipher, counter: Box::new(counter), e: [0; T], pos: T, offset: 0 }
+    }
+
+    // カウンタが足りなければCounterOverflowを返し、位置は変わらない
+    pub fn update(&mut self, in_bytes: &[u8], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self.cipher._check2(in_bytes.len(), out_bytes.len())?;
+        let head = in_bytes.len().min(T - self.pos);
+        let blocks = (in_bytes.len() - head).div_ceil(T) as u128;
+        if self.counter.remaining().is_some_and(|r| blocks > r) {
+            return Err(AesError::CounterOverflow);
+        }
+        self.crypt(in_bytes, out_bytes, head).inspect_err(|_| {
+            // 途中で失敗したら出力を捨てて元の位置に戻す
+            out_bytes.fill(0);
+            let _ = self.seek(self.offset);
+        })?;
+        self.offset += in_bytes.len() as u64;
+        Ok(())
+    }
+
+    fn crypt(&mut self, in_bytes: &[u8], out_bytes: &mut [u8], head: usize) -> Result<(), AesError> {
+        // 前回の鍵ストリームの残りを先に使う
+        for (ib, ob) in in_bytes[..head].iter().zip(out_bytes[..head].iter_mut()) {
+            *ob = *ib ^ self.e[self.pos];
+            self.pos += 1;
+        }
+        let full = head + (in_bytes.len() - head) / T * T;
+        let counter = &mut self.counter;
+        ctr_blocks(self.cipher, &in_bytes[head..full], &mut out_bytes[head..full], || counter.next())?;
+        if full < in_bytes.len() {
+            self.e = self.cipher._encrypt(self.counter.next()?);
+            for (i, (x, y)) in in_bytes[full..].iter().zip(out_bytes[full..].iter_mut()).enumerate() {
+                *y = *x ^ self.e[i];
+            }
+            self.pos = in_bytes.len() - full;
+        }
+        Ok(())
+    }
+
+    pub fn position(&self) -> u64 {
+        self.offset
+    }
+
+    // 鍵ストリームの残りのバイト数。制限がないかu64に収まらなければNone
+    pub fn remaining(&self) -> Option<u64> {
+        let r = self.counter.remaining()?.checked_mul(T as u128)?.checked_add((T - self.pos) as u128)?;
+        r.try_into().ok()
+    }
+
+    // 鍵ストリームのoffsetバイト目から続ける。カウンタに収まらない位置ならCounterOverflow
+    pub fn seek(&mut self, offset: u64) -> Result<(), AesError> {
+        let block = (offset / T as u64) as u128;
+        let pos = (offset % T as u64) as usize;
+        if pos == 0 {
+            // 鍵ストリームの終わりちょうどにも移動できるよう、一つ前のカウンタを使い切った状態にする
+            if let Err(e) = self.counter.seek(block) {
+                if block == 0 {
+                    return Err(e);
+                }
+                self.counter.seek(block - 1)?;
+                self.counter.next()?;
+            }
+            self.pos = T;
+        } else {
+            self.counter.seek(block)?;
+            self.e = self.cipher._encrypt(self.counter.next()?);
+            self.pos = pos;
+        }
+        self.offset = offset;
+        Ok(())
+    }
+}
+
+// 少しずつ処理するGCM。AADはnewでまとめて渡す
+struct GcmStream<'a, C: BlockCipher<16>> {
+    gcm: &'a Gcm<C>,
+    ctr: Ctr<'a, C, 16>,
+    ghash: GHash,
+    j0: [u8; 16],
+    aad_len: usize,
+    total: u64,
+    is_encrypt: bool,
+}
+
+impl<'a, C: BlockCipher<16>> GcmStream<'a, C> {
+    fn new(gcm: &'a Gcm<C>, iv: &[u8], aad: &[u8], is_encrypt: bool) -> Result<Self, AesError> {
+        let (j0, ghash) = gcm.start(iv, aad)?;
+        // 平文の長さの上限までは下位32bitが一周しない。J0の次のブロックから使う
+        let mut ctr = Ctr::with_counter(gcm.cipher(), Counter::new(j0, CounterFormat::Be32, Wraparound::Wrap));
+        ctr.seek(16)?;
+        Ok(Self { gcm, ctr, ghash, j0, aad_len: aad.len(), total: 0, is_encrypt })
+    }
+
+    fn update(&mut self, in_bytes: &[u8], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        let len = self.total + in_bytes.len() as u64;
+        if len > MAX_TEXT_LEN {
+            return Err(AesError::TooLong { len, max: MAX_TEXT_LEN });
+        }
+        self.ctr.update(in_bytes, out_bytes)?;
+        self.ghash.update(if self.is_encrypt { out_bytes } else { in_bytes });
+        self.total = len;
+        Ok(())
+    }
+
+    fn tag(self, tag_len: usize) -> Result<[u8; 16], AesError> {
+        self.gcm.finish(self.j0, self.ghash, self.aad_len, self.total, tag_len)
+    }
+}
+
+// 任意の長さに分けて渡せるGCM暗号化。finalizeでタグを書き込む
+pub struct GcmEncryptor<'a, C: BlockCipher<16>>(GcmStream<'a, C>);
+
+impl<'a, C: BlockCipher<16>> GcmEncryptor<'a, C> {
+    pub fn new(gcm: &'a Gcm<C>, iv: &[u8], aad: &[u8]) -> Result<Self, AesError> {
+        GcmStream::new(gcm, iv, aad, true).map(Self)
+    }
+
+    pub fn update(&mut self, in_bytes: &[u8], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self.0.update(in_bytes, out_bytes)
+    }
+
+    // tagの長さ(4, 8, 12..=16バイト)で切り詰めたタグを書き込む
+    pub fn finalize(self, tag: &mut [u8]) -> Result<(), AesError> {
+        let t = self.0.tag(tag.len())?;
+        let n = tag.len();
+        tag.copy_from_slice(&t[..n]);
+        Ok(())
+    }
+}
+
+// 任意の長さに分けて渡せるGCM復号。finalizeでタグを確かめるまで、updateで出力した平文は信用できない
+pub struct GcmDecryptor<'a, C: BlockCipher<16>>(GcmStream<'a, C>);
+
+impl<'a, C: BlockCipher<16>> GcmDecryptor<'a, C> {
+    pub fn new(gcm: &'a Gcm<C>, iv: &[u8], aad: &[u8]) -> Result<Self, AesError> {
+        GcmStream::new(gcm, iv, aad, false).map(Self)
+    }
+
+    pub fn update(&mut self, in_bytes: &[u8], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self.0.update(in_bytes, out_bytes)
+    }
+
+    // タグが一致しなければAuthenticationFailed
+    pub fn finalize(self, tag: &[u8]) -> Result<(), AesError> {
+        let t = self.0.tag(tag.len())?;
+        if !ct_eq(&t[..tag.len()], tag) {
+            return Err(AesError::AuthenticationFailed);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::array;
+
+    use crate::{aes::{AESkey, AES}, error::AesError, gcm::Gcm, mode::{BlockCipher, Counter, CounterFormat, Wraparound}, padding::Padding};
+
+    use super::{CbcDecryptor, CbcEncryptor, CfbDecryptor, CfbEncryptor, Ctr, GcmDecryptor, GcmEncryptor, Ofb};
+
+    const CHUNKS: [usize; 8] = [0, 1, 15, 16, 17, 5, 600, 33];
+
+    fn cipher() -> AES {
+        AES::new(AESkey::K128(array::from_fn(|i| i as u8 * 3)))
+    }
+
+    // dataをCHUNKSの長さで区切ってfに渡す
+    fn chunked(data: &[u8], mut f: impl FnMut(&[u8])) {
+        let mut rest = data;
+        for &n in CHUNKS.iter().cycle() {
+            if rest.is_empty() {
+                break;
+            }
+            let (a, b) = rest.split_at(n.min(rest.len()));
+            f(a);
+            rest = b;
+        }
+    }
+
+    #[test]
+    fn cbc() {
+        let c = cipher();
+        let iv = [7; 16];
+        let p: [u8; 2000] = array::from_fn(|i| (i * 13) as u8);
+        for len in [0, 15, 16, 47, 48, 1000, 2000] {
+            for padding in [Padding::Pkcs7, Padding::Iso7816, Padding::Zero] {
+                let expect = c.encrypt_cbc_padded(&p[..len], iv, padding);
+                let mut enc = CbcEncryptor::new_padded(&c, iv, padding);
+                let mut out = vec![];
+                chunked(&p[..len], |x| {
+                    let mut o = vec![0; x.len() + 16];
+                    let n = enc.update(x, &mut o).unwrap();
+                    out.extend_from_slice(&o[..n]);
+                });
+                let mut o = [0; 16];
+                let n = enc.finalize(&mut o).unwrap();
+                out.extend_from_slice(&o[..n]);
+                assert_eq!(out, expect);
+
+                let mut dec = CbcDecryptor::new_padded(&c, iv, padding);
+                let mut back = vec![];
+                chunked(&expect, |x| {
+                    let mut o = vec![0; x.len() + 16];
+                    let n = dec.update(x, &mut o).unwrap();
+                    back.extend_from_slice(&o[..n]);
+                });
+                let n = dec.finalize(&mut o).unwrap();
+                back.extend_from_slice(&o[..n]);
+                assert_eq!(back, c.decrypt_cbc_padded(&expect, iv, padding).unwrap());
+            }
+        }
+
+        // パディングなし
+        let mut expect = [0; 64];
+        c.encrypt_cbc(&p[..64], iv, &mut expect);
+        let mut enc = CbcEncryptor::new(&c, iv);
+        let mut out = [0; 80];
+        let n = enc.update(&p[..20], &mut out).unwrap();
+        let n = n + enc.update(&p[20..64], &mut out[n..]).unwrap();
+        assert_eq!(n, 64);
+        assert_eq!(enc.finalize(&mut []), Ok(0));
+        assert_eq!(out[..64], expect);
+
+        let mut dec = CbcDecryptor::new(&c, iv);
+        let n = dec.update(&expect[..40], &mut out).unwrap();
+        assert_eq!(n, 32);
+        let mut short = [0; 16];
+        assert_eq!(dec.update(&expect[40..], &mut short), Err(AesError::BufferTooSmall { needed: 32, len: 16 }));
+        assert_eq!(dec.update(&expect[40..], &mut out[32..]), Ok(32));
+        assert_eq!(out[..64], p[..64]);
+
+        let mut enc = CbcEncryptor::new(&c, iv);
+        enc.update(&p[..20], &mut out).unwrap();
+        assert_eq!(enc.finalize(&mut []), Err(AesError::NotBlockAligned { len: 20, block_size: 16 }));
+
+        let mut dec = CbcDecryptor::new_padded(&c, iv, Padding::Pkcs7);
+        dec.update(&expect[..32], &mut out).unwrap();
+        assert_eq!(dec.finalize(&mut out), Err(AesError::InvalidPadding));
+    }
+
+    #[test]
+    fn stream_modes() {
+        let c = cipher();
+        let iv = [9; 16];
+        let p: [u8; 1500] = array::from_fn(|i| (i * 5 + 1) as u8);
+
+        for n in [8, 64, 128] {
+            let mut expect = [0; 1500];
+            c.encrypt_cfb_n(&p, iv, &mut expect, n);
+            let mut enc = CfbEncryptor::new_n(&c, iv, n).unwrap();
+            let mut dec = CfbDecryptor::new_n(&c, iv, n).unwrap();
+            let mut out = vec![];
+            let mut back = vec![];
+            chunked(&p, |x| {
+                let mut o = vec![0; x.len()];
+                enc.update(x, &mut o).unwrap();
+                out.extend_from_slice(&o);
+            });
+            chunked(&expect, |x| {
+                let mut o = vec![0; x.len()];
+                dec.update(x, &mut o).unwrap();
+                back.extend_from_slice(&o);
+            });
+            assert_eq!(out, expect);
+            assert_eq!(back, p);
+        }
+        assert!(CfbEncryptor::new_n(&c, iv, 12).is_err());
+
+        let mut expect = [0; 1500];
+        c.encrypt_ofb(&p, iv, &mut expect);
+        let mut ofb = Ofb::new(&c, iv);
+        let mut out = vec![];
+        chunked(&p, |x| {
+            let mut o = vec![0; x.len()];
+            ofb.update(x, &mut o).unwrap();
+            out.extend_from_slice(&o);
+        });
+        assert_eq!(out, expect);
+
+        let nonce = [1, 2, 3, 4, 5, 6, 7, 8];
+        c.encrypt_ctr(&p, &nonce, &mut expect);
+        let mut ctr = Ctr::new(&c, &nonce).unwrap();
+        let mut out = vec![];
+        chunked(&p, |x| {
+            let mut o = vec![0; x.len()];
+            ctr.update(x, &mut o).unwrap();
+            out.extend_from_slice(&o);
+        });
+        assert_eq!(out, expect);
+
+        // カウンタが一周したら続けられない
+        let mut ctr = Ctr::new(&c, &[0; 15]).unwrap();
+        let mut o = [0; 256 * 16];
+        ctr.update(&[0; 256 * 16], &mut o).unwrap();
+        assert_eq!(ctr.update(&[0], &mut [0]), Err(AesError::CounterOverflow));
+        // 終わりちょうどには移動できる
+        ctr.seek(ctr.position()).unwrap();
+        assert_eq!(ctr.update(&[0], &mut [0]), Err(AesError::CounterOverflow));
+        ctr.seek(255 * 16).unwrap();
+        ctr.update(&[0; 16], &mut o[..16]).unwrap();
+        assert_eq!(ctr.position(), 256 * 16);
+        assert_eq!(ctr.seek(256 * 16 + 1), Err(AesError::CounterOverflow));
+        assert_eq!(ctr.seek(257 * 16), Err(AesError::CounterOverflow));
+        assert_eq!(Ctr::new(&c, &[0; 16]).err(), Some(AesError::InvalidNonceLength { len: 16, max: 15 }));
+    }
+
+    #[test]
+    fn ctr_seek() {
+        let c = cipher();
+        let nonce = [3; 12];
+        let p: [u8; 1000] = array::from_fn(|i| (i * 3) as u8);
+        let mut expect = [0; 1000];
+        c.encrypt_ctr(&p, &nonce, &mut expect);
+        let mut ctr = Ctr::new(&c, &nonce).unwrap();
+        let mut out = [0; 1000];
+        for (start, end) in [(500, 530), (0, 1), (17, 33), (32, 1000), (999, 1000), (0, 1000)] {
+            ctr.seek(start as u64).unwrap();
+            ctr.update(&p[start..end], &mut out[start..end]).unwrap();
+            assert_eq!(out[start..end], expect[start..end]);
+            assert_eq!(ctr.position(), end as u64);
+        }
+
+        // 32bitのカウンタの範囲外
+        assert_eq!(ctr.seek((16 << 32) + 1), Err(AesError::CounterOverflow));
+        assert_eq!(ctr.seek(17 << 32), Err(AesError::CounterOverflow));
+        let mut ctr = Ctr::new(&c, &nonce).unwrap();
+        ctr.seek((16 << 32) - 8).unwrap();
+        ctr.update(&[0; 8], &mut [0; 8]).unwrap();
+        assert_eq!(ctr.update(&[0], &mut [0]), Err(AesError::CounterOverflow));
+
+        // 足りないときは何も書かず、位置も進めない
+        let end = 16 << 32;
+        ctr.seek(end - 20).unwrap();
+        let mut o = [0x55; 40];
+        assert_eq!(ctr.update(&[0; 40], &mut o), Err(AesError::CounterOverflow));
+        assert_eq!(o, [0x55; 40]);
+        assert_eq!(ctr.position(), end - 20);
+        ctr.update(&[0; 20], &mut o[..20]).unwrap();
+        assert_eq!(ctr.position(), end);
+        assert_eq!(ctr.remaining(), Some(0));
+        ctr.seek(end - 20).unwrap();
+        assert_eq!(ctr.remaining(), Some(20));
+        ctr.seek(end).unwrap();
+        assert_eq!(ctr.remaining(), Some(0));
+        assert_eq!(ctr.update(&[0], &mut [0]), Err(AesError::CounterOverflow));
+
+        // Counterでも終わりちょうどに移動できる
+        let mut icb = [0; 16];
+        icb[12..].fill(0xff);
+        icb[15] = 0xfe;
+        let mut ctr = Ctr::with_counter(&c, Counter::new(icb, CounterFormat::Be32, Wraparound::Error));
+        ctr.seek(32).unwrap();
+        assert_eq!(ctr.update(&[0], &mut [0]), Err(AesError::CounterOverflow));
+        assert_eq!(ctr.seek(33), Err(AesError::CounterOverflow));
+        ctr.seek(16).unwrap();
+        ctr.update(&[0; 16], &mut o[..16]).unwrap();
+
+        // GCMと同じ形のカウンタ
+        let mut icb = [7; 16];
+        icb[12..].fill(0xff);
+        c.encrypt_ctr_with(&p, &mut Counter::new(icb, CounterFormat::Be32, Wraparound::Wrap), &mut expect);
+        let mut ctr = Ctr::with_counter(&c, Counter::new(icb, CounterFormat::Be32, Wraparound::Wrap));
+        ctr.seek(40).unwrap();
+        ctr.update(&p[40..100], &mut out[40..100]).unwrap();
+        assert_eq!(out[40..100], expect[40..100]);
+    }
+
+    #[test]
+    fn gcm() {
+        let g = Gcm::new(cipher());
+        let p: [u8; 1500] = array::from_fn(|i| (i * 13 + 2) as u8);
+        let aad = [5; 20];
+        for iv in [&[1; 12][..], &[2; 60][..]] {
+            let mut expect = [0; 1500];
+            let mut tag = [0; 16];
+            g.encrypt(iv, &aad, &p, &mut expect, &mut tag).unwrap();
+
+            let mut enc = GcmEncryptor::new(&g, iv, &aad).unwrap();
+            let mut out = vec![];
+            chunked(&p, |x| {
+                let mut o = vec![0; x.len()];
+                enc.update(x, &mut o).unwrap();
+                out.extend_from_slice(&o);
+            });
+            let mut t = [0; 16];
+            enc.finalize(&mut t).unwrap();
+            assert_eq!(out, expect);
+            assert_eq!(t, tag);
+
+            let mut dec = GcmDecryptor::new(&g, iv, &aad).unwrap();
+            let mut back = vec![];
+            chunked(&expect, |x| {
+                let mut o = vec![0; x.len()];
+                dec.update(x, &mut o).unwrap();
+                back.extend_from_slice(&o);
+            });
+            dec.finalize(&tag[..12]).unwrap();
+            assert_eq!(back, p);
+        }
+
+        // タグやAADが違う
+        let mut tag = [0; 16];
+        let mut enc = GcmEncryptor::new(&g, &[1; 12], &aad).unwrap();
+        let mut o = [0; 40];
+        enc.update(&p[..40], &mut o).unwrap();
+        enc.finalize(&mut tag).unwrap();
+        let mut dec = GcmDecryptor::new(&g, &[1; 12], &aad[1..]).unwrap();
+        dec.update(&o, &mut [0; 40]).unwrap();
+        assert_eq!(dec.finalize(&tag), Err(AesError::AuthenticationFailed));
+        let dec = GcmDecryptor::new(&g, &[1; 12], &aad).unwrap();
+        assert_eq!(dec.finalize(&tag[..10]), Err(AesError::InvalidTagLength(10)));
+        assert_eq!(GcmEncryptor::new(&g, &[], &aad).err(), Some(AesError::InvalidIvLength(0)));
+    }
+}
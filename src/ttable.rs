@@ -0,0 +1,184 @@
+use std::{array, fmt::{self, Debug}};
+
+use crate::{aes::{mul, AESkey, INV_S_BOX, S_BOX}, mode::BlockCipher};
+
+// SubBytesとMixColumnsをまとめたテーブル (列はビッグエンディアンのu32)
+// TE[r][x]は行rのバイトxが列に与える寄与
+const TE: [[u32; 256]; 4] = {
+    let mut t = [[0u32; 256]; 4];
+    let mut i = 0;
+    while i < 256 {
+        let s = S_BOX[i];
+        let w = u32::from_be_bytes([mul(2, s), s, s, mul(3, s)]);
+        t[0][i] = w;
+        t[1][i] = w.rotate_right(8);
+        t[2][i] = w.rotate_right(16);
+        t[3][i] = w.rotate_right(24);
+        i += 1;
+    }
+    t
+};
+
+const TD: [[u32; 256]; 4] = {
+    let mut t = [[0u32; 256]; 4];
+    let mut i = 0;
+    while i < 256 {
+        let s = INV_S_BOX[i];
+        let w = u32::from_be_bytes([mul(14, s), mul(9, s), mul(13, s), mul(11, s)]);
+        t[0][i] = w;
+        t[1][i] = w.rotate_right(8);
+        t[2][i] = w.rotate_right(16);
+        t[3][i] = w.rotate_right(24);
+        i += 1;
+    }
+    t
+};
+
+fn byte(w: u32, r: usize) -> usize {
+    (w >> (24 - 8 * r)) as u8 as usize
+}
+
+// 秘密データでテーブルを引くのでサイドチャネルには弱い
+pub struct AESTable {
+    round_keys: Box<[[u32; 4]]>,
+    // 等価逆暗号用 (1..Nr-1番目にInvMixColumnsを適用して逆順にしたもの)
+    inv_round_keys: Box<[[u32; 4]]>,
+}
+
+// 拡大鍵は表示しない
+impl Debug for AESTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AESTable").finish_non_exhaustive()
+    }
+}
+
+impl AESTable {
+    pub fn new(key: AESkey) -> Self {
+        let round_keys: Box<[[u32; 4]]> = key.key_expansion().iter()
+            .map(|k| array::from_fn(|c| u32::from_be_bytes(k[4 * c..4 * c + 4].try_into().unwrap())))
+            .collect();
+        let round = round_keys.len();
+        let inv_round_keys = (0..round).map(|i| {
+            let k = round_keys[round - 1 - i];
+            if i == 0 || i == round - 1 {
+                k
+            } else {
+                // S_BOXを通してからTDを引くとInvMixColumnsだけが残る
+                k.map(|w| (0..4).fold(0, |acc, r| acc ^ TD[r][S_BOX[byte(w, r)] as usize]))
+            }
+        }).collect();
+        Self { round_keys, inv_round_keys }
+    }
+
+    pub fn encrypt(&self, in_bytes: [u8; 16]) -> [u8; 16] {
+        let keys = &self.round_keys;
+        let round = keys.len();
+        let mut s: [u32; 4] = array::from_fn(|c| u32::from_be_bytes(in_bytes[4 * c..4 * c + 4].try_into().unwrap()) ^ keys[0][c]);
+
+        for k in &keys[1..round - 1] {
+            s = array::from_fn(|c| {
+                TE[0][byte(s[c], 0)] ^ TE[1][byte(s[(c + 1) % 4], 1)] ^ TE[2][byte(s[(c + 2) % 4], 2)] ^ TE[3][byte(s[(c + 3) % 4], 3)] ^ k[c]
+            });
+        }
+        let mut out = [0; 16];
+        for c in 0..4 {
+            let w = u32::from_be_bytes(array::from_fn(|r| S_BOX[byte(s[(c + r) % 4], r)])) ^ keys[round - 1][c];
+            out[4 * c..4 * c + 4].copy_from_slice(&w.to_be_bytes());
+        }
+        out
+    }
+
+    pub fn decrypt(&self, in_bytes: [u8; 16]) -> [u8; 16] {
+        let keys = &self.inv_round_keys;
+        let round = keys.len();
+        let mut s: [u32; 4] = array::from_fn(|c| u32::from_be_bytes(in_bytes[4 * c..4 * c + 4].try_into().unwrap()) ^ keys[0][c]);
+
+        for k in &keys[1..round - 1] {
+            s = array::from_fn(|c| {
+                TD[0][byte(s[c], 0)] ^ TD[1][byte(s[(c + 3) % 4], 1)] ^ TD[2][byte(s[(c + 2) % 4], 2)] ^ TD[3][byte(s[(c + 1) % 4], 3)] ^ k[c]
+            });
+        }
+        let mut out = [0; 16];
+        for c in 0..4 {
+            let w = u32::from_be_bytes(array::from_fn(|r| INV_S_BOX[byte(s[(c + 4 - r) % 4], r)])) ^ keys[round - 1][c];
+            out[4 * c..4 * c + 4].copy_from_slice(&w.to_be_bytes());
+        }
+        out
+    }
+}
+
+impl BlockCipher<16> for AESTable {
+    fn _encrypt(&self, in_bytes: [u8; 16]) -> [u8; 16] {
+        self.encrypt(in_bytes)
+    }
+
+    fn _decrypt(&self, in_bytes: [u8; 16]) -> [u8; 16] {
+        self.decrypt(in_bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::array;
+
+    use crate::aes::{AESkey, AES};
+    use crate::mode::BlockCipher;
+    use crate::ttable::AESTable;
+
+    #[test]
+    fn encrypt() {
+        let a = AESTable::new(
+            AESkey::K128(*b"!\xf4\x02\xf2[\x1a\x0f\xd7\"\xb81i\xe1\x05\t\xf8")
+        );
+        assert_eq!(a.encrypt(*b"s\xdf\xffW\xfe$\xe8\x07\xbdO\xb1\xbcN\x07\xcds"), *b"\x9c)\xe4l\xf1\xce\x04\xe8=:k\x16{{\xe1J");
+
+        let b = AESTable::new(
+            AESkey::K192(*b"\x01kG\xc4\xa2XI\nRA\xea\xc9m\xde\x81\xb8\"\xbd \xd5_\xa2A\x0e")
+        );
+        assert_eq!(b.encrypt(*b"s\xdf\xffW\xfe$\xe8\x07\xbdO\xb1\xbcN\x07\xcds"), *b"\xfa\xe3\xc6v\x8f\x90Xj>Rg,b\x05\xca\xb4");
+
+        let c = AESTable::new(
+            AESkey::K256(*b"\xa8\x19@\x8c\xe5\x01\x0c\xa2\xe0\x9e\xf5\x9a\xc3\xd8\x9f_\xf8Y]\x02\xb5$\xe6\x1b\xf8\xaf\xa8\x94\xa9]YO")
+        );
+        assert_eq!(c.encrypt(*b"s\xdf\xffW\xfe$\xe8\x07\xbdO\xb1\xbcN\x07\xcds"), *b"e\x13\xa2\xa4\xc7R\xca@3\xc0\xde\xf6\xab:\xe8\xcb");
+
+        // 鍵は表示しない
+        assert_eq!(format!("{:?}", c), "AESTable { .. }");
+    }
+
+    #[test]
+    fn decrypt() {
+        let ans = *b"s\xdf\xffW\xfe$\xe8\x07\xbdO\xb1\xbcN\x07\xcds";
+        let a = AESTable::new(
+            AESkey::K128(*b"!\xf4\x02\xf2[\x1a\x0f\xd7\"\xb81i\xe1\x05\t\xf8")
+        );
+        assert_eq!(a.decrypt(*b"\x9c)\xe4l\xf1\xce\x04\xe8=:k\x16{{\xe1J"), ans);
+
+        let b = AESTable::new(
+            AESkey::K192(*b"\x01kG\xc4\xa2XI\nRA\xea\xc9m\xde\x81\xb8\"\xbd \xd5_\xa2A\x0e")
+        );
+        assert_eq!(b.decrypt(*b"\xfa\xe3\xc6v\x8f\x90Xj>Rg,b\x05\xca\xb4"), ans);
+
+        let c = AESTable::new(
+            AESkey::K256(*b"\xa8\x19@\x8c\xe5\x01\x0c\xa2\xe0\x9e\xf5\x9a\xc3\xd8\x9f_\xf8Y]\x02\xb5$\xe6\x1b\xf8\xaf\xa8\x94\xa9]YO")
+        );
+        assert_eq!(c.decrypt(*b"e\x13\xa2\xa4\xc7R\xca@3\xc0\xde\xf6\xab:\xe8\xcb"), ans);
+    }
+
+    #[test]
+    fn block_cipher() {
+        let key = *b"!\xf4\x02\xf2[\x1a\x0f\xd7\"\xb81i\xe1\x05\t\xf8";
+        let soft = AES::new(AESkey::K128(key));
+        let t = AESTable::new(AESkey::K128(key));
+        let data: [u8; 16 * 5] = array::from_fn(|i| (i * 7 + 3) as u8);
+        let iv: [u8; 16] = array::from_fn(|i| i as u8);
+        let mut ans = [0u8; 16 * 5];
+        let mut out = [0u8; 16 * 5];
+        let mut dec = [0u8; 16 * 5];
+        soft.encrypt_cbc(&data, iv, &mut ans);
+        t.encrypt_cbc(&data, iv, &mut out);
+        assert_eq!(ans, out);
+        t.decrypt_cbc(&out, iv, &mut dec);
+        assert_eq!(data, dec);
+    }
+}
@@ -0,0 +1,223 @@
+use crate::{error::AesError, mode::{xor, BlockCipher, PARALLEL_BLOCKS}};
+
+// データユニットは2^20ブロックまで
+const MAX_DATA_UNIT_LEN: usize = 16 << 20;
+
+// GF(2^128)でαを掛ける。ブロックはリトルエンディアンで扱う。tweakは秘密なので分岐しない
+fn mul_alpha(t: u128) -> u128 {
+    (t << 1) ^ (0x87 & (t >> 127).wrapping_neg())
+}
+
+// XTS-AES (IEEE 1619, NIST SP 800-38E)。dataでデータを、tweakでtweakを暗号化する
+pub struct Xts<C: BlockCipher<16>> {
+    data: C,
+    tweak: C,
+}
+
+impl<C: BlockCipher<16>> Xts<C> {
+    // SP 800-38Eでは二つの鍵が異なることを求めている
+    pub fn new(data: C, tweak: C) -> Self {
+        Self { data, tweak }
+    }
+
+    fn check(&self, in_len: usize, out_len: usize) -> Result<(), AesError> {
+        if in_len != out_len {
+            return Err(AesError::LengthMismatch { in_len, out_len });
+        }
+        if in_len < 16 {
+            return Err(AesError::TooShort { len: in_len, min: 16 });
+        }
+        if in_len > MAX_DATA_UNIT_LEN {
+            return Err(AesError::TooLong { len: in_len as u64, max: MAX_DATA_UNIT_LEN as u64 });
+        }
+        Ok(())
+    }
+
+    fn block(&self, b: &mut [u8; 16], t: u128, is_encrypt: bool) {
+        let t = t.to_le_bytes();
+        xor(b, &t);
+        *b = if is_encrypt { self.data._encrypt(*b) } else { self.data._decrypt(*b) };
+        xor(b, &t);
+    }
+
+    fn crypt(&self, tweak: [u8; 16], in_bytes: &[u8], out_bytes: &mut [u8], is_encrypt: bool) {
+        out_bytes.copy_from_slice(in_bytes);
+        let rest = in_bytes.len() % 16;
+        // 半端があるときは最後の完全なブロックを暗号文窃取で処理する
+        let full = in_bytes.len() / 16 - (rest != 0) as usize;
+        let mut t = u128::from_le_bytes(self.tweak._encrypt(tweak));
+        let mut ts = [[0; 16]; PARALLEL_BLOCKS];
+        for chunk in out_bytes.as_chunks_mut::<16>().0[..full].chunks_mut(PARALLEL_BLOCKS) {
+            for (b, ti) in chunk.iter_mut().zip(ts.iter_mut()) {
+                *ti = t.to_le_bytes();
+                xor(b, ti);
+                t = mul_alpha(t);
+            }
+            if is_encrypt {
+                self.data._encrypt_blocks(chunk);
+            } else {
+                self.data._decrypt_blocks(chunk);
+            }
+            for (b, ti) in chunk.iter_mut().zip(&ts) {
+                xor(b, ti);
+            }
+        }
+        if rest != 0 {
+            // 復号では最後の二つのtweakを入れ替えて使う
+            let next = mul_alpha(t);
+            let (t1, t2) = if is_encrypt { (t, next) } else { (next, t) };
+            let (head, tail) = out_bytes[full * 16..].split_at_mut(16);
+            let head: &mut [u8; 16] = head.try_into().unwrap();
+            self.block(head, t1, is_encrypt);
+            let mut b = *head;
+            b[..rest].copy_from_slice(tail);
+            tail.copy_from_slice(&head[..rest]);
+            self.block(&mut b, t2, is_encrypt);
+            *head = b;
+        }
+    }
+
+    // 1データユニットを暗号化する。長さは16バイトから2^24バイトまで
+    pub fn encrypt(&self, tweak: [u8; 16], in_bytes: &[u8], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self.check(in_bytes.len(), out_bytes.len())?;
+        self.crypt(tweak, in_bytes, out_bytes, true);
+        Ok(())
+    }
+    pub fn decrypt(&self, tweak: [u8; 16], in_bytes: &[u8], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self.check(in_bytes.len(), out_bytes.len())?;
+        self.crypt(tweak, in_bytes, out_bytes, false);
+        Ok(())
+    }
+
+    // tweakはセクタ番号のリトルエンディアン表現
+    pub fn encrypt_sector(&self, sector: u128, in_bytes: &[u8], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self.encrypt(sector.to_le_bytes(), in_bytes, out_bytes)
+    }
+    pub fn decrypt_sector(&self, sector: u128, in_bytes: &[u8], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self.decrypt(sector.to_le_bytes(), in_bytes, out_bytes)
+    }
+
+    fn sectors(&self, first_sector: u128, sector_size: usize, in_bytes: &[u8], out_bytes: &mut [u8], is_encrypt: bool) -> Result<(), AesError> {
+        self.check(sector_size, sector_size)?;
+        if in_bytes.len() != out_bytes.len() {
+            return Err(AesError::LengthMismatch { in_len: in_bytes.len(), out_len: out_bytes.len() });
+        }
+        if !in_bytes.len().is_multiple_of(sector_size) {
+            return Err(AesError::NotBlockAligned { len: in_bytes.len(), block_size: sector_size });
+        }
+        for (i, (ib, ob)) in in_bytes.chunks(sector_size).zip(out_bytes.chunks_mut(sector_size)).enumerate() {
+            let sector = first_sector.checked_add(i as u128).ok_or(AesError::CounterOverflow)?;
+            self.crypt(sector.to_le_bytes(), ib, ob, is_encrypt);
+        }
+        Ok(())
+    }
+
+    // sector_sizeごとに区切り、first_sectorから順に番号を振って暗号化する
+    pub fn encrypt_sectors(&self, first_sector: u128, sector_size: usize, in_bytes: &[u8], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self.sectors(first_sector, sector_size, in_bytes, out_bytes, true)
+    }
+    pub fn decrypt_sectors(&self, first_sector: u128, sector_size: usize, in_bytes: &[u8], out_bytes: &mut [u8]) -> Result<(), AesError> {
+        self.sectors(first_sector, sector_size, in_bytes, out_bytes, false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::array;
+
+    use crate::{aes::{AESkey, AES}, error::AesError, xts::Xts};
+
+    const C4: &[u8; 512] = b"'\xa7G\x9b\xef\xa1\xd4vH\x9f0\x8c\xd4\xcf\xa6\xe2\xa9nK\xbe2\x08\xff%(}\xd3\x81\x96\x16\xe8\x9c\xc7\x8c\xf7\xf5\xe5CD_\x833\xd8\xfa\x7fV\x00\x00\x05'\x9f\xa5\xd8\xb5\xe4\xad@\xe76\xdd\xb4\xd3T\x122\x80c\xfd*\xabS\xe5\xea\x1e\n\x9f3%\x00\xa5\xdf\x94\x87\xd0z\\\x92\xccQ,\x88f\xc7\xe8`\xce\x93\xfd\xf1f\xa2I\x12\xb4\"\x97aF\xae \xce\x84k\xb7\xdc\x9b\xa9Jvz\xae\xf2\x0c\ra\xad\x02e^\xa9-\xc4\xc4\xe4\x1a\x89R\xc6Q\xd31t\xbeQ\xa1\x0cB\x11\x10\xe6\xd8\x15\x88\xed\xe8!\x03\xa2R\xd8\xa7P\xe8v\x8d\xef\xff\xed\x91\"\x81\n\xae\xb9\x9f\x91r\xaf\x82\xb6\x04\xdcK\x8eQ\xbc\xb0\x825\xa6\xf44\x132\xe4\xca`H*K\xa1\xa0;>e\x00\x8f\xc5\xdav\xb7\x0b\xf1i\r\xb4\xea\xe2\x9c_\x1b\xad\xd0<\\\xcf*U\xd7\x05\xdd\xcd\x86\xd4IQ\x1c\xeb~\xc3\x0b\xf1+\x1f\xa3[\x91?\x9ftz\x8a\xfd\x1b\x13\x0e\x94\xbf\xf9N\xff\xd0\x1a\x91s\\\xa1rj\xcd\x0b\x19|N[\x0396\x97\xe1&\x82o\xb6\xbb\xde\x8e\xcc\x1e\x08)\x85\x16\xe2\xc9\xed\x03\xff<\x1bx`\xf6\xdev\xd4\xce\xcd\x94\xc8\x11\x98U\xefR\x97\xcag\xe9\xf3\xe7\xffr\xb1\xe9\x97\x85\xca\n~w \xc5\xb3m\xc6\xd7,\xac\x95t\xc8\xcb\xbc/\x80\x1e#\xe5o\xd3D\xb0\x7f\"\x15K\xeb\xa0\xf0\x8c\xe8\x89\x1ed>\xd9\x95\xc9M\x9ai\xc9\xf1\xb5\xf4\x99\x02zxW*\xee\xbdt\xd2\x0c\xc3\x98\x81\xc2\x13\xeew\x0b\x10\x10\xe4\xbe\xa7\x18\x84iw\xae\x11\x9fz\x02:\xb5\x8c\xca\n\xd7R\xaf\xe6V\xbb<\x17%j\x9fn\x9b\xf1\x9f\xddZ8\xfc\x82\xbb\xe8r\xc5S\x9e\xdb`\x9e\xf4\xf7\x9c >\xbb\x14\x0f.X<\xb2\xad\x15\xb4\xaa[eP\x16\xa8D\x92w\xdb\xd4w\xef,\x8dl\x01}\xb78\xb1\x8d\xebJB}\x19#\xce?\xf2bsWy\xa4\x18\xf2\n(-\xf9 \x14{\xea\xbeB\x1e\xe51\x9d\x05h";
+    const C10: &[u8; 512] = b"\x1c;:\x10/w\x03\x86\xe4\x83l\x99\xe3p\xcf\x9b\xea\x00\x80?^H#W\xa4\xae\x12\xd4\x14\xa3\xe6;]1\xe2v\xf8\xfeJ\x8df\xb3\x17\xf9\xach?Dh\n\x86\xac5\xad\xfc3E\xbe\xfe\xcbK\xb1\x88\xfdWv\x92lI\xa3\t^\xb1\x08\xfd\x10\x98\xba\xecp\xaa\xa6i\x99\xa7*\x82\xf2}\x84\x8b!\xd4\xa7A\xb0\xc5\xcdM_\xff\x9d\xac\x89\xae\xba\x12)a\xd0:uq#\xe9\x87\x0f\x8a\xcf\x10\x00\x02\x08\x87\x89\x14)\xca*>z}}\xf7\xb1\x03U\x16\\\x8b\x9am\n}\xe8\xb0b\xc4P\r\xc4\xcd\x12\x0c\x0ft\x18\xda\xe3\xd0\xb5x\x1c4\x80?\xa7T!\xc7\x90\xdf\xe1\xde\x184\xf2\x80\xd7f{2\x7fl\x8c\xd7U~\x12\xac:\x0f\x93\xec\x05\xc5.\x04\x93\xef1\xa1-=\x92`\xf7\x9a(\x9dj7\x9b\xc7\x0cP\x84\x14s\xd1\xa8\xcc\x81\xecX>\x96E\xe0{\x8d\x96pe[\xa5\xbb\xcf\xec\xc6\xdc9f8\n\xd8\xfe\xcb\x17\xb6\xba\x02F\x9a\x02\n\x84\xe1\x8e\x8f\x84% p\xc1>\x9f\x1f(\x9b\xe5O\xbcH\x14Ww\x8fa`\x15\xe12z\x02\xb1@\xf1P^\xb3\t2mh7\x8f\x83tY\\\x84\x9d\x84\xf4\xc33\xecD#\x88QC\xcbG\xbdq\xc5\xed\xae\x9b\xe6\x9a/\xfe\xce\xb1\xbe\xc9\xde$O\xbe\x15\x99+\x11\xb7|\x04\x0f\x12\xbd\x8fj\x97ZD\xa0\xf9\x0c)\xa9\xab\xc3\xd4\xd8\x93\x92r\x84\xc5\x87T\xcc\xe2\x94R\x9f\x86\x14\xdc\xd2\xab\xa9\x91\x92_\xed\xc4\xaet\xff\xacn3;\x93\xebJ\xff\x04y\xda\x9aA\x0eDP\xe0\xddz\xe4\xc6\xe2\x91\t\x00W]\xa4\x01\xfc\x07\x05\x9fd^\x8b~\x9b\xfd\xef3\x940T\xff\x84\x01\x14\x93\xc2{4)\xea\xed\xb4\xedSvD\x1aw\xedC\x85\x1a\xd7\x7f\x16\xf5A\xdf\xd2i\xd5\rj_\x14\xfb\n\xab\x1c\xbbL\x15P\xbe\x97\xf7\xab@f\x19<L\xaaw=\xad8\x01K\xd2\t/\xa7U\xc8$\xbb^T\xc4\xf3o\xfd\xa9\xfc\xeap\xb9\xc6\xe6\x93\xe1H\xc1Q";
+
+    fn aes128(k: &str) -> AES {
+        AES::new(AESkey::K128(array::from_fn(|i| u8::from_str_radix(&k[i * 2..i * 2 + 2], 16).unwrap())))
+    }
+
+    fn aes256(k: &str) -> AES {
+        AES::new(AESkey::K256(array::from_fn(|i| u8::from_str_radix(&k[i * 2..i * 2 + 2], 16).unwrap())))
+    }
+
+    // IEEE 1619 付録B
+    #[test]
+    fn encrypt() {
+        // Vector 1
+        let x = Xts::new(AES::new(AESkey::K128([0; 16])), AES::new(AESkey::K128([0; 16])));
+        let mut out = [0; 32];
+        x.encrypt_sector(0, &[0; 32], &mut out).unwrap();
+        assert_eq!(out, *b"\x91|\xf6\x9e\xbdh\xb2\xec\x9b\x9f\xe9\xa3\xea\xdd\xa6\x92\xcdC\xd2\xf5\x95\x98\xed\x85\x8c\x02\xc2e/\xbf\x92.");
+
+        // Vector 2
+        let x = Xts::new(AES::new(AESkey::K128([0x11; 16])), AES::new(AESkey::K128([0x22; 16])));
+        x.encrypt_sector(0x3333333333, &[0x44; 32], &mut out).unwrap();
+        assert_eq!(out, *b"\xc4T\x18^j\x16\x93n93@8\xac\xef\x83\x8b\xfb\x18o\xfft\x80\xad\xc4(\x93\x82\xec\xd6\xd3\x94\xf0");
+
+        // Vector 4, 5
+        let p: [u8; 512] = array::from_fn(|i| i as u8);
+        let x = Xts::new(aes128("27182818284590452353602874713526"), aes128("31415926535897932384626433832795"));
+        let mut out = [0; 512];
+        x.encrypt_sector(0, &p, &mut out).unwrap();
+        assert_eq!(out, *C4);
+        let mut out5 = [0; 512];
+        x.encrypt_sector(1, &out, &mut out5).unwrap();
+        assert_eq!(out5[..16], *b"&M<\xa8Q!\x94\xfe\xc3\x12\xc8\xc9\x89\x1f'\x9f");
+
+        // 連続したセクタはまとめて処理できる
+        let mut two = [0; 1024];
+        two[..512].copy_from_slice(&p);
+        two[512..].copy_from_slice(&out);
+        let mut out2 = [0; 1024];
+        x.encrypt_sectors(0, 512, &two, &mut out2).unwrap();
+        assert_eq!(out2[..512], *C4);
+        assert_eq!(out2[512..], out5);
+        let mut back = [0; 1024];
+        x.decrypt_sectors(0, 512, &out2, &mut back).unwrap();
+        assert_eq!(back, two);
+
+        // Vector 10
+        let x = Xts::new(
+            aes256("2718281828459045235360287471352662497757247093699959574966967627"),
+            aes256("3141592653589793238462643383279502884197169399375105820974944592"),
+        );
+        x.encrypt_sector(0xff, &p, &mut out).unwrap();
+        assert_eq!(out, *C10);
+        x.decrypt_sector(0xff, C10, &mut out).unwrap();
+        assert_eq!(out, p);
+    }
+
+    // Vector 15〜18 (暗号文窃取)
+    #[test]
+    fn stealing() {
+        let x = Xts::new(aes128("fffefdfcfbfaf9f8f7f6f5f4f3f2f1f0"), aes128("bfbebdbcbbbab9b8b7b6b5b4b3b2b1b0"));
+        let tweak = *b"\x9a\x78\x56\x34\x12\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00";
+        let p: [u8; 20] = array::from_fn(|i| i as u8);
+        let vectors: [&[u8]; 4] = [
+            b"l\x16%\xdbFqR-=u\x99`\x1d\xe7\xca\t\xed",
+            b"\xd0iDKz~\x0c\xab\t\xe2DG\xd2M\xeb\x1f\xed\xbf",
+            b"\xe5\xdf\x13Q\xc0TK\xa15\x0b3c\xcd\x8e\xf4\xbe\xed\xbf\x9d",
+            b"\x9d\x84\xc8\x13\xf7\x19\xaa,{\xe3\xf6aq\xc7\xc5\xc2\xed\xbf\x9d\xac",
+        ];
+        for c in vectors {
+            let mut out = vec![0; c.len()];
+            x.encrypt(tweak, &p[..c.len()], &mut out).unwrap();
+            assert_eq!(out, c);
+            x.decrypt(tweak, c, &mut out).unwrap();
+            assert_eq!(out, p[..c.len()]);
+        }
+
+        // まとめて処理するブロック数をまたぐ長さ
+        let p: [u8; 1000] = array::from_fn(|i| (i * 7) as u8);
+        for len in [16, 31, 512, 527, 999, 1000] {
+            let mut c = vec![0; len];
+            let mut out = vec![0; len];
+            x.encrypt(tweak, &p[..len], &mut c).unwrap();
+            x.decrypt(tweak, &c, &mut out).unwrap();
+            assert_eq!(out, p[..len]);
+        }
+
+        let mut out = [0; 15];
+        assert_eq!(x.encrypt(tweak, &p[..15], &mut out), Err(AesError::TooShort { len: 15, min: 16 }));
+        let mut out = [0; 100];
+        assert_eq!(x.encrypt_sectors(0, 32, &p[..100], &mut out), Err(AesError::NotBlockAligned { len: 100, block_size: 32 }));
+    }
+}